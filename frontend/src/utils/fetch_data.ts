@@ -1,10 +1,23 @@
+// Collection endpoints return one page at a time, follow next_cursor until every record is loaded
+async function fetch_all(url) {
+	const records = []
+	let cursor = null
+	do {
+		const page_url = cursor ? `${url}?limit=200&after=${encodeURIComponent(cursor)}` : `${url}?limit=200`
+		const page = await fetch(page_url).then((response) => {
+			if (!response.ok) {
+				throw new Error(`Error ${response.status}`)
+			}
+			return response.json()
+		})
+		records.push(...page.data)
+		cursor = page.next_cursor
+	} while (cursor)
+	return records
+}
+
 export async function fetch_vendors() {
-	const vendors = await fetch("http://localhost:8080/api/vendors").then((response) => {
-		if (!response.ok) {
-			throw new Error(`Error ${response.status}`)
-		}
-		return response.json()
-	})
+	const vendors = await fetch_all("http://localhost:8080/api/vendors")
 	.catch(() => {
 		console.error('Failed to retrieve vendor data')
 	})
@@ -13,12 +26,7 @@ export async function fetch_vendors() {
 }
 
 export async function fetch_events() {
-	const events = await fetch("http://localhost:8080/api/events").then((response) => {
-		if (!response.ok) {
-			throw new Error(`Error ${response.status}`)
-		}
-		return response.json()
-	})
+	const events = await fetch_all("http://localhost:8080/api/events")
 	.catch(() => {
 		console.error('Failed to retrieve event data')
 	})
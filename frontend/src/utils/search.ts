@@ -1,3 +1,19 @@
-export function search() {
-	
+// Searches vendors, menus and items on the server, results come back grouped by kind
+// Each result has highlights with the matching words wrapped in <mark>, the rest is escaped
+export async function search(query, limit = 10) {
+	if (!query || !query.trim()) {
+		return { query: "", vendors: [], menus: [], items: [] }
+	}
+	const results = await fetch(`http://localhost:8080/api/search?q=${encodeURIComponent(query)}&limit=${limit}`)
+		.then((response) => {
+			if (!response.ok) {
+				throw new Error(`Error ${response.status}`)
+			}
+			return response.json()
+		})
+		.catch(() => {
+			console.error('Failed to search')
+		})
+
+	return results
 }
@@ -0,0 +1,26 @@
+use std::process::Command;
+
+// Records which commit the server was built from for /version
+// FTF_GIT_SHA can be set by the build environment when the .git directory isn't available
+fn main() {
+    println!("cargo:rerun-if-env-changed=FTF_GIT_SHA");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs");
+
+    let sha = std::env::var("FTF_GIT_SHA").ok().or_else(|| {
+        Command::new("git")
+            .args(["rev-parse", "--short=12", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|sha| sha.trim().to_string())
+    });
+    println!(
+        "cargo:rustc-env=FTF_GIT_SHA={}",
+        sha.unwrap_or_else(|| "unknown".to_string())
+    );
+
+    let profile = std::env::var("PROFILE").unwrap_or_else(|_| "unknown".to_string());
+    println!("cargo:rustc-env=FTF_BUILD_PROFILE={profile}");
+}
@@ -0,0 +1,68 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::database::store::{Store, StoreError};
+use crate::server::app;
+use clap::{Parser, Subcommand};
+use color_eyre::{eyre::bail, Result};
+
+#[path = "cli/export.rs"]
+pub mod export;
+#[path = "cli/import.rs"]
+pub mod import;
+
+// How long commands wait for the database before giving up
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Parser, Debug)]
+#[command(version, about = "The Food Truck Finder API server")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// Running without a command serves the API
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Serve the API, the default when no command is given
+    Serve,
+    /// Import vendors, items, menus and events from delimited files or a json export
+    Import(import::ImportArgs),
+    /// Export every vendor, item, menu and event as json or delimited files
+    Export(export::ExportArgs),
+}
+
+// Connects the configured store, the SurrealDB store connects in the background so this waits
+//      until it answers
+pub async fn connect(config: &Config) -> Result<Arc<dyn Store>> {
+    let store = app::make_store(&config.database);
+    let ready = async {
+        loop {
+            match store.ping().await {
+                Ok(()) => return Ok(()),
+                Err(StoreError::Unavailable(_)) => {
+                    tokio::time::sleep(Duration::from_millis(500)).await
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    };
+    tokio::select! {
+        ready = ready => match ready {
+            Ok(()) => Ok(store),
+            Err(err) => bail!("The database can't be used: {err}"),
+        },
+        reason = store.failed() => bail!("The database can't be used: {reason}"),
+        _ = tokio::time::sleep(CONNECT_TIMEOUT) => {
+            bail!("The database could not be reached within {CONNECT_TIMEOUT:?}")
+        }
+    }
+}
+
+pub fn delimiter(delimiter: char) -> Result<u8> {
+    match u8::try_from(delimiter) {
+        Ok(delimiter) if delimiter.is_ascii() => Ok(delimiter),
+        _ => bail!("The delimiter must be a single ascii character"),
+    }
+}
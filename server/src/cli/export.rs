@@ -0,0 +1,86 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cli;
+use crate::config::Config;
+use crate::database::export::{Export, Format, Table, JSON_FILE};
+use clap::Args;
+use color_eyre::{eyre::bail, Result};
+
+#[derive(Args, Debug)]
+pub struct ExportArgs {
+    /// Directory the export is written to, created if it doesn't exist
+    #[arg(default_value = ".")]
+    pub dir: PathBuf,
+
+    /// json writes export.json, csv writes vendors.csv, food.csv, menus.csv and events.csv
+    #[arg(long, default_value = "json")]
+    pub format: Format,
+
+    /// The character separating columns in csv files
+    #[arg(long, default_value_t = '|')]
+    pub delimiter: char,
+}
+
+// Writes a snapshot of every table which `server import` can read back
+// Existing files with the same names are replaced
+pub async fn run(args: ExportArgs, config: &Config) -> Result<()> {
+    let delimiter = cli::delimiter(args.delimiter)?;
+    if let Err(err) = std::fs::create_dir_all(&args.dir) {
+        bail!("Failed to create {}: {err}", args.dir.display());
+    }
+
+    let store = cli::connect(config).await?;
+    let mut export = Export::new(store.clone(), delimiter);
+    let written = match args.format {
+        Format::Json => write_file(&args.dir.join(JSON_FILE), &mut export, None).await,
+        Format::Csv => {
+            let mut written = Ok(());
+            for table in Table::ALL {
+                written = write_file(&args.dir.join(table.file()), &mut export, Some(table)).await;
+                if written.is_err() {
+                    break;
+                }
+            }
+            written
+        }
+    };
+    store.close().await;
+    written?;
+    println!(
+        "Exported {} vendors, {} items, {} menus and {} events to {}",
+        export.count(Table::Vendors),
+        export.count(Table::Items),
+        export.count(Table::Menus),
+        export.count(Table::Events),
+        args.dir.display()
+    );
+    Ok(())
+}
+
+// Writes the json document, or the table as csv, a page at a time
+async fn write_file(path: &Path, export: &mut Export, table: Option<Table>) -> Result<()> {
+    let mut writer = match File::create(path) {
+        Ok(file) => BufWriter::new(file),
+        Err(err) => bail!("Failed to write {}: {err}", path.display()),
+    };
+    loop {
+        let chunk = match table {
+            Some(table) => export.next_csv(table).await,
+            None => export.next_json().await,
+        };
+        let chunk = match chunk {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(err) => bail!("Failed to read the database: {err}"),
+        };
+        if let Err(err) = writer.write_all(chunk.as_bytes()) {
+            bail!("Failed to write {}: {err}", path.display());
+        }
+    }
+    match writer.flush() {
+        Ok(()) => Ok(()),
+        Err(err) => bail!("Failed to write {}: {err}", path.display()),
+    }
+}
@@ -0,0 +1,1113 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::cli;
+use crate::config::Config;
+use crate::database::export::{cell, Table};
+use crate::database::models::{
+    Event, Item, Menu, ReoccurancePattern, Vendor, MAX_DURATION_MINUTES,
+};
+use crate::database::recurrence::RecurrenceRule;
+use crate::database::schedule::{parse_datetime_in, parse_end_in};
+use crate::database::store::{vendor_thing, Model, Repository, Store, StoreError};
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use clap::Args;
+use color_eyre::{eyre::bail, Result};
+use csv::{Position, ReaderBuilder, StringRecord};
+use encoding_rs::WINDOWS_1252;
+use serde_json::Value;
+use surrealdb::sql::Thing;
+use tracing::info;
+use uuid::Uuid;
+
+// Reads either a directory of delimited files or the export.json written by `server export`
+// Columns are matched by header so their order and any extra columns don't matter, only the
+//      columns marked * are required
+//      vendors.csv  uuid|name*|email*|phone|description|vendor_type|website
+//      food.csv     uuid|name*|description|price|picture|vendor
+//      menus.csv    uuid|name|vendor|item1|item2|...
+//      events.csv   uuid|name|datetime*|timezone|duration_minutes|location*|repeat_end|cord_x|
+//                   cord_y|repeat|overrides|vendor|menu
+// Vendors are keyed by email, items and menus by their name within the vendor, and events by
+//      their vendor, name, datetime and location
+// vendor columns hold the vendor's email, menu and item columns hold names from the same vendor
+//      A blank vendor imports the record without an owner
+// Links are only resolved against the files being imported, a missing file is skipped
+
+// Records without a uuid get one derived from their key, so importing a row again replaces the
+//      record it created the first time instead of adding a copy
+const NAMESPACE: Uuid = Uuid::from_u128(0x6f1c_2b7e_95d4_4f0a_b3a8_1e5c_7d90_c2f4);
+
+#[derive(Args, Debug)]
+pub struct ImportArgs {
+    /// Directory holding vendors.csv, food.csv, menus.csv and events.csv, or an export.json
+    #[arg(default_value = ".")]
+    pub path: PathBuf,
+
+    /// Validate the files and report what would be imported without writing anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// The character separating columns
+    #[arg(long, default_value_t = '|')]
+    pub delimiter: char,
+}
+
+// A row which was skipped, reported by where it came from so it can be fixed in the source
+#[derive(Debug)]
+pub struct RowError {
+    at: String,
+    field: String,
+    message: String,
+}
+
+impl RowError {
+    fn new(at: &str, field: &str, message: impl Into<String>) -> Self {
+        RowError {
+            at: at.to_string(),
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.field.is_empty() {
+            true => write!(f, "{}: {}", self.at, self.message),
+            false => write!(f, "{}: {}: {}", self.at, self.field, self.message),
+        }
+    }
+}
+
+// One record of a file with its fields named by the header
+// at is file:line for csv files and export.json:table:index for json
+struct Row {
+    at: String,
+    fields: HashMap<String, String>,
+}
+
+impl Row {
+    // Missing and blank fields read as empty
+    fn get(&self, field: &str) -> &str {
+        self.fields.get(field).map(String::as_str).unwrap_or("")
+    }
+
+    fn require(&self, field: &str) -> Result<&str, RowError> {
+        match self.get(field) {
+            "" => Err(self.error(field, "is required")),
+            value => Ok(value),
+        }
+    }
+
+    fn error(&self, field: &str, message: impl Into<String>) -> RowError {
+        RowError::new(&self.at, field, message)
+    }
+
+    // The uuid column if it was given, otherwise a uuid derived from the record's key
+    fn uuid(&self, table: &str, key: &[&str]) -> String {
+        match self.get("uuid") {
+            "" => key_uuid(table, key),
+            uuid => uuid.to_string(),
+        }
+    }
+
+    // A coordinate between -limit and limit, 0 if it is blank
+    fn coordinate(&self, field: &str, limit: f64) -> Result<f64, RowError> {
+        match self.get(field) {
+            "" => Ok(0.0),
+            value => match value.parse::<f64>() {
+                Ok(number) if (-limit..=limit).contains(&number) => Ok(number),
+                _ => Err(self.error(
+                    field,
+                    format!("{value} is not a number between -{limit} and {limit}"),
+                )),
+            },
+        }
+    }
+}
+
+// A record waiting to be written with where it came from
+struct Planned<T> {
+    at: String,
+    record: T,
+}
+
+// Everything read from the files, records are only built from rows which passed validation
+#[derive(Default)]
+struct Plan {
+    vendors: Vec<Planned<Vendor>>,
+    items: Vec<Planned<Item>>,
+    menus: Vec<Planned<Menu>>,
+    events: Vec<Planned<Event>>,
+    errors: Vec<RowError>,
+}
+
+pub async fn run(args: ImportArgs, config: &Config) -> Result<()> {
+    import(&args, cli::connect(config)).await?;
+    Ok(())
+}
+
+// Reads the files and writes their records to the store, returning how many vendors, items,
+//      menus and events were imported
+// The store is only connected once the files have been read, and not at all for a dry run
+async fn import(
+    args: &ImportArgs,
+    connect: impl Future<Output = Result<Arc<dyn Store>>>,
+) -> Result<[usize; 4]> {
+    let delimiter = cli::delimiter(args.delimiter)?;
+    let mut plan = read_plan(&args.path, delimiter)?;
+    for err in plan.errors.iter() {
+        println!("{err}");
+    }
+
+    let imported = if args.dry_run {
+        println!(
+            "Dry run, would import {} vendors, {} items, {} menus and {} events",
+            plan.vendors.len(),
+            plan.items.len(),
+            plan.menus.len(),
+            plan.events.len()
+        );
+        [
+            plan.vendors.len(),
+            plan.items.len(),
+            plan.menus.len(),
+            plan.events.len(),
+        ]
+    } else {
+        let reported = plan.errors.len();
+        let store = connect.await?;
+        let written = write_plan(&store, &mut plan).await;
+        store.close().await;
+        let [vendors, items, menus, events] = written?;
+        for err in plan.errors.iter().skip(reported) {
+            println!("{err}");
+        }
+        println!("Imported {vendors} vendors, {items} items, {menus} menus and {events} events");
+        [vendors, items, menus, events]
+    };
+
+    match plan.errors.len() {
+        0 => Ok(imported),
+        skipped => bail!("{skipped} rows could not be imported"),
+    }
+}
+
+// region:      -- Reading
+
+// Reads a directory of delimited files, or the json export at path when it isn't a directory
+fn read_plan(path: &Path, delimiter: u8) -> Result<Plan> {
+    let mut plan = Plan::default();
+    let [vendor_rows, item_rows, menu_rows, event_rows] = match path.is_dir() {
+        true => [
+            read_file(path, Table::Vendors.file(), delimiter, &mut plan.errors)?,
+            read_file(path, Table::Items.file(), delimiter, &mut plan.errors)?,
+            read_file(path, Table::Menus.file(), delimiter, &mut plan.errors)?,
+            read_file(path, Table::Events.file(), delimiter, &mut plan.errors)?,
+        ],
+        false => read_json(path)?,
+    };
+    // Keyed by vendor email, then by the vendor's item and menu names
+    let mut vendors: HashMap<String, usize> = HashMap::new();
+    let mut items: HashMap<(String, String), usize> = HashMap::new();
+    let mut menus: HashMap<(String, String), usize> = HashMap::new();
+    let mut events: HashMap<String, usize> = HashMap::new();
+
+    for row in vendor_rows {
+        match plan_vendor(&row) {
+            Ok((key, vendor)) => match vendors.get(&key) {
+                Some(first) => plan.errors.push(duplicate(&row, &plan.vendors[*first].at)),
+                None => {
+                    vendors.insert(key, plan.vendors.len());
+                    plan.vendors.push(Planned {
+                        at: row.at,
+                        record: vendor,
+                    });
+                }
+            },
+            Err(err) => plan.errors.push(err),
+        }
+    }
+
+    for row in item_rows {
+        let planned = find_vendor(&row, &vendors, &plan.vendors)
+            .and_then(|(vendor, email)| plan_item(&row, vendor, email));
+        match planned {
+            Ok((key, item)) => match items.get(&key) {
+                Some(first) => plan.errors.push(duplicate(&row, &plan.items[*first].at)),
+                None => {
+                    items.insert(key, plan.items.len());
+                    plan.items.push(Planned {
+                        at: row.at,
+                        record: item,
+                    });
+                }
+            },
+            Err(err) => plan.errors.push(err),
+        }
+    }
+
+    for row in menu_rows {
+        let planned = find_vendor(&row, &vendors, &plan.vendors)
+            .and_then(|(vendor, email)| plan_menu(&row, vendor, email, &items, &plan.items));
+        match planned {
+            Ok((key, menu)) => match menus.get(&key) {
+                Some(first) => plan.errors.push(duplicate(&row, &plan.menus[*first].at)),
+                None => {
+                    menus.insert(key, plan.menus.len());
+                    plan.menus.push(Planned {
+                        at: row.at,
+                        record: menu,
+                    });
+                }
+            },
+            Err(err) => plan.errors.push(err),
+        }
+    }
+
+    for row in event_rows {
+        let planned = find_vendor(&row, &vendors, &plan.vendors)
+            .and_then(|(vendor, email)| plan_event(&row, vendor, email, &menus, &plan.menus));
+        match planned {
+            Ok((key, event)) => match events.get(&key) {
+                Some(first) => plan.errors.push(duplicate(&row, &plan.events[*first].at)),
+                None => {
+                    events.insert(key, plan.events.len());
+                    plan.events.push(Planned {
+                        at: row.at,
+                        record: event,
+                    });
+                }
+            },
+            Err(err) => plan.errors.push(err),
+        }
+    }
+
+    // Vendors list what they own so their records can be found from the vendor
+    let owners: HashMap<String, usize> = plan
+        .vendors
+        .iter()
+        .enumerate()
+        .map(|(index, vendor)| (vendor.record.uuid.to_string(), index))
+        .collect();
+    let owner =
+        |record: Option<Thing>| record.and_then(|vendor| owners.get(&vendor.id.to_raw()).copied());
+    for item in plan.items.iter() {
+        if let Some(vendor) = owner(item.record.vendor()) {
+            let thing = thing(Item::TABLE, &item.record.uuid);
+            plan.vendors[vendor].record.items.push(thing);
+        }
+    }
+    for menu in plan.menus.iter() {
+        if let Some(vendor) = owner(menu.record.vendor()) {
+            let thing = thing(Menu::TABLE, &menu.record.uuid);
+            plan.vendors[vendor].record.menus.push(thing);
+        }
+    }
+    for event in plan.events.iter() {
+        if let Some(vendor) = owner(event.record.vendor()) {
+            let thing = thing(Event::TABLE, &event.record.uuid);
+            plan.vendors[vendor].record.events.push(thing);
+        }
+    }
+
+    Ok(plan)
+}
+
+// Reads every row of the file, rows which can't be parsed are added to errors
+// Files are decoded as UTF-8 when they are valid UTF-8 and as Windows-1252 otherwise, which is
+//      what spreadsheets on Windows export
+fn read_file(
+    dir: &Path,
+    file: &'static str,
+    delimiter: u8,
+    errors: &mut Vec<RowError>,
+) -> Result<Vec<Row>> {
+    let path = dir.join(file);
+    if !path.exists() {
+        info!("{} doesn't exist, skipping it", path.display());
+        return Ok(Vec::new());
+    }
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(err) => bail!("Failed to read {}: {err}", path.display()),
+    };
+    let (text, encoding) = match std::str::from_utf8(&bytes) {
+        Ok(text) => (text.trim_start_matches('\u{feff}').to_string(), "UTF-8"),
+        Err(_) => (WINDOWS_1252.decode(&bytes).0.into_owned(), "Windows-1252"),
+    };
+    info!("Reading {} as {encoding}", path.display());
+
+    let mut reader = ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let headers: Vec<String> = match reader.headers() {
+        Ok(headers) => headers
+            .iter()
+            .map(|header| header.trim().to_lowercase())
+            .collect(),
+        Err(err) => bail!("Failed to read the header of {}: {err}", path.display()),
+    };
+
+    let mut rows = Vec::new();
+    let mut record = StringRecord::new();
+    loop {
+        match reader.read_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => {
+                let line = record
+                    .position()
+                    .map_or(0, |position| record_line(&text, position));
+                let at = format!("{file}:{line}");
+                if record.iter().all(|field| field.trim().is_empty()) {
+                    continue;
+                }
+                if record.len() > headers.len() {
+                    errors.push(RowError::new(
+                        &at,
+                        "",
+                        format!(
+                            "has {} columns but the header has {}",
+                            record.len(),
+                            headers.len()
+                        ),
+                    ));
+                    continue;
+                }
+                let fields = headers
+                    .iter()
+                    .cloned()
+                    .zip(record.iter().map(|field| field.trim().to_string()))
+                    .collect();
+                rows.push(Row { at, fields });
+            }
+            Err(err) => {
+                let line = err
+                    .position()
+                    .map_or(0, |position| record_line(&text, position));
+                errors.push(RowError::new(
+                    &format!("{file}:{line}"),
+                    "",
+                    err.to_string(),
+                ));
+            }
+        }
+    }
+    Ok(rows)
+}
+
+// The line a record starts on, the reader places a record which follows blank lines on the first
+//      of them
+fn record_line(text: &str, position: &Position) -> u64 {
+    let mut blank = 0;
+    let mut bytes = text.as_bytes()[position.byte() as usize..]
+        .iter()
+        .peekable();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'\n' => blank += 1,
+            b'\r' if bytes.peek() != Some(&&b'\n') => blank += 1,
+            b'\r' => (),
+            _ => break,
+        }
+    }
+    position.line() + blank
+}
+
+// Reads a json export, each table is an array of objects with the same fields as the csv columns
+//      except menus list their items in an items array
+// The rows of each table are returned in the order of Table::ALL
+fn read_json(path: &Path) -> Result<[Vec<Row>; 4]> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) => bail!("Failed to read {}: {err}", path.display()),
+    };
+    let document: Value = match serde_json::from_str(&text) {
+        Ok(document) => document,
+        Err(err) => bail!("{} is not valid json: {err}", path.display()),
+    };
+    let file = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut tables: [Vec<Row>; 4] = Default::default();
+    for (index, table) in Table::ALL.iter().enumerate() {
+        let records = match &document[table.name()] {
+            Value::Null => continue,
+            Value::Array(records) => records,
+            _ => bail!("{} in {file} is not an array", table.name()),
+        };
+        for (number, record) in records.iter().enumerate() {
+            let at = format!("{file}:{}:{}", table.name(), number + 1);
+            let object = match record.as_object() {
+                Some(object) => object,
+                None => bail!("{at} is not an object"),
+            };
+            let mut fields = HashMap::new();
+            for (field, value) in object {
+                match (field.as_str(), value) {
+                    ("items", Value::Array(items)) => {
+                        for (number, item) in items.iter().enumerate() {
+                            fields.insert(format!("item{}", number + 1), cell(item));
+                        }
+                    }
+                    (field, value) => {
+                        fields.insert(field.to_string(), cell(value).trim().to_string());
+                    }
+                }
+            }
+            tables[index].push(Row { at, fields });
+        }
+    }
+    Ok(tables)
+}
+
+fn plan_vendor(row: &Row) -> Result<(String, Vendor), RowError> {
+    let name = row.require("name")?;
+    let email = row.require("email")?;
+    if !email.contains('@') {
+        return Err(row.error("email", format!("{email} is not an email address")));
+    }
+    let key = email.to_lowercase();
+    let mut vendor = Vendor::new(name.to_string()).email(email.to_string());
+    vendor.uuid = row.uuid(Vendor::TABLE, &[&key]).into();
+    vendor.phone_number = row.get("phone").to_string().into();
+    vendor.description = row.get("description").to_string().into();
+    vendor.vendor_type = row.get("vendor_type").to_string().into();
+    vendor.website = row.get("website").to_string().into();
+    Ok((key, vendor))
+}
+
+fn plan_item(
+    row: &Row,
+    vendor: Option<Thing>,
+    email: String,
+) -> Result<((String, String), Item), RowError> {
+    let name = row.require("name")?;
+    let mut item = Item::new(name.to_string(), vendor);
+    item.uuid = row.uuid(Item::TABLE, &[&email, name]).into();
+    item.description = row.get("description").to_string().into();
+    item.price = row.get("price").to_string().into();
+    item.picture = row.get("picture").to_string().into();
+    Ok(((email, name.to_string()), item))
+}
+
+// Items are found by name within the menu's vendor
+fn plan_menu(
+    row: &Row,
+    vendor: Option<Thing>,
+    email: String,
+    items: &HashMap<(String, String), usize>,
+    planned: &[Planned<Item>],
+) -> Result<((String, String), Menu), RowError> {
+    let mut menu = Menu::new(vendor);
+    // Every item column in order, item1, item2 and so on
+    let mut columns: Vec<(u32, &str)> = row
+        .fields
+        .iter()
+        .filter_map(|(field, value)| {
+            let number = field.strip_prefix("item")?.parse().ok()?;
+            match value.is_empty() {
+                true => None,
+                false => Some((number, value.as_str())),
+            }
+        })
+        .collect();
+    columns.sort();
+    let mut names = Vec::new();
+    for (number, name) in columns {
+        match items.get(&(email.clone(), name.to_string())) {
+            Some(item) => {
+                names.push(name);
+                menu.items
+                    .push(thing(Item::TABLE, &planned[*item].record.uuid));
+            }
+            None => {
+                return Err(row.error(
+                    &format!("item{number}"),
+                    format!("{name} is not an item of {}", owner(&email)),
+                ))
+            }
+        }
+    }
+    // Older exports have no name column, the menu is named after its items instead
+    let name = match row.get("name") {
+        "" => names.join(":"),
+        name => name.to_string(),
+    };
+    if name.is_empty() {
+        return Err(row.error("name", "is required"));
+    }
+    menu.uuid = row.uuid(Menu::TABLE, &[&email, &name]).into();
+    menu.name = name.clone().into();
+    Ok(((email, name), menu))
+}
+
+// Events are keyed by the uuid derived from their key, even when the row has a uuid, so two
+//      rows for the same event are still caught
+fn plan_event(
+    row: &Row,
+    vendor: Option<Thing>,
+    email: String,
+    menus: &HashMap<(String, String), usize>,
+    planned: &[Planned<Menu>],
+) -> Result<(String, Event), RowError> {
+    let datetime = row.require("datetime")?;
+    let location = row.require("location")?;
+    let name = row.get("name");
+    let timezone = match row.get("timezone") {
+        "" => Tz::UTC,
+        timezone => match timezone.parse::<Tz>() {
+            Ok(timezone) => timezone,
+            Err(_) => {
+                return Err(row.error("timezone", format!("{timezone} is not an IANA time zone")))
+            }
+        },
+    };
+    // Datetimes without an offset are on the wall clock of the event's zone
+    let start = match parse_datetime_in(datetime, timezone) {
+        Some(start) => start,
+        None => return Err(row.error("datetime", format!("{datetime} is not a datetime"))),
+    };
+    let key = key_uuid(Event::TABLE, &[&email, name, datetime, location]);
+    let mut event = Event::new(start, location.to_string(), vendor).with_cords(
+        row.coordinate("cord_x", 180.0)?,
+        row.coordinate("cord_y", 90.0)?,
+    );
+    event.uuid = row
+        .uuid(Event::TABLE, &[&email, name, datetime, location])
+        .into();
+    event.name = name.to_string().into();
+    match row.get("duration_minutes") {
+        "" => (),
+        minutes => match minutes.parse::<u32>() {
+            Ok(minutes) if (1..=MAX_DURATION_MINUTES).contains(&minutes) => {
+                event.duration_minutes = minutes
+            }
+            _ => {
+                return Err(row.error(
+                    "duration_minutes",
+                    format!("{minutes} is not a whole number between 1 and {MAX_DURATION_MINUTES}"),
+                ))
+            }
+        },
+    }
+    // Older files call the column end_date, a blank end repeats forever
+    let (column, repeat_end) = match row.get("repeat_end") {
+        "" => ("end_date", row.get("end_date")),
+        repeat_end => ("repeat_end", repeat_end),
+    };
+    if !repeat_end.is_empty() {
+        event.repeat_end = match parse_end_in(repeat_end, timezone) {
+            Some(end) if end >= start => Some(end),
+            Some(_) => return Err(row.error(column, "is before datetime")),
+            None => return Err(row.error(column, format!("{repeat_end} is not a datetime"))),
+        };
+    }
+    event = event.with_timezone(timezone);
+    event.repeat_schedule = plan_repeat(row, event.datetime.with_timezone(&timezone).date_naive())?;
+    if !row.get("overrides").is_empty() {
+        event.overrides = match serde_json::from_str(row.get("overrides")) {
+            Ok(overrides) => overrides,
+            Err(err) => {
+                return Err(row.error(
+                    "overrides",
+                    format!("is not a list of occurrence overrides: {err}"),
+                ))
+            }
+        };
+    }
+    event.menu = match row.get("menu") {
+        "" => None,
+        menu => match menus.get(&(email.clone(), menu.to_string())) {
+            Some(menu) => Some(thing(Menu::TABLE, &planned[*menu].record.uuid)),
+            None => {
+                return Err(row.error("menu", format!("{menu} is not a menu of {}", owner(&email))))
+            }
+        },
+    };
+    Ok((key, event))
+}
+
+// Older files hold the legacy patterns, written as their name when they have no fields such as
+//      Daily and as json otherwise, newer ones hold an RRULE
+// Legacy patterns become the rule they describe starting on first
+fn plan_repeat(row: &Row, first: NaiveDate) -> Result<Option<RecurrenceRule>, RowError> {
+    let repeat = row.get("repeat");
+    let pattern: Result<ReoccurancePattern, String> = match repeat {
+        "" => return Ok(None),
+        // Exports wrote the pattern as serde does, the API took {"pattern": ...}
+        repeat if repeat.starts_with('{') => match serde_json::from_str::<Value>(repeat) {
+            Ok(pattern) if pattern.get("pattern").is_some() => ReoccurancePattern::parse(&pattern),
+            Ok(pattern) => serde_json::from_value(pattern).map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        },
+        repeat => match serde_json::from_value(Value::String(repeat.to_string())) {
+            Ok(pattern) => Ok(pattern),
+            Err(_) => {
+                return match repeat.parse::<RecurrenceRule>() {
+                    Ok(rule) => Ok(Some(rule)),
+                    Err(err) => Err(row.error("repeat", format!("is not an RRULE: {err}"))),
+                }
+            }
+        },
+    };
+    match pattern {
+        Ok(pattern) => Ok(pattern.to_rule(first)),
+        Err(err) => Err(row.error("repeat", format!("is not a recurrence pattern: {err}"))),
+    }
+}
+
+// The vendor named by the row's vendor column and its email, a blank vendor is no vendor
+fn find_vendor(
+    row: &Row,
+    vendors: &HashMap<String, usize>,
+    planned: &[Planned<Vendor>],
+) -> Result<(Option<Thing>, String), RowError> {
+    let email = row.get("vendor").to_lowercase();
+    if email.is_empty() {
+        return Ok((None, email));
+    }
+    match vendors.get(&email) {
+        Some(vendor) => Ok((Some(vendor_thing(&planned[*vendor].record.uuid)), email)),
+        None => Err(row.error(
+            "vendor",
+            format!("{email} is not in {}", Table::Vendors.file()),
+        )),
+    }
+}
+
+fn owner(email: &str) -> &str {
+    match email {
+        "" => "the records without a vendor",
+        email => email,
+    }
+}
+
+fn duplicate(row: &Row, first: &str) -> RowError {
+    row.error("", format!("duplicates {first}"))
+}
+
+fn key_uuid(table: &str, key: &[&str]) -> String {
+    let name = format!("{table}:{}", key.join("\u{1f}"));
+    Uuid::new_v5(&NAMESPACE, name.as_bytes())
+        .simple()
+        .encode_upper(&mut Uuid::encode_buffer())
+        .to_string()
+}
+
+fn thing(table: &str, uuid: &str) -> Thing {
+    Thing {
+        tb: table.to_string(),
+        id: uuid.into(),
+    }
+}
+
+// endregion:   -- Reading
+
+// region:      -- Writing
+
+// Writes every planned record, returning how many of each were written
+// Records the database rejects are added to the errors
+async fn write_plan(store: &Arc<dyn Store>, plan: &mut Plan) -> Result<[usize; 4]> {
+    let vendors = write_all(store, &plan.vendors, &mut plan.errors).await?;
+    let items = write_all(store, &plan.items, &mut plan.errors).await?;
+    let menus = write_all(store, &plan.menus, &mut plan.errors).await?;
+    let events = write_all(store, &plan.events, &mut plan.errors).await?;
+    Ok([vendors, items, menus, events])
+}
+
+async fn write_all<T: Model>(
+    store: &Arc<dyn Store>,
+    records: &[Planned<T>],
+    errors: &mut Vec<RowError>,
+) -> Result<usize>
+where
+    dyn Store: Repository<T>,
+{
+    let mut written = 0;
+    for planned in records {
+        match store.upsert(planned.record.clone()).await {
+            Ok(()) => written += 1,
+            // Losing the database isn't a problem with the row, stop instead of failing every row
+            Err(StoreError::Unavailable(err)) => bail!("The database became unavailable: {err}"),
+            Err(StoreError::Invalid { field, message }) => {
+                errors.push(RowError::new(&planned.at, &field, message))
+            }
+            Err(err) => errors.push(RowError::new(&planned.at, "", err.to_string())),
+        }
+    }
+    Ok(written)
+}
+
+// endregion:   -- Writing
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::export::{Export, Format, JSON_FILE};
+    use crate::database::memory_store::MemoryStore;
+
+    // A directory of import files which is removed when dropped
+    struct Files(PathBuf);
+
+    impl Files {
+        fn new(name: &str, files: &[(&str, &[u8])]) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("ftf-import-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            for (file, contents) in files {
+                std::fs::write(dir.join(file), contents).unwrap();
+            }
+            Files(dir)
+        }
+
+        fn args(&self, dry_run: bool) -> ImportArgs {
+            ImportArgs {
+                path: self.0.clone(),
+                dry_run,
+                delimiter: '|',
+            }
+        }
+    }
+
+    impl Drop for Files {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const VENDORS: &[u8] = b"name|phone|email\nTaco Truck|555 0100|tacos@example.com\n";
+    const ITEMS: &[u8] =
+        b"name|description|price|vendor\nTaco|Corn tortilla|3.50|tacos@example.com\n";
+    const MENUS: &[u8] = b"name|vendor|item1\nLunch|tacos@example.com|Taco\n";
+    const EVENTS: &[u8] = b"name|datetime|location|end_date|vendor|menu\n\
+        Market|2024-05-04T11:00:00Z|Main Street||tacos@example.com|Lunch\n";
+
+    fn seed(name: &str) -> Files {
+        Files::new(
+            name,
+            &[
+                ("vendors.csv", VENDORS),
+                ("food.csv", ITEMS),
+                ("menus.csv", MENUS),
+                ("events.csv", EVENTS),
+            ],
+        )
+    }
+
+    fn errors(plan: &Plan) -> Vec<String> {
+        plan.errors.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn windows_1252_files_are_decoded() {
+        // 0xE9 is é and 0x80 is the euro sign in Windows-1252, neither is valid UTF-8
+        let files = Files::new(
+            "windows-1252",
+            &[
+                (
+                    "vendors.csv",
+                    b"name|email\nCaf\xe9 Cr\xe8me|cafe@example.com\n",
+                ),
+                (
+                    "food.csv",
+                    b"name|price|vendor\nCr\xeape|\x80 4|cafe@example.com\n",
+                ),
+            ],
+        );
+        let plan = read_plan(&files.0, b'|').unwrap();
+        assert!(plan.errors.is_empty(), "{:?}", errors(&plan));
+        assert_eq!(plan.vendors[0].record.name, "Café Crème");
+        assert_eq!(plan.items[0].record.name, "Crêpe");
+        assert_eq!(plan.items[0].record.price, "€ 4");
+    }
+
+    #[test]
+    fn utf8_files_keep_their_text_without_the_bom() {
+        let files = Files::new(
+            "utf-8",
+            &[(
+                "vendors.csv",
+                "\u{feff}name|email\nCafé Crème|cafe@example.com\n".as_bytes(),
+            )],
+        );
+        let plan = read_plan(&files.0, b'|').unwrap();
+        assert!(plan.errors.is_empty(), "{:?}", errors(&plan));
+        assert_eq!(plan.vendors[0].record.name, "Café Crème");
+    }
+
+    #[test]
+    fn rows_are_reported_by_file_and_line() {
+        let files = Files::new(
+            "errors",
+            &[
+                (
+                    "vendors.csv",
+                    b"name|email\nTaco Truck|tacos@example.com\nNo Email|\n\
+                      Again|TACOS@example.com\nBad|not-an-email\n",
+                ),
+                (
+                    "food.csv",
+                    b"name|vendor\nTaco|tacos@example.com\nBurger|burgers@example.com\n",
+                ),
+                (
+                    "menus.csv",
+                    b"name|vendor|item1\nLunch|tacos@example.com|Burrito\n",
+                ),
+                (
+                    "events.csv",
+                    b"name|datetime|location|vendor|menu\n\
+                      Market||Main Street|tacos@example.com|\n\
+                      \n\
+                      Fair|2024-05-04 11:00|Park|tacos@example.com|Dinner\n\
+                      \r\n\
+                      Night|2024-05-04 20:00|Park|tacos@example.com||extra\n",
+                ),
+            ],
+        );
+        let plan = read_plan(&files.0, b'|').unwrap();
+        assert_eq!(
+            errors(&plan),
+            [
+                "events.csv:6: has 6 columns but the header has 5",
+                "vendors.csv:3: email: is required",
+                "vendors.csv:4: duplicates vendors.csv:2",
+                "vendors.csv:5: email: not-an-email is not an email address",
+                "food.csv:3: vendor: burgers@example.com is not in vendors.csv",
+                "menus.csv:2: item1: Burrito is not an item of tacos@example.com",
+                "events.csv:2: datetime: is required",
+                "events.csv:4: menu: Dinner is not a menu of tacos@example.com",
+            ]
+        );
+        // The rows which passed are still imported
+        assert_eq!(plan.vendors.len(), 1);
+        assert_eq!(plan.items.len(), 1);
+        assert!(plan.menus.is_empty());
+        assert!(plan.events.is_empty());
+    }
+
+    #[test]
+    fn uuids_are_derived_from_the_key() {
+        let files = seed("uuids");
+        let plan = read_plan(&files.0, b'|').unwrap();
+        assert!(plan.errors.is_empty(), "{:?}", errors(&plan));
+        let vendor = &plan.vendors[0].record;
+        assert_eq!(vendor.uuid, key_uuid(Vendor::TABLE, &["tacos@example.com"]));
+        assert_eq!(
+            plan.items[0].record.uuid,
+            key_uuid(Item::TABLE, &["tacos@example.com", "Taco"])
+        );
+        assert_eq!(
+            plan.events[0].record.uuid,
+            key_uuid(
+                Event::TABLE,
+                &[
+                    "tacos@example.com",
+                    "Market",
+                    "2024-05-04T11:00:00Z",
+                    "Main Street"
+                ]
+            )
+        );
+        // Links use the same uuids
+        assert_eq!(
+            plan.menus[0].record.items,
+            [thing(Item::TABLE, &plan.items[0].record.uuid)]
+        );
+        assert_eq!(
+            plan.events[0].record.menu,
+            Some(thing(Menu::TABLE, &plan.menus[0].record.uuid))
+        );
+        assert_eq!(
+            vendor.events,
+            [thing(Event::TABLE, &plan.events[0].record.uuid)]
+        );
+    }
+
+    #[tokio::test]
+    async fn importing_again_replaces_the_records() {
+        let files = seed("again");
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let connect = || async { Ok(store.clone()) };
+
+        assert_eq!(
+            import(&files.args(false), connect()).await.unwrap(),
+            [1, 1, 1, 1]
+        );
+        let vendors: Vec<Vendor> = store.list().await.unwrap();
+        let events: Vec<Event> = store.list().await.unwrap();
+
+        assert_eq!(
+            import(&files.args(false), connect()).await.unwrap(),
+            [1, 1, 1, 1]
+        );
+        let again: Vec<Vendor> = store.list().await.unwrap();
+        assert_eq!(again.len(), 1);
+        assert_eq!(again[0].uuid, vendors[0].uuid);
+        assert_eq!(again[0].events, vendors[0].events);
+        let again: Vec<Event> = store.list().await.unwrap();
+        assert_eq!(again.len(), 1);
+        assert_eq!(again[0].uuid, events[0].uuid);
+        let items: Vec<Item> = store.list().await.unwrap();
+        let menus: Vec<Menu> = store.list().await.unwrap();
+        assert_eq!((items.len(), menus.len()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn dry_runs_write_nothing() {
+        let files = seed("dry-run");
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let connected = std::sync::atomic::AtomicBool::new(false);
+        let connect = async {
+            connected.store(true, std::sync::atomic::Ordering::Relaxed);
+            Ok(store.clone())
+        };
+
+        assert_eq!(
+            import(&files.args(true), connect).await.unwrap(),
+            [1, 1, 1, 1]
+        );
+        assert!(!connected.load(std::sync::atomic::Ordering::Relaxed));
+        let vendors: Vec<Vendor> = store.list().await.unwrap();
+        let events: Vec<Event> = store.list().await.unwrap();
+        assert!(vendors.is_empty());
+        assert!(events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rows_with_errors_fail_the_import_after_the_rest_is_written() {
+        let files = Files::new(
+            "partial",
+            &[(
+                "vendors.csv",
+                b"name|email\nTaco Truck|tacos@example.com\nNo Email|\n",
+            )],
+        );
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let err = import(&files.args(false), async { Ok(store.clone()) })
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "1 rows could not be imported");
+        let vendors: Vec<Vendor> = store.list().await.unwrap();
+        assert_eq!(vendors.len(), 1);
+    }
+
+    // Writes the store out the way `server export` does
+    async fn export(store: &Arc<dyn Store>, dir: &Path, format: Format) {
+        let mut export = Export::new(store.clone(), b'|');
+        let files: Vec<(&str, Option<Table>)> = match format {
+            Format::Json => vec![(JSON_FILE, None)],
+            Format::Csv => Table::ALL
+                .iter()
+                .map(|table| (table.file(), Some(*table)))
+                .collect(),
+        };
+        for (file, table) in files {
+            let mut text = String::new();
+            loop {
+                let chunk = match table {
+                    Some(table) => export.next_csv(table).await.unwrap(),
+                    None => export.next_json().await.unwrap(),
+                };
+                match chunk {
+                    Some(chunk) => text.push_str(&chunk),
+                    None => break,
+                }
+            }
+            std::fs::write(dir.join(file), text).unwrap();
+        }
+    }
+
+    // Every record in the store ordered by uuid, vendors' links are sorted as the order they are
+    //      listed in depends on the order the records were imported
+    async fn records(store: &Arc<dyn Store>) -> Vec<Value> {
+        let mut vendors: Vec<Vendor> = store.list().await.unwrap();
+        for vendor in vendors.iter_mut() {
+            vendor.events.sort_by_key(ToString::to_string);
+            vendor.items.sort_by_key(ToString::to_string);
+            vendor.menus.sort_by_key(ToString::to_string);
+        }
+        let items: Vec<Item> = store.list().await.unwrap();
+        let menus: Vec<Menu> = store.list().await.unwrap();
+        let events: Vec<Event> = store.list().await.unwrap();
+        let mut records: Vec<Value> = vendors
+            .iter()
+            .map(|vendor| serde_json::to_value(vendor).unwrap())
+            .chain(items.iter().map(|item| serde_json::to_value(item).unwrap()))
+            .chain(menus.iter().map(|menu| serde_json::to_value(menu).unwrap()))
+            .chain(
+                events
+                    .iter()
+                    .map(|event| serde_json::to_value(event).unwrap()),
+            )
+            .collect();
+        records.sort_by_key(|record| record["uuid"].to_string());
+        records
+    }
+
+    #[tokio::test]
+    async fn exports_import_back_as_the_same_records() {
+        // More items than fit on one page of the export
+        let mut items = String::from("name|price|vendor\n");
+        for number in 0..205 {
+            items.push_str(&format!("Taco {number}|3.50|tacos@example.com\n"));
+        }
+        let files = Files::new(
+            "round-trip",
+            &[
+                ("vendors.csv", VENDORS),
+                ("food.csv", items.as_bytes()),
+                (
+                    "menus.csv",
+                    b"name|vendor|item1|item2\nLunch|tacos@example.com|Taco 1|Taco 2\n",
+                ),
+                (
+                    "events.csv",
+                    b"name|datetime|timezone|duration_minutes|location|cord_x|cord_y|repeat|\
+                      overrides|vendor|menu\n\
+                      Market|2024-05-04T11:00|Europe/Berlin|90|Main Street|13.4|52.5|\
+                      FREQ=WEEKLY;BYDAY=SA|\"[{\"\"date\"\":\"\"2024-05-11\"\",\
+                      \"\"cancelled\"\":true}]\"|tacos@example.com|Lunch\n\
+                      Pop up|2024-06-01T18:00:00Z|||Park||||||\n",
+                ),
+            ],
+        );
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        import(&files.args(false), async { Ok(store.clone()) })
+            .await
+            .unwrap();
+        let original = records(&store).await;
+        assert_eq!(original.len(), 1 + 205 + 1 + 2);
+        let events: Vec<Event> = store.list().await.unwrap();
+        let market = events.iter().find(|event| event.name == "Market").unwrap();
+        assert!(market.repeat_schedule.is_some());
+        assert_eq!(market.overrides.len(), 1);
+        assert_eq!(market.timezone, Tz::Europe__Berlin);
+
+        for format in [Format::Json, Format::Csv] {
+            let exported = Files::new(&format!("round-trip-{format:?}"), &[]);
+            export(&store, &exported.0, format).await;
+            let path = match format {
+                Format::Json => exported.0.join(JSON_FILE),
+                Format::Csv => exported.0.clone(),
+            };
+            let args = ImportArgs {
+                path,
+                dry_run: false,
+                delimiter: '|',
+            };
+            let copy: Arc<dyn Store> = Arc::new(MemoryStore::new());
+            import(&args, async { Ok(copy.clone()) }).await.unwrap();
+            assert_eq!(records(&copy).await, original, "{format:?}");
+        }
+    }
+}
@@ -0,0 +1,593 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use axum::http::HeaderValue;
+use color_eyre::{eyre::bail, Result};
+use dotenv::dotenv;
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+// The file read when FTF_CONFIG isn't set, it is optional so the defaults and env variables alone
+//      are enough to start the server
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+// Everything the server needs to know at startup
+// Loaded from a TOML file, see config.example.toml, and then overridden by the environment
+//      FTF_CONFIG              -> Path of the config file, defaults to config.toml
+//      FTF_LISTEN              -> server.listen
+//      FTF_TLS_CERT            -> server.tls_cert
+//      FTF_TLS_KEY             -> server.tls_key
+//      FTF_SHUTDOWN_TIMEOUT    -> server.shutdown_timeout_secs
+//      FTF_STORE               -> database.store
+//      FTF_DB_ENDPOINT         -> database.endpoint
+//      DBNS                    -> database.namespace
+//      DBNM                    -> database.database
+//      DBUN                    -> database.username
+//      DBPW                    -> database.password
+//      FTF_OAUTH_CLIENT_ID     -> oauth.client_id
+//      FTF_OAUTH_CLIENT_SECRET -> oauth.client_secret
+//      JWT_SECRET              -> auth.jwt_secret
+//      ADMIN_IDS               -> auth.admin_ids, comma seperated
+//      FTF_CORS_ORIGINS        -> cors.origins, comma seperated
+//      FTF_CHECK_GEOCODER      -> health.check_geocoder, true or false
+//      FTF_LOG_FORMAT          -> logging.format
+//      FTF_LOG_OUTPUT          -> logging.output
+//      RUST_LOG                -> logging.filter
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub oauth: OAuthConfig,
+    pub auth: AuthConfig,
+    pub cors: CorsConfig,
+    pub health: HealthConfig,
+    pub logging: LoggingConfig,
+}
+
+// What the process was started to do, settings are only required by the parts which use them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    // Serving the API, which needs TLS, OAuth, the JWT secret and CORS as well as the database
+    Serve,
+    // The import and export commands, which only use the database
+    Command,
+}
+
+// A value which must never end up in the logs, printing it with {:?} shows [redacted]
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Secret(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[redacted]")
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: SocketAddr,
+    // TLS is enabled when both are set, they are paths to PEM files
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    // How long in flight requests get to finish after SIGTERM or SIGINT before being cut off
+    pub shutdown_timeout_secs: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreKind {
+    Surreal,
+    // Kept in memory and lost on restart, for development and testing without a database
+    Memory,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub store: StoreKind,
+    pub endpoint: String,
+    pub namespace: String,
+    pub database: String,
+    pub username: Option<String>,
+    pub password: Option<Secret>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct OAuthConfig {
+    pub client_id: Option<String>,
+    pub client_secret: Option<Secret>,
+    pub auth_url: String,
+    pub token_url: String,
+    pub user_info_url: String,
+}
+
+// The tokens this API issues once a vendor has logged in through OAuth
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    // Signs and verifies the JWTs, changing it logs every vendor out
+    pub jwt_secret: Secret,
+    // The facebook ids of the users given the admin role when they log in
+    pub admin_ids: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    // Origins allowed to call the API, * allows any origin
+    pub origins: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    // Whether /readyz also requires the geocoder to respond, off by default since events can
+    //      still be served without it
+    pub check_geocoder: bool,
+    pub geocoder_url: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // Human readable multi line output for development
+    Pretty,
+    // One json object per line for log collectors
+    Json,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    // An env filter directive such as info or info,server=debug
+    pub filter: String,
+    // stdout, stderr or the path of a file the logs are appended to
+    pub output: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            tls_cert: None,
+            tls_key: None,
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        // Including these in the source shouldn't be a security risk since its just database names
+        DatabaseConfig {
+            store: StoreKind::Surreal,
+            endpoint: "localhost:8000".to_string(),
+            namespace: "food_truck_finder".to_string(),
+            database: "ftf_db".to_string(),
+            username: None,
+            password: None,
+        }
+    }
+}
+
+impl Default for OAuthConfig {
+    fn default() -> Self {
+        OAuthConfig {
+            client_id: None,
+            client_secret: None,
+            auth_url: "https://www.facebook.com/v18.0/dialog/oauth".to_string(),
+            token_url: "https://graph.facebook.com/v18.0/oauth/access_token".to_string(),
+            user_info_url: "https://graph.facebook.com/v18.0/me".to_string(),
+        }
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            origins: vec!["*".to_string()],
+        }
+    }
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            check_geocoder: false,
+            geocoder_url: "https://nominatim.openstreetmap.org/status".to_string(),
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            format: LogFormat::Pretty,
+            filter: "info".to_string(),
+            output: "stdout".to_string(),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_secs)
+    }
+
+    pub fn tls(&self) -> Option<(&Path, &Path)> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Some((cert.as_path(), key.as_path())),
+            _ => None,
+        }
+    }
+}
+
+impl Config {
+    // Reads the config file, applies the environment and validates the result for the mode
+    // Every problem found is reported at once so a broken deployment can be fixed in one go
+    pub fn load(mode: Mode) -> Result<Self> {
+        dotenv().ok();
+
+        let mut config = match env::var("FTF_CONFIG") {
+            Ok(path) => Config::from_file(Path::new(&path))?,
+            Err(_) if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Config::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            Err(_) => Config::default(),
+        };
+
+        let mut problems = config.apply_env(|name| env::var(name).ok());
+        problems.extend(config.validate(mode));
+        if !problems.is_empty() {
+            bail!("Invalid configuration\n  - {}", problems.join("\n  - "));
+        }
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) => bail!("Failed to read config file {}: {err}", path.display()),
+        };
+        match toml::from_str(&contents) {
+            Ok(config) => Ok(config),
+            Err(err) => bail!("Failed to parse config file {}: {err}", path.display()),
+        }
+    }
+
+    // Overrides the settings with the variables var finds, which reads the environment outside of
+    //      tests, and returns the variables which couldn't be parsed
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if let Some(listen) = var("FTF_LISTEN") {
+            match listen.parse() {
+                Ok(listen) => self.server.listen = listen,
+                Err(_) => problems.push(format!(
+                    "FTF_LISTEN must be an address and port such as 0.0.0.0:8080, got {listen}"
+                )),
+            }
+        }
+        if let Some(cert) = var("FTF_TLS_CERT") {
+            self.server.tls_cert = Some(cert.into());
+        }
+        if let Some(key) = var("FTF_TLS_KEY") {
+            self.server.tls_key = Some(key.into());
+        }
+        if let Some(shutdown_timeout) = var("FTF_SHUTDOWN_TIMEOUT") {
+            match shutdown_timeout.parse() {
+                Ok(shutdown_timeout) => self.server.shutdown_timeout_secs = shutdown_timeout,
+                Err(_) => problems.push(format!(
+                    "FTF_SHUTDOWN_TIMEOUT must be a whole number of seconds, got {shutdown_timeout}"
+                )),
+            }
+        }
+
+        if let Some(store) = var("FTF_STORE") {
+            match store.as_str() {
+                "surreal" => self.database.store = StoreKind::Surreal,
+                "memory" => self.database.store = StoreKind::Memory,
+                _ => problems.push(format!("FTF_STORE must be surreal or memory, got {store}")),
+            }
+        }
+        if let Some(endpoint) = var("FTF_DB_ENDPOINT") {
+            self.database.endpoint = endpoint;
+        }
+        if let Some(namespace) = var("DBNS") {
+            self.database.namespace = namespace;
+        }
+        if let Some(database) = var("DBNM") {
+            self.database.database = database;
+        }
+        if let Some(username) = var("DBUN") {
+            self.database.username = Some(username);
+        }
+        if let Some(password) = var("DBPW") {
+            self.database.password = Some(Secret::new(password));
+        }
+
+        if let Some(client_id) = var("FTF_OAUTH_CLIENT_ID") {
+            self.oauth.client_id = Some(client_id);
+        }
+        if let Some(client_secret) = var("FTF_OAUTH_CLIENT_SECRET") {
+            self.oauth.client_secret = Some(Secret::new(client_secret));
+        }
+
+        if let Some(jwt_secret) = var("JWT_SECRET") {
+            self.auth.jwt_secret = Secret::new(jwt_secret);
+        }
+        if let Some(admin_ids) = var("ADMIN_IDS") {
+            self.auth.admin_ids = admin_ids
+                .split(',')
+                .map(|admin_id| admin_id.trim().to_string())
+                .filter(|admin_id| !admin_id.is_empty())
+                .collect();
+        }
+
+        if let Some(origins) = var("FTF_CORS_ORIGINS") {
+            self.cors.origins = origins
+                .split(',')
+                .map(|origin| origin.trim().to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect();
+        }
+
+        if let Some(check_geocoder) = var("FTF_CHECK_GEOCODER") {
+            match check_geocoder.parse() {
+                Ok(check_geocoder) => self.health.check_geocoder = check_geocoder,
+                Err(_) => problems.push(format!(
+                    "FTF_CHECK_GEOCODER must be true or false, got {check_geocoder}"
+                )),
+            }
+        }
+
+        if let Some(format) = var("FTF_LOG_FORMAT") {
+            match format.as_str() {
+                "pretty" => self.logging.format = LogFormat::Pretty,
+                "json" => self.logging.format = LogFormat::Json,
+                _ => problems.push(format!(
+                    "FTF_LOG_FORMAT must be pretty or json, got {format}"
+                )),
+            }
+        }
+        if let Some(filter) = var("RUST_LOG") {
+            self.logging.filter = filter;
+        }
+        if let Some(output) = var("FTF_LOG_OUTPUT") {
+            self.logging.output = output;
+        }
+
+        problems
+    }
+
+    fn validate(&self, mode: Mode) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        // Database credentials are only needed to reach SurrealDB, the memory store has none
+        if self.database.store == StoreKind::Surreal {
+            if self.database.endpoint.is_empty() {
+                problems.push("database.endpoint must not be empty".into());
+            }
+            if self.database.username.is_none() {
+                problems.push("database.username (DBUN) is required".into());
+            }
+            if self.database.password.is_none() {
+                problems.push("database.password (DBPW) is required".into());
+            }
+        }
+        if self.database.namespace.is_empty() || self.database.database.is_empty() {
+            problems.push("database.namespace and database.database must not be empty".into());
+        }
+
+        if let Err(err) = EnvFilter::try_new(&self.logging.filter) {
+            problems.push(format!(
+                "logging.filter is not a valid filter, got {}: {err}",
+                self.logging.filter
+            ));
+        }
+        if self.logging.output.is_empty() {
+            problems.push("logging.output must be stdout, stderr or a file path".into());
+        }
+
+        if mode == Mode::Serve {
+            problems.extend(self.validate_server());
+        }
+        problems
+    }
+
+    // The settings only used while serving the API
+    fn validate_server(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        match (&self.server.tls_cert, &self.server.tls_key) {
+            (Some(cert), Some(key)) => {
+                for (name, path) in [("server.tls_cert", cert), ("server.tls_key", key)] {
+                    if !path.is_file() {
+                        problems.push(format!("{name} {} does not exist", path.display()));
+                    }
+                }
+            }
+            (Some(_), None) => {
+                problems.push("server.tls_key is required with server.tls_cert".into())
+            }
+            (None, Some(_)) => {
+                problems.push("server.tls_cert is required with server.tls_key".into())
+            }
+            (None, None) => {}
+        }
+
+        if self.oauth.client_id.as_deref().unwrap_or("").is_empty() {
+            problems.push("oauth.client_id (FTF_OAUTH_CLIENT_ID) is required".into());
+        }
+        if self
+            .oauth
+            .client_secret
+            .as_ref()
+            .is_none_or(|secret| secret.expose().is_empty())
+        {
+            problems.push("oauth.client_secret (FTF_OAUTH_CLIENT_SECRET) is required".into());
+        }
+        if self.auth.jwt_secret.expose().is_empty() {
+            problems.push("auth.jwt_secret (JWT_SECRET) is required".into());
+        }
+        for (name, url) in [
+            ("oauth.auth_url", &self.oauth.auth_url),
+            ("oauth.token_url", &self.oauth.token_url),
+            ("oauth.user_info_url", &self.oauth.user_info_url),
+            ("health.geocoder_url", &self.health.geocoder_url),
+        ] {
+            if oauth2::url::Url::parse(url).is_err() {
+                problems.push(format!("{name} is not a valid url, got {url}"));
+            }
+        }
+
+        if self.cors.origins.is_empty() {
+            problems.push("cors.origins must list at least one origin or *".into());
+        }
+        for origin in self.cors.origins.iter().filter(|origin| *origin != "*") {
+            let valid = (origin.starts_with("http://") || origin.starts_with("https://"))
+                && HeaderValue::from_str(origin).is_ok();
+            if !valid {
+                problems.push(format!(
+                    "cors.origins entries must be * or an origin such as https://example.com, got {origin}"
+                ));
+            }
+        }
+
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // Everything serving needs except the JWT secret
+    const SERVER_TOML: &str = r#"
+        [database]
+        store = "memory"
+
+        [oauth]
+        client_id = "client"
+        client_secret = "oauth-secret"
+
+        [auth]
+        admin_ids = ["1"]
+    "#;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn the_jwt_secret_is_only_required_to_serve() {
+        let config = config(SERVER_TOML);
+        assert_eq!(
+            config.validate(Mode::Serve),
+            vec!["auth.jwt_secret (JWT_SECRET) is required".to_string()]
+        );
+        assert!(config.validate(Mode::Command).is_empty());
+
+        let mut config = config.clone();
+        assert!(config.apply_env(env(&[("JWT_SECRET", "jwt")])).is_empty());
+        assert!(config.validate(Mode::Serve).is_empty());
+    }
+
+    #[test]
+    fn database_credentials_are_only_required_by_surreal() {
+        let mut config = config(SERVER_TOML);
+        config.database.store = StoreKind::Surreal;
+        let problems = config.validate(Mode::Command);
+        assert!(problems.contains(&"database.username (DBUN) is required".to_string()));
+        assert!(problems.contains(&"database.password (DBPW) is required".to_string()));
+    }
+
+    #[test]
+    fn the_environment_overrides_the_file() {
+        let mut config = config(
+            r#"
+            [server]
+            listen = "127.0.0.1:8080"
+
+            [auth]
+            jwt_secret = "from-file"
+            admin_ids = ["1"]
+
+            [cors]
+            origins = ["https://file.example.com"]
+        "#,
+        );
+        let problems = config.apply_env(env(&[
+            ("FTF_LISTEN", "0.0.0.0:9000"),
+            ("JWT_SECRET", "from-env"),
+            ("ADMIN_IDS", "2, 3,"),
+            ("FTF_CORS_ORIGINS", "https://env.example.com"),
+            ("FTF_STORE", "memory"),
+            ("FTF_LOG_OUTPUT", "stderr"),
+        ]));
+        assert!(problems.is_empty(), "{problems:?}");
+        assert_eq!(config.server.listen, SocketAddr::from(([0, 0, 0, 0], 9000)));
+        assert_eq!(config.auth.jwt_secret.expose(), "from-env");
+        assert_eq!(config.auth.admin_ids, vec!["2", "3"]);
+        assert_eq!(config.cors.origins, vec!["https://env.example.com"]);
+        assert_eq!(config.database.store, StoreKind::Memory);
+        assert_eq!(config.logging.output, "stderr");
+        // Settings the environment doesn't name keep their value from the file
+        assert_eq!(config.database.namespace, "food_truck_finder");
+    }
+
+    #[test]
+    fn unparseable_variables_are_reported_and_ignored() {
+        let mut config = config(SERVER_TOML);
+        let problems = config.apply_env(env(&[
+            ("FTF_LISTEN", "everywhere"),
+            ("FTF_STORE", "postgres"),
+        ]));
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert_eq!(config.server.listen, ServerConfig::default().listen);
+        assert_eq!(config.database.store, StoreKind::Memory);
+    }
+
+    #[test]
+    fn secrets_are_redacted_when_debug_printed() {
+        let mut config = config(SERVER_TOML);
+        config.apply_env(env(&[("JWT_SECRET", "jwt-secret"), ("DBPW", "db-secret")]));
+        for secret in [
+            format!("{:?}", Secret::new("jwt-secret".to_string())),
+            format!("{config:?}"),
+        ] {
+            for value in ["jwt-secret", "db-secret", "oauth-secret"] {
+                assert!(!secret.contains(value), "{value} is in {secret}");
+            }
+        }
+        assert_eq!(config.auth.jwt_secret.expose(), "jwt-secret");
+    }
+}
@@ -1,2 +1,24 @@
+#[path = "database/export.rs"]
+pub mod export;
+#[path = "database/geo.rs"]
+pub mod geo;
+#[path = "database/geojson.rs"]
+pub mod geojson;
+#[path = "database/ical.rs"]
+pub mod ical;
+#[path = "database/memory_store.rs"]
+pub mod memory_store;
+#[path = "database/migrations.rs"]
+pub mod migrations;
 #[path = "database/models.rs"]
 pub mod models;
+#[path = "database/recurrence.rs"]
+pub mod recurrence;
+#[path = "database/schedule.rs"]
+pub mod schedule;
+#[path = "database/search.rs"]
+pub mod search;
+#[path = "database/store.rs"]
+pub mod store;
+#[path = "database/surreal_store.rs"]
+pub mod surreal_store;
@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::database::models::{Event, Item, Menu, Vendor};
+use crate::database::store::{Listing, PageQuery, Store, StoreError};
+use csv::WriterBuilder;
+use futures_util::stream::{self, Stream};
+use serde_json::{json, Value};
+use surrealdb::sql::Thing;
+
+// The whole dataset is either one json document or one csv file per table
+// Both are read back by `server import`, so an export can be restored or moved between databases
+pub const JSON_FILE: &str = "export.json";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("{value} is not json or csv")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Table {
+    Vendors,
+    Items,
+    Menus,
+    Events,
+}
+
+impl Table {
+    // In the order they are imported, records only link to tables before them
+    pub const ALL: [Table; 4] = [Table::Vendors, Table::Items, Table::Menus, Table::Events];
+
+    // The key of the table in the json document
+    pub fn name(self) -> &'static str {
+        match self {
+            Table::Vendors => "vendors",
+            Table::Items => "items",
+            Table::Menus => "menus",
+            Table::Events => "events",
+        }
+    }
+
+    // Items were always seeded from food.csv so the name is kept
+    pub fn file(self) -> &'static str {
+        match self {
+            Table::Vendors => "vendors.csv",
+            Table::Items => "food.csv",
+            Table::Menus => "menus.csv",
+            Table::Events => "events.csv",
+        }
+    }
+
+    // The csv columns, menus also get an item1, item2... column for each of their items
+    fn columns(self) -> &'static [&'static str] {
+        match self {
+            Table::Vendors => &[
+                "uuid",
+                "name",
+                "email",
+                "phone",
+                "description",
+                "vendor_type",
+                "website",
+            ],
+            Table::Items => &["uuid", "name", "description", "price", "picture", "vendor"],
+            Table::Menus => &["uuid", "name", "vendor"],
+            Table::Events => &[
+                "uuid",
+                "name",
+                "datetime",
+                "timezone",
+                "duration_minutes",
+                "location",
+                "repeat_end",
+                "cord_x",
+                "cord_y",
+                "repeat",
+                "overrides",
+                "vendor",
+                "menu",
+            ],
+        }
+    }
+}
+
+impl FromStr for Table {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match Table::ALL.iter().find(|table| table.name() == value) {
+            Some(table) => Ok(*table),
+            None => Err(format!(
+                "{value} is not one of vendors, items, menus or events"
+            )),
+        }
+    }
+}
+
+// How many records are read from the store at a time
+const PAGE_SIZE: usize = 200;
+
+// How far a table has been read
+#[derive(Debug, Clone, Default, PartialEq)]
+enum Cursor {
+    #[default]
+    Start,
+    After(String),
+    Done,
+}
+
+// Reads the dataset from the store a page at a time, writing each page as it is read so the
+//      whole dataset is never held in memory
+// Every record is written as a flat json object, links between records are replaced by the keys
+//      the import matches on, vendors by email and menus and items by name within their vendor
+// Tables link only to the tables before them in Table::ALL, so their keys are kept as they are
+//      read and a table is only written once the tables it links to have been read
+pub struct Export {
+    store: Arc<dyn Store>,
+    delimiter: u8,
+    // Keyed by uuid
+    emails: HashMap<String, String>,
+    item_names: HashMap<String, String>,
+    menu_names: HashMap<String, String>,
+    // In the order of Table::ALL
+    cursors: [Cursor; 4],
+    read: [usize; 4],
+    // The number of csv columns of each table, set once its header is written
+    widths: [Option<usize>; 4],
+}
+
+impl Export {
+    // delimiter separates the columns of csv files
+    pub fn new(store: Arc<dyn Store>, delimiter: u8) -> Self {
+        Export {
+            store,
+            delimiter,
+            emails: HashMap::new(),
+            item_names: HashMap::new(),
+            menu_names: HashMap::new(),
+            cursors: Default::default(),
+            read: [0; 4],
+            widths: [None; 4],
+        }
+    }
+
+    // How many records of the table have been read
+    pub fn count(&self, table: Table) -> usize {
+        self.read[table as usize]
+    }
+
+    // The next part of the json document, None once it has all been written
+    //      { "vendors": [...], "items": [...], "menus": [...], "events": [...] }
+    pub async fn next_json(&mut self) -> Result<Option<String>, StoreError> {
+        let (index, table) = match Table::ALL
+            .iter()
+            .enumerate()
+            .find(|(_, table)| self.cursors[**table as usize] != Cursor::Done)
+        {
+            Some((index, table)) => (index, *table),
+            None => return Ok(None),
+        };
+        let first = self.cursors[index] == Cursor::Start;
+        let rows = self.next_page(table).await?;
+
+        let mut chunk = String::new();
+        if first {
+            let separator = if index == 0 { "{" } else { "," };
+            chunk.push_str(&format!("{separator}\n\"{}\": [", table.name()));
+        }
+        for (row_index, row) in rows.iter().enumerate() {
+            let separator = if first && row_index == 0 { "" } else { "," };
+            chunk.push_str(&format!("{separator}\n  {row}"));
+        }
+        if self.cursors[index] == Cursor::Done {
+            chunk.push_str("\n]");
+            if index == Table::ALL.len() - 1 {
+                chunk.push_str("\n}\n");
+            }
+        }
+        Ok(Some(chunk))
+    }
+
+    // The next part of the table as csv, None once it has all been written
+    // The header comes first, the tables it links to are read before it so their keys are known
+    pub async fn next_csv(&mut self, table: Table) -> Result<Option<String>, StoreError> {
+        let width = match self.widths[table as usize] {
+            Some(width) => width,
+            None => {
+                for linked in Table::ALL.iter().take_while(|linked| **linked != table) {
+                    while self.cursors[*linked as usize] != Cursor::Done {
+                        self.next_page(*linked).await?;
+                    }
+                }
+                let mut header: Vec<String> =
+                    table.columns().iter().map(|c| c.to_string()).collect();
+                if table == Table::Menus {
+                    let most_items = self.most_items().await?;
+                    header.extend((1..=most_items).map(|number| format!("item{number}")));
+                }
+                self.widths[table as usize] = Some(header.len());
+                return csv_line(&header, self.delimiter).map(Some);
+            }
+        };
+        if self.cursors[table as usize] == Cursor::Done {
+            return Ok(None);
+        }
+
+        let mut chunk = String::new();
+        for row in self.next_page(table).await? {
+            let mut fields: Vec<String> = table
+                .columns()
+                .iter()
+                .map(|column| cell(&row[*column]))
+                .collect();
+            if let Some(items) = row["items"].as_array() {
+                fields.extend(items.iter().map(cell));
+            }
+            // Every line has as many fields as the header
+            fields.resize(width, String::new());
+            chunk.push_str(&csv_line(&fields, self.delimiter)?);
+        }
+        Ok(Some(chunk))
+    }
+
+    // Reads the table's next page, keeping the keys of its records for the tables linking to them
+    async fn next_page(&mut self, table: Table) -> Result<Vec<Value>, StoreError> {
+        let after = match &self.cursors[table as usize] {
+            Cursor::Start => None,
+            Cursor::After(after) => Some(after.clone()),
+            Cursor::Done => return Ok(Vec::new()),
+        };
+        let query = page_query(after);
+        let (rows, next_cursor) = match table {
+            Table::Vendors => {
+                let listing: Listing<Vendor> = self.store.page(&query).await?;
+                let mut rows = Vec::new();
+                for vendor in listing.records {
+                    self.emails
+                        .insert(vendor.uuid.to_string(), vendor.email.to_string());
+                    rows.push(json!({
+                        "uuid": vendor.uuid,
+                        "name": vendor.name,
+                        "email": vendor.email,
+                        "phone": vendor.phone_number,
+                        "description": vendor.description,
+                        "vendor_type": vendor.vendor_type,
+                        "website": vendor.website,
+                    }));
+                }
+                (rows, listing.next_cursor)
+            }
+            Table::Items => {
+                let listing: Listing<Item> = self.store.page(&query).await?;
+                let mut rows = Vec::new();
+                for item in listing.records {
+                    self.item_names
+                        .insert(item.uuid.to_string(), item.name.to_string());
+                    rows.push(json!({
+                        "uuid": item.uuid,
+                        "name": item.name,
+                        "description": item.description,
+                        "price": item.price,
+                        "picture": item.picture,
+                        "vendor": key(&self.emails, &item.vendor),
+                    }));
+                }
+                (rows, listing.next_cursor)
+            }
+            Table::Menus => {
+                let listing: Listing<Menu> = self.store.page(&query).await?;
+                let mut rows = Vec::new();
+                for menu in listing.records {
+                    self.menu_names
+                        .insert(menu.uuid.to_string(), menu.name.to_string());
+                    let items: Vec<&str> = menu
+                        .items
+                        .iter()
+                        .filter_map(|item| self.item_names.get(&item.id.to_raw()))
+                        .map(String::as_str)
+                        .collect();
+                    rows.push(json!({
+                        "uuid": menu.uuid,
+                        "name": menu.name,
+                        "vendor": key(&self.emails, &menu.vendor),
+                        "items": items,
+                    }));
+                }
+                (rows, listing.next_cursor)
+            }
+            Table::Events => {
+                let listing: Listing<Event> = self.store.page(&query).await?;
+                let rows = listing
+                    .records
+                    .iter()
+                    .map(|event| {
+                        json!({
+                            "uuid": event.uuid,
+                            "name": event.name,
+                            "datetime": event.datetime,
+                            "timezone": event.timezone,
+                            "duration_minutes": event.duration_minutes,
+                            "location": event.location,
+                            "repeat_end": event.repeat_end,
+                            "cord_x": event.cord_x,
+                            "cord_y": event.cord_y,
+                            "repeat": event.repeat_schedule,
+                            "overrides": event.overrides,
+                            "vendor": key(&self.emails, &event.vendor),
+                            "menu": key(&self.menu_names, &event.menu),
+                        })
+                    })
+                    .collect();
+                (rows, listing.next_cursor)
+            }
+        };
+        self.cursors[table as usize] = match next_cursor {
+            Some(after) => Cursor::After(after),
+            None => Cursor::Done,
+        };
+        self.read[table as usize] += rows.len();
+        Ok(rows)
+    }
+
+    // The most items on any menu, which is how many item columns menus.csv needs
+    // Only the counts are kept so this pages through menus apart from the export
+    async fn most_items(&self) -> Result<usize, StoreError> {
+        let mut most_items = 0;
+        let mut after = None;
+        loop {
+            let listing: Listing<Menu> = self.store.page(&page_query(after)).await?;
+            for menu in listing.records.iter() {
+                let items = menu
+                    .items
+                    .iter()
+                    .filter(|item| self.item_names.contains_key(&item.id.to_raw()))
+                    .count();
+                most_items = most_items.max(items);
+            }
+            match listing.next_cursor {
+                Some(next_cursor) => after = Some(next_cursor),
+                None => return Ok(most_items),
+            }
+        }
+    }
+}
+
+// Streams the export as json, or as csv when a table is given, reading each page from the store
+//      only when the stream is polled for it
+pub fn stream(
+    export: Export,
+    table: Option<Table>,
+) -> impl Stream<Item = Result<String, StoreError>> + Send {
+    stream::try_unfold(export, move |mut export| async move {
+        let chunk = match table {
+            Some(table) => export.next_csv(table).await?,
+            None => export.next_json().await?,
+        };
+        Ok(chunk.map(|chunk| (chunk, export)))
+    })
+}
+
+fn page_query(after: Option<String>) -> PageQuery {
+    PageQuery {
+        vendor: None,
+        limit: PAGE_SIZE,
+        after,
+        sort: None,
+        descending: false,
+        origin: None,
+    }
+}
+
+// The key of the linked record, links to deleted records are exported blank rather than as keys
+//      which match nothing
+fn key<'a>(keys: &'a HashMap<String, String>, thing: &Option<Thing>) -> &'a str {
+    match thing {
+        Some(thing) => keys
+            .get(&thing.id.to_raw())
+            .map(String::as_str)
+            .unwrap_or(""),
+        None => "",
+    }
+}
+
+// How a json value is written to a csv field, objects such as the overrides stay json
+pub fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+// Errors writing to memory can only come from the record, so they are reported as serialization
+fn csv_line(fields: &[String], delimiter: u8) -> Result<String, StoreError> {
+    let mut writer = WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(Vec::new());
+    let written = writer
+        .write_record(fields)
+        .map_err(|err| err.to_string())
+        .and_then(|()| writer.into_inner().map_err(|err| err.error().to_string()));
+    match written {
+        Ok(line) => Ok(String::from_utf8_lossy(&line).into_owned()),
+        Err(err) => Err(StoreError::Serialization(format!(
+            "Failed to write csv: {err}"
+        ))),
+    }
+}
@@ -0,0 +1,165 @@
+use crate::database::models::Event;
+use crate::database::schedule::Occurrence;
+use geoutils::Location;
+use serde::Serialize;
+
+// An occurrence along with how far it is from the point that was searched from
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct NearbyOccurrence {
+    pub distance_m: f64,
+    #[serde(flatten)]
+    pub occurrence: Occurrence,
+}
+
+// Events store their position as cord_x (longitude) and cord_y (latitude) since that is the
+//      order the geocoder returns them in
+pub fn event_location(event: &Event) -> Location {
+    Location::new(event.cord_y, event.cord_x)
+}
+
+// Distance in meters along the surface of the earth between the event and the point
+pub fn distance_m(event: &Event, lat: f64, lon: f64) -> f64 {
+    event_location(event)
+        .haversine_distance_to(&Location::new(lat, lon))
+        .meters()
+}
+
+// Occurrences which were moved are measured from where they were moved to
+pub fn occurrence_distance_m(occurrence: &Occurrence, lat: f64, lon: f64) -> f64 {
+    cords_distance_m(occurrence.cord_x, occurrence.cord_y, lat, lon)
+}
+
+// Whether any occurrence of the event could be within radius_m of the point, either at the
+//      event's location or one an occurrence was moved to
+pub fn may_be_near(event: &Event, lat: f64, lon: f64, radius_m: f64) -> bool {
+    distance_m(event, lat, lon) <= radius_m
+        || event
+            .overrides
+            .iter()
+            .any(|over| match (over.cord_x, over.cord_y) {
+                (Some(cord_x), Some(cord_y)) => {
+                    cords_distance_m(cord_x, cord_y, lat, lon) <= radius_m
+                }
+                _ => false,
+            })
+}
+
+fn cords_distance_m(cord_x: f64, cord_y: f64, lat: f64, lon: f64) -> f64 {
+    Location::new(cord_y, cord_x)
+        .haversine_distance_to(&Location::new(lat, lon))
+        .meters()
+}
+
+// Keeps the occurrences within radius_m of the point, sorted nearest first
+// Occurrences at the same distance stay sorted by start
+pub fn nearby(
+    occurrences: Vec<Occurrence>,
+    lat: f64,
+    lon: f64,
+    radius_m: f64,
+) -> Vec<NearbyOccurrence> {
+    let mut nearby: Vec<NearbyOccurrence> = occurrences
+        .into_iter()
+        .map(|occurrence| NearbyOccurrence {
+            distance_m: occurrence_distance_m(&occurrence, lat, lon),
+            occurrence,
+        })
+        .filter(|nearby| nearby.distance_m <= radius_m)
+        .collect();
+    nearby.sort_by(|a, b| a.distance_m.total_cmp(&b.distance_m));
+    nearby
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::OccurrenceOverride;
+    use crate::database::schedule;
+    use chrono::{DateTime, FixedOffset, NaiveDate};
+
+    fn at(value: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(value).unwrap()
+    }
+
+    fn event(name: &str, cord_x: f64, cord_y: f64, rule: Option<&str>) -> Event {
+        let mut event = Event::new(at("2024-05-03T10:00:00Z"), "Main Street".to_string(), None)
+            .with_cords(cord_x, cord_y);
+        event.name = name.to_string().into();
+        event.repeat_schedule = rule.map(|rule| rule.parse().unwrap());
+        event
+    }
+
+    #[test]
+    fn events_on_the_edge_of_the_radius_are_near() {
+        let event = event("Edge", 0.01, 0.0, None);
+        let radius_m = distance_m(&event, 0.0, 0.0);
+        assert!(radius_m > 1000.0 && radius_m < 1200.0, "{radius_m}");
+        assert!(may_be_near(&event, 0.0, 0.0, radius_m));
+        assert!(!may_be_near(&event, 0.0, 0.0, radius_m - 1.0));
+
+        let occurrences = schedule::expand(
+            &[event],
+            at("2024-05-03T00:00:00Z"),
+            at("2024-05-04T00:00:00Z"),
+        );
+        assert_eq!(nearby(occurrences.clone(), 0.0, 0.0, radius_m).len(), 1);
+        assert!(nearby(occurrences, 0.0, 0.0, radius_m - 1.0).is_empty());
+    }
+
+    #[test]
+    fn distances_wrap_around_the_antimeridian() {
+        let event = event("Date Line", 179.99, 0.0, None);
+        assert!(may_be_near(&event, 0.0, -179.99, 5000.0));
+        let nearby = nearby(
+            schedule::expand(
+                &[event],
+                at("2024-05-03T00:00:00Z"),
+                at("2024-05-04T00:00:00Z"),
+            ),
+            0.0,
+            -179.99,
+            5000.0,
+        );
+        assert_eq!(nearby.len(), 1);
+        assert!(nearby[0].distance_m < 2500.0, "{}", nearby[0].distance_m);
+    }
+
+    #[test]
+    fn events_moved_near_may_be_near() {
+        let mut event = event("Moved", 13.4, 52.5, None);
+        assert!(!may_be_near(&event, 0.0, 0.0, 5000.0));
+        let mut moved = OccurrenceOverride::new(NaiveDate::from_ymd_opt(2024, 5, 3).unwrap());
+        moved.cord_x = Some(0.0);
+        moved.cord_y = Some(0.0);
+        event.overrides.push(moved);
+        assert!(may_be_near(&event, 0.0, 0.0, 5000.0));
+    }
+
+    #[test]
+    fn weekly_events_are_near_on_their_day_nearest_first() {
+        let events = [
+            event("Farther", 0.02, 0.0, Some("FREQ=WEEKLY;BYDAY=FR")),
+            event("Nearer", 0.01, 0.0, Some("FREQ=WEEKLY;BYDAY=FR")),
+            event("Far Away", 1.0, 0.0, Some("FREQ=WEEKLY;BYDAY=FR")),
+        ];
+        // Monday to Monday, the events are only on Friday
+        let occurrences = schedule::expand(
+            &events,
+            at("2024-05-06T00:00:00Z"),
+            at("2024-05-13T00:00:00Z"),
+        );
+        let nearby = nearby(occurrences, 0.0, 0.0, 5000.0);
+        let names: Vec<&str> = nearby
+            .iter()
+            .map(|nearby| nearby.occurrence.event.name.as_ref())
+            .collect();
+        assert_eq!(names, vec!["Nearer", "Farther"]);
+        for nearby in nearby.iter() {
+            assert_eq!(
+                nearby.occurrence.date,
+                NaiveDate::from_ymd_opt(2024, 5, 10).unwrap()
+            );
+            assert_eq!(nearby.occurrence.start, at("2024-05-10T10:00:00Z"));
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use crate::database::geo::NearbyOccurrence;
+use crate::database::models::{Event, Vendor};
+use crate::database::schedule::{next_occurrence, Occurrence};
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+// Events, occurrences and vendors as RFC 7946 GeoJSON, so map libraries can show them without
+//      converting cord_x and cord_y by hand
+// Vendors have no location of their own, they are shown at where they will be next
+// Positions are [longitude, latitude], which is the order cord_x and cord_y are already in
+// Events without coordinates are left at 0, 0, they get a null geometry instead of a point in
+//      the ocean
+
+pub const CONTENT_TYPE: &str = "application/geo+json";
+
+// next_cursor and total are the paging of /events, they're left out elsewhere
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FeatureCollection {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub features: Vec<Feature>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Feature {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub id: String,
+    pub geometry: Option<Point>,
+    pub properties: Map<String, Value>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Point {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub coordinates: [f64; 2],
+}
+
+impl FeatureCollection {
+    pub fn new(features: Vec<Feature>) -> Self {
+        FeatureCollection {
+            kind: "FeatureCollection",
+            features,
+            next_cursor: None,
+            total: None,
+        }
+    }
+}
+
+pub fn point(cord_x: f64, cord_y: f64) -> Option<Point> {
+    match cord_x == 0.0 && cord_y == 0.0 {
+        true => None,
+        false => Some(Point {
+            kind: "Point",
+            coordinates: [cord_x, cord_y],
+        }),
+    }
+}
+
+// An event at its location, properties are the event's fields as returned by /events
+// vendor and next_occurrence are added to them, null when the event has no vendor or won't
+//      happen again within a year of now
+pub fn event_feature(
+    event: &Event,
+    mut properties: Map<String, Value>,
+    vendors: &HashMap<String, &Vendor>,
+    now: DateTime<FixedOffset>,
+) -> Feature {
+    let next = next_occurrence(event, now);
+    properties.insert("vendor".into(), vendor_properties(event, vendors));
+    properties.insert(
+        "next_occurrence".into(),
+        match &next {
+            Some(next) => occurrence_properties(next),
+            None => Value::Null,
+        },
+    );
+    Feature {
+        kind: "Feature",
+        id: event.uuid.to_string(),
+        geometry: point(event.cord_x, event.cord_y),
+        properties,
+    }
+}
+
+// A vendor at the location of the soonest next occurrence of its events, properties are the
+//      vendor's fields as returned by /vendors
+// next_occurrence is added to them with the uuid and name of its event, null when none of the
+//      vendor's events happen again within a year of now, which leaves it without a geometry
+pub fn vendor_feature(
+    vendor: &Vendor,
+    mut properties: Map<String, Value>,
+    events: &[Event],
+    now: DateTime<FixedOffset>,
+) -> Feature {
+    let next = events
+        .iter()
+        .filter_map(|event| next_occurrence(event, now))
+        .min_by_key(|occurrence| occurrence.start);
+    let (geometry, next_properties) = match &next {
+        Some(next) => {
+            let mut next_properties = occurrence_properties(next);
+            next_properties["event"] = json!({
+                "uuid": next.event.uuid,
+                "name": next.event.name,
+            });
+            (point(next.cord_x, next.cord_y), next_properties)
+        }
+        None => (None, Value::Null),
+    };
+    properties.insert("next_occurrence".into(), next_properties);
+    Feature {
+        kind: "Feature",
+        id: vendor.uuid.to_string(),
+        geometry,
+        properties,
+    }
+}
+
+// An occurrence found by /events/nearby at the location it happens at, which differs from its
+//      event's when it was relocated
+pub fn nearby_feature(nearby: &NearbyOccurrence, vendors: &HashMap<String, &Vendor>) -> Feature {
+    let occurrence = &nearby.occurrence;
+    let event = &occurrence.event;
+    let mut properties = Map::new();
+    properties.insert("distance_m".into(), json!(nearby.distance_m));
+    properties.insert("occurrence".into(), occurrence_properties(occurrence));
+    properties.insert(
+        "event".into(),
+        json!({
+            "uuid": event.uuid,
+            "name": event.name,
+            "location": event.location,
+            "timezone": event.timezone,
+            "duration_minutes": event.duration_minutes,
+            "repeat_schedule": event.repeat_schedule,
+        }),
+    );
+    properties.insert("vendor".into(), vendor_properties(event, vendors));
+    Feature {
+        kind: "Feature",
+        id: format!("{}/{}", event.uuid, occurrence.date),
+        geometry: point(occurrence.cord_x, occurrence.cord_y),
+        properties,
+    }
+}
+
+fn occurrence_properties(occurrence: &Occurrence) -> Value {
+    json!({
+        "date": occurrence.date,
+        "start": occurrence.start,
+        "end": occurrence.end,
+        "location": occurrence.location,
+        "note": occurrence.note,
+    })
+}
+
+fn vendor_properties(event: &Event, vendors: &HashMap<String, &Vendor>) -> Value {
+    let vendor = match &event.vendor {
+        Some(vendor) => vendors.get(&vendor.id.to_raw()),
+        None => None,
+    };
+    match vendor {
+        Some(vendor) => json!({
+            "uuid": vendor.uuid,
+            "name": vendor.name,
+            "vendor_type": vendor.vendor_type,
+            "website": vendor.website,
+        }),
+        None => Value::Null,
+    }
+}
@@ -0,0 +1,1081 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::database::models::{
+    Event, Menu, Vendor, DEFAULT_DURATION_MINUTES, MAX_DURATION_MINUTES,
+};
+use crate::database::recurrence::{RecurrenceRule, RuleTime};
+use crate::database::schedule::{occurrence_on, resolve_local, Occurrence};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+use serde::Serialize;
+use uuid::Uuid;
+
+// Writes events as an RFC 5545 calendar which phone and desktop calendars can subscribe to
+// Each event is one VEVENT whose UID is built from its uuid, so subscribers update the same
+//      entry when the event changes
+// Cancelled occurrences become EXDATEs and moved or relocated ones a VEVENT with a RECURRENCE-ID
+// Times are written on the wall clock of the event's zone with a VTIMEZONE describing it, so
+//      repeats keep their local time across daylight saving changes like they do here
+
+const PRODUCT_ID: &str = "-//Food Truck Finder//Schedule//EN";
+const UID_DOMAIN: &str = "food-truck-finder";
+
+// Lines longer than this many octets are folded onto continuation lines
+const MAX_LINE_OCTETS: usize = 75;
+
+// VTIMEZONEs list the zone's changes up to this many years after now, subscribers fetch the feed
+//      again long before then
+const ZONE_YEARS_AHEAD: i32 = 5;
+// and at most this many years before, for events which started long ago
+const ZONE_YEARS_BEHIND: i32 = 50;
+
+// region:      -- Calendar
+
+pub fn calendar(name: &str, events: &[Event], menus: &[Menu], vendors: &[Vendor]) -> String {
+    let menu_names: HashMap<String, &str> = menus
+        .iter()
+        .map(|menu| (menu.uuid.to_string(), menu.name.as_ref()))
+        .collect();
+    let vendor_names: HashMap<String, &str> = vendors
+        .iter()
+        .map(|vendor| (vendor.uuid.to_string(), vendor.name.as_ref()))
+        .collect();
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+
+    let mut lines = Writer::default();
+    lines.push("BEGIN:VCALENDAR");
+    lines.push("VERSION:2.0");
+    lines.push(format!("PRODID:{PRODUCT_ID}"));
+    lines.push("CALSCALE:GREGORIAN");
+    lines.push("METHOD:PUBLISH");
+    lines.push(format!("X-WR-CALNAME:{}", text(name)));
+
+    // Every zone used by an event is described once, covering its earliest event onwards
+    let mut zones: Vec<(Tz, i32)> = Vec::new();
+    for event in events.iter().filter(|event| event.timezone != Tz::UTC) {
+        let year = event.datetime.with_timezone(&event.timezone).year();
+        match zones.iter_mut().find(|(zone, _)| *zone == event.timezone) {
+            Some((_, earliest)) => *earliest = (*earliest).min(year),
+            None => zones.push((event.timezone, year)),
+        }
+    }
+    zones.sort_by_key(|(zone, _)| zone.name());
+    for (zone, earliest) in zones {
+        timezone(&mut lines, zone, earliest);
+    }
+
+    for event in events.iter() {
+        let menu = match &event.menu {
+            Some(menu) => menu_names.get(&menu.id.to_raw()).copied(),
+            None => None,
+        };
+        let summary = match (event.name.as_ref(), &event.vendor) {
+            ("", Some(vendor)) => vendor_names
+                .get(&vendor.id.to_raw())
+                .copied()
+                .unwrap_or("Food truck"),
+            ("", None) => "Food truck",
+            (name, _) => name,
+        };
+        vevent(&mut lines, event, summary, menu, &stamp);
+    }
+    lines.push("END:VCALENDAR");
+    lines.0
+}
+
+fn vevent(lines: &mut Writer, event: &Event, summary: &str, menu: Option<&str>, stamp: &str) {
+    let uid = format!("UID:{}@{UID_DOMAIN}", event.uuid);
+    let first = event.datetime.with_timezone(&event.timezone).date_naive();
+    let rule = match &event.repeat_schedule {
+        Some(rule) => rule,
+        // A single occurrence has nothing to recur, its override is applied to it directly
+        None => {
+            if let Some(occurrence) = occurrence_on(event, first) {
+                lines.push("BEGIN:VEVENT");
+                lines.push(&uid);
+                lines.push(format!("DTSTAMP:{stamp}"));
+                details(lines, &occurrence, summary, menu);
+                if occurrence.cancelled {
+                    lines.push("STATUS:CANCELLED");
+                }
+                lines.push("END:VEVENT");
+            }
+            return;
+        }
+    };
+
+    let mut excluded: Vec<DateTime<FixedOffset>> = rule
+        .exdates
+        .iter()
+        .filter_map(|exdate| scheduled_start(event, exdate.local_date(event.timezone)))
+        .collect();
+    excluded.extend(
+        event
+            .overrides
+            .iter()
+            .filter(|over| over.cancelled)
+            .filter_map(|over| scheduled_start(event, over.date)),
+    );
+    excluded.sort();
+    excluded.dedup();
+
+    // The recurring event describes every occurrence as scheduled, before any override
+    let scheduled = Occurrence::new(
+        first,
+        event.datetime,
+        &Event {
+            overrides: Vec::new(),
+            ..event.clone()
+        },
+    );
+    lines.push("BEGIN:VEVENT");
+    lines.push(&uid);
+    lines.push(format!("DTSTAMP:{stamp}"));
+    details(lines, &scheduled, summary, menu);
+    lines.push(feed_rule(event, rule).to_string());
+    for start in excluded {
+        lines.push(datetime("EXDATE", start, event.timezone));
+    }
+    lines.push("END:VEVENT");
+
+    // Occurrences which were moved, relocated or given a note replace their scheduled instance
+    for over in event.overrides.iter().filter(|over| !over.cancelled) {
+        let (occurrence, scheduled) = match (
+            occurrence_on(event, over.date),
+            scheduled_start(event, over.date),
+        ) {
+            (Some(occurrence), Some(scheduled)) => (occurrence, scheduled),
+            _ => continue,
+        };
+        lines.push("BEGIN:VEVENT");
+        lines.push(&uid);
+        lines.push(format!("DTSTAMP:{stamp}"));
+        lines.push(datetime("RECURRENCE-ID", scheduled, event.timezone));
+        details(lines, &occurrence, summary, menu);
+        lines.push("END:VEVENT");
+    }
+}
+
+// The when, where and what of an occurrence, the note comes before the menu
+fn details(lines: &mut Writer, occurrence: &Occurrence, summary: &str, menu: Option<&str>) {
+    let event = &occurrence.event;
+    lines.push(datetime("DTSTART", occurrence.start, event.timezone));
+    lines.push(format!("DURATION:PT{}M", event.duration_minutes));
+    lines.push(format!("SUMMARY:{}", text(summary)));
+    lines.push(format!("LOCATION:{}", text(&occurrence.location)));
+    if let Some(geo) = geo(occurrence.cord_x, occurrence.cord_y) {
+        lines.push(geo);
+    }
+    let menu = menu.map(|menu| format!("Menu: {menu}"));
+    let description = match (&occurrence.note, menu) {
+        (Some(note), Some(menu)) => Some(format!("{note}\n{menu}")),
+        (Some(note), None) => Some(note.to_string()),
+        (None, menu) => menu,
+    };
+    if let Some(description) = description {
+        lines.push(format!("DESCRIPTION:{}", text(&description)));
+    }
+}
+
+// The rule as subscribers should expand it, with the event's repeat_end folded into UNTIL
+// RFC 5545 requires UNTIL in UTC when DTSTART has a zone, and EXDATEs are written separately
+fn feed_rule(event: &Event, rule: &RecurrenceRule) -> RecurrenceRule {
+    let zone = event.timezone;
+    let mut feed = rule.clone();
+    feed.exdates.clear();
+    let until = [
+        event.repeat_end,
+        rule.until.and_then(|until| until.latest_start(zone)),
+    ]
+    .into_iter()
+    .flatten()
+    .min();
+    let until = match until {
+        Some(until) => until,
+        None => return feed,
+    };
+    // UNTIL and COUNT can't both be given, so the one which ends the event first is kept
+    if let Some(count) = rule.count {
+        let first = event.datetime.with_timezone(&zone).date_naive();
+        let last = until.with_timezone(&zone).date_naive();
+        let before_until = feed
+            .dates(first, zone, first, last)
+            .into_iter()
+            .filter_map(|date| scheduled_start(event, date))
+            .filter(|start| *start <= until)
+            .count();
+        if before_until >= count as usize {
+            return feed;
+        }
+        feed.count = None;
+    }
+    feed.until = Some(RuleTime::Utc(until.with_timezone(&Utc)));
+    feed
+}
+
+// When the occurrence on the date starts before any override, whether or not the rule
+//      produces the date
+fn scheduled_start(event: &Event, date: NaiveDate) -> Option<DateTime<FixedOffset>> {
+    let local = event.datetime.with_timezone(&event.timezone);
+    match date == local.date_naive() {
+        true => Some(event.datetime),
+        false => resolve_local(event.timezone, date.and_time(local.time())),
+    }
+}
+
+// endregion:   -- Calendar
+
+// region:      -- Time zones
+
+// The zone's offsets from the start of the earliest year onwards, an observance for the offset
+//      the year starts in followed by one for every change
+fn timezone(lines: &mut Writer, zone: Tz, earliest: i32) {
+    let latest = Utc::now().year() + ZONE_YEARS_AHEAD;
+    let earliest = earliest.clamp(latest - ZONE_YEARS_BEHIND, latest);
+    let start = match zone.with_ymd_and_hms(earliest, 1, 1, 0, 0, 0).earliest() {
+        Some(start) => start.with_timezone(&Utc),
+        None => return,
+    };
+
+    lines.push("BEGIN:VTIMEZONE");
+    lines.push(format!("TZID:{}", zone.name()));
+    observance(lines, zone, start, start);
+    for transition in transitions(zone, latest)
+        .iter()
+        .filter(|transition| transition.at > start)
+    {
+        observance(lines, zone, transition.before, transition.at);
+    }
+    lines.push("END:VTIMEZONE");
+}
+
+// A change of a zone's offset, before is the last minute of the old offset and at the first of
+//      the new one
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Transition {
+    before: DateTime<Utc>,
+    at: DateTime<Utc>,
+}
+
+// The changes of every zone described so far, keyed by its name and the last year covered
+// Finding them checks every day of ZONE_YEARS_BEHIND years, so it's done once per zone rather
+//      than every time a feed is fetched
+static TRANSITIONS: Mutex<ZoneTransitions> = Mutex::new(BTreeMap::new());
+
+type ZoneTransitions = BTreeMap<(&'static str, i32), Arc<Vec<Transition>>>;
+
+// The zone's changes from ZONE_YEARS_BEHIND years before latest up to the end of latest
+fn transitions(zone: Tz, latest: i32) -> Arc<Vec<Transition>> {
+    let key = (zone.name(), latest);
+    if let Some(found) = TRANSITIONS
+        .lock()
+        .expect("time zone transition lock poisoned")
+        .get(&key)
+    {
+        return found.clone();
+    }
+
+    let found = Arc::new(find_transitions(zone, latest));
+    let mut cached = TRANSITIONS
+        .lock()
+        .expect("time zone transition lock poisoned");
+    // Entries for earlier years are left over from before the new year
+    cached.retain(|(_, year), _| *year == latest);
+    cached.insert(key, found.clone());
+    found
+}
+
+fn find_transitions(zone: Tz, latest: i32) -> Vec<Transition> {
+    // A day early so a change in the first hours of the zone's year isn't missed
+    let start = match Utc
+        .with_ymd_and_hms(latest - ZONE_YEARS_BEHIND - 1, 12, 31, 0, 0, 0)
+        .single()
+    {
+        Some(start) => start,
+        None => return Vec::new(),
+    };
+    let end = match Utc.with_ymd_and_hms(latest + 1, 1, 1, 0, 0, 0).single() {
+        Some(end) => end,
+        None => return Vec::new(),
+    };
+
+    let mut found = Vec::new();
+    let mut day = start;
+    while day < end {
+        let next = day + Duration::days(1);
+        if offset(zone, day) != offset(zone, next) {
+            // Changes happen on the minute, narrowed down from the day they happen in
+            let (mut before, mut after) = (0, 24 * 60);
+            while after - before > 1 {
+                let middle = (before + after) / 2;
+                match offset(zone, day + Duration::minutes(middle)) == offset(zone, day) {
+                    true => before = middle,
+                    false => after = middle,
+                }
+            }
+            found.push(Transition {
+                before: day + Duration::minutes(before),
+                at: day + Duration::minutes(after),
+            });
+        }
+        day = next;
+    }
+    found
+}
+
+// The offset in effect from at, which began after the offset in effect at before
+// Its DTSTART is the wall clock time of the change in the earlier offset
+fn observance(lines: &mut Writer, zone: Tz, before: DateTime<Utc>, at: DateTime<Utc>) {
+    let from = offset(zone, before);
+    let to = offset(zone, at);
+    let local = zone.offset_from_utc_datetime(&at.naive_utc());
+    let kind = match local.dst_offset().is_zero() {
+        true => "STANDARD",
+        false => "DAYLIGHT",
+    };
+    lines.push(format!("BEGIN:{kind}"));
+    lines.push(format!(
+        "DTSTART:{}",
+        at.with_timezone(&from).format("%Y%m%dT%H%M%S")
+    ));
+    lines.push(format!("TZOFFSETFROM:{}", utc_offset(from)));
+    lines.push(format!("TZOFFSETTO:{}", utc_offset(to)));
+    if let Some(abbreviation) = local.abbreviation() {
+        lines.push(format!("TZNAME:{}", text(abbreviation)));
+    }
+    lines.push(format!("END:{kind}"));
+}
+
+fn offset(zone: Tz, at: DateTime<Utc>) -> FixedOffset {
+    at.with_timezone(&zone).fixed_offset().timezone()
+}
+
+// +0530 or -0400, with seconds only when the offset has them
+fn utc_offset(offset: FixedOffset) -> String {
+    let seconds = offset.local_minus_utc();
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let seconds = seconds.abs();
+    match seconds % 60 {
+        0 => format!("{sign}{:02}{:02}", seconds / 3600, seconds % 3600 / 60),
+        rest => format!(
+            "{sign}{:02}{:02}{rest:02}",
+            seconds / 3600,
+            seconds % 3600 / 60
+        ),
+    }
+}
+
+// endregion:   -- Time zones
+
+// region:      -- Reading
+
+// Calendars exported by Google and Apple Calendar can be read back into events
+// Each VEVENT becomes an entry or the reason it was skipped, see CalendarEntry::read
+
+// Events are stored under a uuid derived from their UID and vendor, so importing the same
+//      calendar again updates the events it created
+const UID_NAMESPACE: Uuid = Uuid::from_u128(0x3a9e_51c4_0d7b_4e62_a1f8_6c2d_94b0_e7f3);
+
+// A property of a component after unfolding, NAME;PARAM=VALUE:value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Property {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub value: String,
+}
+
+impl Property {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// The VEVENTs of a calendar along with the zone X-WR-TIMEZONE names for times without one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calendar {
+    pub timezone: Option<Tz>,
+    pub events: Vec<Vec<Property>>,
+}
+
+// Reads the VEVENTs out of an iCalendar file, the other components are skipped
+// Only files which aren't iCalendar at all are an error, problems with a VEVENT are found when
+//      it is read into an entry so the rest of the file can still be imported
+pub fn read(text: &str) -> Result<Calendar, String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(folded), Some(last)) => last.push_str(folded),
+            _ if line.trim().is_empty() => (),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    match lines.first() {
+        Some(first) if first.trim().eq_ignore_ascii_case("BEGIN:VCALENDAR") => (),
+        _ => return Err("must be an iCalendar file starting with BEGIN:VCALENDAR".into()),
+    }
+
+    let mut calendar = Calendar {
+        timezone: None,
+        events: Vec::new(),
+    };
+    // The components the current line is nested in, VEVENT properties are only collected when
+    //      the VEVENT is the innermost so its VALARMs are left out
+    let mut components: Vec<String> = Vec::new();
+    let mut event: Vec<Property> = Vec::new();
+    for line in lines.iter() {
+        let property = match property(line) {
+            Some(property) => property,
+            None => return Err(format!("{line} is not an iCalendar content line")),
+        };
+        match property.name.as_str() {
+            "BEGIN" => {
+                if property.value.eq_ignore_ascii_case("VEVENT") {
+                    event.clear();
+                }
+                components.push(property.value.to_uppercase());
+            }
+            "END" => {
+                let ended = components.pop();
+                if ended.as_deref() == Some("VEVENT") {
+                    calendar.events.push(std::mem::take(&mut event));
+                }
+            }
+            "X-WR-TIMEZONE" if components.len() == 1 => {
+                calendar.timezone = property.value.trim().parse::<Tz>().ok()
+            }
+            _ if components.last().map(String::as_str) == Some("VEVENT") => event.push(property),
+            _ => (),
+        }
+    }
+    Ok(calendar)
+}
+
+// Splits a content line at the first colon outside of a quoted parameter value
+fn property(line: &str) -> Option<Property> {
+    let mut quoted = false;
+    let mut split = None;
+    for (index, character) in line.char_indices() {
+        match character {
+            '"' => quoted = !quoted,
+            ':' if !quoted => {
+                split = Some(index);
+                break;
+            }
+            _ => (),
+        }
+    }
+    let (head, value) = line.split_at(split?);
+    let mut parts = head.split(';');
+    let name = parts.next()?.trim().to_uppercase();
+    if name.is_empty() {
+        return None;
+    }
+    let params = parts
+        .filter_map(|param| param.split_once('='))
+        .map(|(param, value)| {
+            (
+                param.trim().to_uppercase(),
+                value.trim().trim_matches('"').to_string(),
+            )
+        })
+        .collect();
+    Some(Property {
+        name,
+        params,
+        value: value[1..].to_string(),
+    })
+}
+
+// A VEVENT read into an event, which has no uuid or vendor yet
+//      uid     -> The VEVENT's UID, events are matched by it when a calendar is imported again
+//      geo     -> The coordinates came from GEO, otherwise LOCATION still needs geocoding
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalendarEntry {
+    pub uid: String,
+    pub event: Event,
+    pub geo: bool,
+}
+
+impl CalendarEntry {
+    // Err is the reason the VEVENT can't be imported
+    pub fn read(properties: &[Property], default_zone: Option<Tz>) -> Result<Self, String> {
+        let get = |name: &str| properties.iter().find(|property| property.name == name);
+        let uid = match get("UID") {
+            Some(uid) if !uid.value.trim().is_empty() => uid.value.trim().to_string(),
+            _ => return Err("has no UID".into()),
+        };
+        if get("RECURRENCE-ID").is_some() {
+            return Err("changes to a single occurrence aren't imported".into());
+        }
+        if get("RDATE").is_some() {
+            return Err("RDATE isn't supported, only RRULE and EXDATE".into());
+        }
+        if matches!(get("STATUS"), Some(status) if status.value.eq_ignore_ascii_case("CANCELLED")) {
+            return Err("is cancelled".into());
+        }
+        let location = match get("LOCATION").map(|location| unescape(&location.value)) {
+            Some(location) if !location.trim().is_empty() => location.trim().to_string(),
+            _ => return Err("has no LOCATION".into()),
+        };
+
+        let start = match get("DTSTART") {
+            Some(start) => start,
+            None => return Err("has no DTSTART".into()),
+        };
+        let zone = match start.param("TZID") {
+            Some(tzid) => match tzid.parse::<Tz>() {
+                Ok(zone) => zone,
+                Err(_) => return Err(format!("TZID {tzid} is not an IANA time zone")),
+            },
+            None => default_zone.unwrap_or(Tz::UTC),
+        };
+        let datetime = match instant(start, zone)? {
+            Some(datetime) => datetime,
+            None => return Err("is an all day event, events need a start time".into()),
+        };
+        let duration_minutes = match (get("DTEND"), get("DURATION")) {
+            (Some(end), _) => {
+                let end_zone = match end.param("TZID").map(|tzid| tzid.parse::<Tz>()) {
+                    Some(Ok(end_zone)) => end_zone,
+                    Some(Err(_)) => return Err("DTEND has an unknown TZID".into()),
+                    None => zone,
+                };
+                match instant(end, end_zone)? {
+                    Some(end) => (end - datetime).num_minutes(),
+                    None => return Err("DTEND must be a datetime like DTSTART".into()),
+                }
+            }
+            (None, Some(duration)) => match minutes(&duration.value) {
+                Some(minutes) => minutes,
+                None => return Err(format!("DURATION {} is not a duration", duration.value)),
+            },
+            (None, None) => i64::from(DEFAULT_DURATION_MINUTES),
+        };
+        let duration_minutes = match u32::try_from(duration_minutes) {
+            Ok(minutes) if (1..=MAX_DURATION_MINUTES).contains(&minutes) => minutes,
+            _ => return Err("must last between 1 minute and 24 hours".into()),
+        };
+
+        // The rule and its EXDATEs are read together the same way the API reads them
+        let mut rule_lines: Vec<String> = Vec::new();
+        for property in properties.iter() {
+            match (property.name.as_str(), property.param("TZID")) {
+                ("RRULE", _) => rule_lines.insert(0, format!("RRULE:{}", property.value)),
+                ("EXDATE", Some(tzid)) => {
+                    rule_lines.push(format!("EXDATE;TZID={tzid}:{}", property.value))
+                }
+                ("EXDATE", None) => rule_lines.push(format!("EXDATE:{}", property.value)),
+                _ => (),
+            }
+        }
+        let repeat_schedule = match get("RRULE") {
+            Some(_) => match rule_lines.join("\n").parse::<RecurrenceRule>() {
+                Ok(rule) => Some(rule),
+                Err(err) => return Err(format!("RRULE can't be imported: {err}")),
+            },
+            None => None,
+        };
+
+        let mut event = Event::new(datetime, location, None).with_timezone(zone);
+        event.duration_minutes = duration_minutes;
+        event.repeat_schedule = repeat_schedule;
+        if let Some(summary) = get("SUMMARY") {
+            event.name = unescape(&summary.value).trim().to_string().into();
+        }
+        let geo = match get("GEO").and_then(|geo| geo.value.split_once(';')) {
+            Some((lat, lon)) => match (lat.trim().parse::<f64>(), lon.trim().parse::<f64>()) {
+                (Ok(lat), Ok(lon))
+                    if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) =>
+                {
+                    event = event.with_cords(lon, lat);
+                    true
+                }
+                _ => false,
+            },
+            None => false,
+        };
+        Ok(CalendarEntry { uid, event, geo })
+    }
+
+    // The uuid the entry is stored under for the vendor, UIDs from our own feeds keep the uuid
+    //      of the event they were written from
+    pub fn uuid(&self, vendor_id: &str) -> String {
+        if let Some(uuid) = self.uid.strip_suffix(&format!("@{UID_DOMAIN}")) {
+            if Uuid::try_parse(uuid).is_ok() {
+                return uuid.to_uppercase();
+            }
+        }
+        let name = format!("{vendor_id}\u{1f}{}", self.uid);
+        Uuid::new_v5(&UID_NAMESPACE, name.as_bytes())
+            .simple()
+            .encode_upper(&mut Uuid::encode_buffer())
+            .to_string()
+    }
+}
+
+// The instant a DTSTART or DTEND is at, None for dates without a time
+// Times without a Z are on the wall clock of the zone
+fn instant(property: &Property, zone: Tz) -> Result<Option<DateTime<FixedOffset>>, String> {
+    let value = property.value.trim();
+    if property.param("VALUE") == Some("DATE") || value.len() == 8 {
+        return Ok(None);
+    }
+    let invalid = || format!("{} {value} is not a datetime", property.name);
+    let (local, utc) = match value.strip_suffix('Z') {
+        Some(local) => (local, true),
+        None => (value, false),
+    };
+    let local = match NaiveDateTime::parse_from_str(local, "%Y%m%dT%H%M%S") {
+        Ok(local) => local,
+        Err(_) => return Err(invalid()),
+    };
+    match utc {
+        true => Ok(Some(local.and_utc().fixed_offset())),
+        false => match resolve_local(zone, local) {
+            Some(datetime) => Ok(Some(datetime)),
+            None => Err(invalid()),
+        },
+    }
+}
+
+// The length of a DURATION such as PT1H30M in whole minutes
+fn minutes(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (sign, value) = match value.strip_prefix('-') {
+        Some(value) => (-1, value),
+        None => (1, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let mut seconds = 0;
+    let mut number = String::new();
+    let mut time = false;
+    for character in value.strip_prefix('P')?.chars() {
+        match character {
+            '0'..='9' => number.push(character),
+            'T' if number.is_empty() => time = true,
+            unit => {
+                let amount: i64 = number.parse().ok()?;
+                number.clear();
+                seconds += amount
+                    * match (unit, time) {
+                        ('W', false) => 7 * 24 * 3600,
+                        ('D', false) => 24 * 3600,
+                        ('H', true) => 3600,
+                        ('M', true) => 60,
+                        ('S', true) => 1,
+                        _ => return None,
+                    };
+            }
+        }
+    }
+    match number.is_empty() {
+        true => Some(sign * seconds / 60),
+        false => None,
+    }
+}
+
+// Reverses the escaping of a TEXT value
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut characters = value.chars();
+    while let Some(character) = characters.next() {
+        match (character, character == '\\') {
+            (_, true) => match characters.next() {
+                Some('n') | Some('N') => unescaped.push('\n'),
+                Some(escaped) => unescaped.push(escaped),
+                None => unescaped.push('\\'),
+            },
+            (character, false) => unescaped.push(character),
+        }
+    }
+    unescaped
+}
+
+// What happened to each VEVENT of an imported calendar, in the order they appear
+#[derive(Serialize, Debug, Clone, PartialEq, Default)]
+pub struct ImportReport {
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub events: Vec<ImportedEvent>,
+}
+
+//      event_id    -> The event the VEVENT created or updated
+//      reason      -> Why it was skipped
+//      warning     -> Something to check on an imported event, such as a LOCATION which
+//                     couldn't be geocoded
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ImportedEvent {
+    pub uid: Option<String>,
+    pub summary: Option<String>,
+    pub status: ImportStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    Created,
+    Updated,
+    Skipped,
+}
+
+impl ImportReport {
+    pub fn push(&mut self, event: ImportedEvent) {
+        match event.status {
+            ImportStatus::Created => self.created += 1,
+            ImportStatus::Updated => self.updated += 1,
+            ImportStatus::Skipped => self.skipped += 1,
+        }
+        self.events.push(event);
+    }
+
+    pub fn skip(&mut self, properties: &[Property], reason: impl Into<String>) {
+        let value = |name: &str| {
+            properties
+                .iter()
+                .find(|property| property.name == name)
+                .map(|property| unescape(&property.value))
+        };
+        self.push(ImportedEvent {
+            uid: value("UID"),
+            summary: value("SUMMARY"),
+            status: ImportStatus::Skipped,
+            event_id: None,
+            reason: Some(reason.into()),
+            warning: None,
+        });
+    }
+}
+
+// endregion:   -- Reading
+
+// region:      -- Formatting
+
+// Times in UTC events are written as UTC, others on the wall clock of their VTIMEZONE
+fn datetime(name: &str, at: DateTime<FixedOffset>, zone: Tz) -> String {
+    match zone {
+        Tz::UTC => format!("{name}:{}", at.naive_utc().format("%Y%m%dT%H%M%SZ")),
+        zone => format!(
+            "{name};TZID={}:{}",
+            zone.name(),
+            at.with_timezone(&zone).format("%Y%m%dT%H%M%S")
+        ),
+    }
+}
+
+// GEO is latitude then longitude, events without coordinates are left at 0, 0
+fn geo(cord_x: f64, cord_y: f64) -> Option<String> {
+    match cord_x == 0.0 && cord_y == 0.0 {
+        true => None,
+        false => Some(format!("GEO:{cord_y};{cord_x}")),
+    }
+}
+
+// Escapes a TEXT value
+fn text(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        match character {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => (),
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+// Content lines ending in CRLF, folded so none is longer than 75 octets
+#[derive(Default)]
+struct Writer(String);
+
+impl Writer {
+    fn push(&mut self, line: impl AsRef<str>) {
+        let mut octets = 0;
+        for character in line.as_ref().chars() {
+            if octets + character.len_utf8() > MAX_LINE_OCTETS {
+                // The space starting a continuation line counts towards its length
+                self.0.push_str("\r\n ");
+                octets = 1;
+            }
+            self.0.push(character);
+            octets += character.len_utf8();
+        }
+        self.0.push_str("\r\n");
+    }
+}
+
+// endregion:   -- Formatting
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn berlin_event() -> Event {
+        let datetime = DateTime::parse_from_rfc3339("2024-05-03T10:00:00+02:00").unwrap();
+        let mut event = Event::new(datetime, "Alexanderplatz".to_string(), None)
+            .with_timezone(chrono_tz::Europe::Berlin);
+        event.name = "Currywurst".into();
+        event.repeat_schedule = Some("FREQ=WEEKLY;BYDAY=FR".parse().unwrap());
+        event
+    }
+
+    #[test]
+    fn long_lines_are_folded_at_75_octets() {
+        let mut lines = Writer::default();
+        let line = format!("DESCRIPTION:{}", "ä".repeat(40) + &"x".repeat(100));
+        lines.push(&line);
+        let written = lines.0.strip_suffix("\r\n").unwrap();
+        for physical in written.split("\r\n") {
+            assert!(physical.len() <= MAX_LINE_OCTETS, "{physical:?}");
+        }
+        assert!(written
+            .split("\r\n")
+            .skip(1)
+            .all(|line| line.starts_with(' ')));
+        assert_eq!(written.replace("\r\n ", ""), line);
+    }
+
+    #[test]
+    fn short_lines_are_left_alone() {
+        let mut lines = Writer::default();
+        let line = "X".repeat(MAX_LINE_OCTETS);
+        lines.push(&line);
+        assert_eq!(lines.0, format!("{line}\r\n"));
+    }
+
+    #[test]
+    fn text_is_escaped() {
+        assert_eq!(
+            text("Tacos, burritos; more\\less\r\nnext"),
+            r"Tacos\, burritos\; more\\less\nnext"
+        );
+    }
+
+    #[test]
+    fn calendars_escape_and_describe_zones() {
+        let feed = calendar("Trucks; Berlin", &[berlin_event()], &[], &[]);
+        assert!(feed.contains("X-WR-CALNAME:Trucks\\; Berlin\r\n"));
+        assert!(feed.contains("DTSTART;TZID=Europe/Berlin:20240503T100000\r\n"));
+        assert!(feed.contains("RRULE:FREQ=WEEKLY;BYDAY=FR\r\n"));
+        assert!(feed.contains(
+            "BEGIN:DAYLIGHT\r\nDTSTART:20240331T020000\r\nTZOFFSETFROM:+0100\r\nTZOFFSETTO:+0200\r\n"
+        ));
+        assert!(feed.contains(
+            "BEGIN:STANDARD\r\nDTSTART:20241027T030000\r\nTZOFFSETFROM:+0200\r\nTZOFFSETTO:+0100\r\n"
+        ));
+        // The zone is described from the start of the event's year, not before
+        assert!(!feed.contains("DTSTART:2023"));
+        assert!(feed.ends_with("END:VCALENDAR\r\n"));
+    }
+
+    #[test]
+    fn zone_transitions_are_found_once() {
+        let latest = Utc::now().year() + ZONE_YEARS_AHEAD;
+        let first = transitions(chrono_tz::America::New_York, latest);
+        let second = transitions(chrono_tz::America::New_York, latest);
+        assert!(Arc::ptr_eq(&first, &second));
+        // Twice a year, every year
+        assert!(first.len() >= 2 * ZONE_YEARS_BEHIND as usize);
+        let change = first
+            .iter()
+            .find(|transition| transition.at.year() == 2024)
+            .unwrap();
+        assert_eq!(change.at.to_rfc3339(), "2024-03-10T07:00:00+00:00");
+        assert_eq!(change.at - change.before, Duration::minutes(1));
+        assert!(transitions(chrono_tz::Asia::Kolkata, latest).is_empty());
+    }
+
+    // A VEVENT with the given lines, in a calendar with an optional X-WR-TIMEZONE
+    fn entry(lines: &[&str], zone: Option<&str>) -> Result<CalendarEntry, String> {
+        let mut text = vec!["BEGIN:VCALENDAR".to_string()];
+        if let Some(zone) = zone {
+            text.push(format!("X-WR-TIMEZONE:{zone}"));
+        }
+        text.push("BEGIN:VEVENT".to_string());
+        text.push("UID:abc@example.com".to_string());
+        text.push("LOCATION:Main Street".to_string());
+        text.extend(lines.iter().map(|line| line.to_string()));
+        text.push("END:VEVENT".to_string());
+        text.push("END:VCALENDAR".to_string());
+        let calendar = read(&text.join("\r\n")).unwrap();
+        assert_eq!(calendar.events.len(), 1);
+        CalendarEntry::read(&calendar.events[0], calendar.timezone)
+    }
+
+    #[test]
+    fn folded_lines_are_unfolded() {
+        let calendar = read(
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:Taco\r\n  Tuesday at the\r\n\t park\r\n\
+             DESCRIPTION;LANGUAGE=en:a\r\n b\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+        )
+        .unwrap();
+        let event = &calendar.events[0];
+        assert_eq!(event[0].value, "Taco Tuesday at the park");
+        assert_eq!(event[1].value, "ab");
+        assert_eq!(event[1].param("language"), Some("en"));
+    }
+
+    #[test]
+    fn only_calendars_are_read() {
+        assert!(read("BEGIN:VCARD\r\nEND:VCARD\r\n").is_err());
+        assert!(read("").is_err());
+    }
+
+    #[test]
+    fn alarms_are_left_out() {
+        let entry = entry(
+            &[
+                "DTSTART:20240503T100000Z",
+                "SUMMARY:Tacos",
+                "BEGIN:VALARM",
+                "SUMMARY:Reminder",
+                "TRIGGER:-PT15M",
+                "END:VALARM",
+            ],
+            None,
+        )
+        .unwrap();
+        assert_eq!(entry.event.name, "Tacos");
+    }
+
+    #[test]
+    fn dtstart_with_a_tzid_is_on_its_wall_clock() {
+        let entry = entry(
+            &["DTSTART;TZID=America/New_York:20240503T100000"],
+            Some("Europe/Berlin"),
+        )
+        .unwrap();
+        assert_eq!(entry.event.timezone, chrono_tz::America::New_York);
+        assert_eq!(
+            entry.event.datetime.to_rfc3339(),
+            "2024-05-03T10:00:00-04:00"
+        );
+    }
+
+    #[test]
+    fn dtstart_in_utc_is_kept_as_is() {
+        let entry = entry(&["DTSTART:20240503T140000Z"], None).unwrap();
+        assert_eq!(entry.event.timezone, Tz::UTC);
+        assert_eq!(
+            entry.event.datetime.to_rfc3339(),
+            "2024-05-03T14:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn floating_dtstart_uses_the_calendar_zone() {
+        let entry = entry(&["DTSTART:20240503T100000"], Some("Europe/Berlin")).unwrap();
+        assert_eq!(entry.event.timezone, chrono_tz::Europe::Berlin);
+        assert_eq!(
+            entry.event.datetime.to_rfc3339(),
+            "2024-05-03T10:00:00+02:00"
+        );
+
+        let entry = self::entry(&["DTSTART:20240503T100000"], None).unwrap();
+        assert_eq!(
+            entry.event.datetime.to_rfc3339(),
+            "2024-05-03T10:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn unusable_starts_are_skipped() {
+        assert!(entry(&["DTSTART;VALUE=DATE:20240503"], None).is_err());
+        assert!(entry(&["DTSTART;TZID=Mars/Olympus:20240503T100000"], None).is_err());
+        assert!(entry(&["DTSTART:tomorrow"], None).is_err());
+        assert!(entry(&["SUMMARY:No start"], None).is_err());
+    }
+
+    #[test]
+    fn durations_are_read_in_minutes() {
+        assert_eq!(minutes("PT1H30M"), Some(90));
+        assert_eq!(minutes("PT90M"), Some(90));
+        assert_eq!(minutes("P1D"), Some(24 * 60));
+        assert_eq!(minutes("P1W"), Some(7 * 24 * 60));
+        assert_eq!(minutes("P1DT2H"), Some(26 * 60));
+        assert_eq!(minutes("PT45S"), Some(0));
+        assert_eq!(minutes("-PT15M"), Some(-15));
+        assert_eq!(minutes("PT1H30"), None);
+        assert_eq!(minutes("P1H"), None);
+        assert_eq!(minutes("1H"), None);
+    }
+
+    #[test]
+    fn the_length_comes_from_dtend_or_duration() {
+        let entry = entry(&["DTSTART:20240503T100000Z", "DURATION:PT2H30M"], None).unwrap();
+        assert_eq!(entry.event.duration_minutes, 150);
+        let entry = self::entry(
+            &[
+                "DTSTART;TZID=Europe/Berlin:20240503T100000",
+                "DTEND:20240503T090000Z",
+            ],
+            None,
+        )
+        .unwrap();
+        assert_eq!(entry.event.duration_minutes, 60);
+        let entry = self::entry(&["DTSTART:20240503T100000Z"], None).unwrap();
+        assert_eq!(entry.event.duration_minutes, DEFAULT_DURATION_MINUTES);
+        assert!(self::entry(&["DTSTART:20240503T100000Z", "DURATION:P2D"], None).is_err());
+    }
+
+    #[test]
+    fn overrides_and_cancelled_events_are_skipped() {
+        assert_eq!(
+            entry(
+                &["DTSTART:20240510T100000Z", "RECURRENCE-ID:20240510T100000Z"],
+                None
+            ),
+            Err("changes to a single occurrence aren't imported".to_string())
+        );
+        assert_eq!(
+            entry(&["DTSTART:20240510T100000Z", "STATUS:CANCELLED"], None),
+            Err("is cancelled".to_string())
+        );
+    }
+
+    #[test]
+    fn rules_geo_and_text_are_read() {
+        let entry = entry(
+            &[
+                "DTSTART;TZID=Europe/Berlin:20240503T100000",
+                "RRULE:FREQ=WEEKLY;BYDAY=FR",
+                "EXDATE;TZID=Europe/Berlin:20240510T100000",
+                r"SUMMARY:Tacos\, burritos\; more",
+                "GEO:52.52;13.40",
+            ],
+            None,
+        )
+        .unwrap();
+        assert_eq!(entry.event.name, "Tacos, burritos; more");
+        assert!(entry.geo);
+        assert_eq!((entry.event.cord_x, entry.event.cord_y), (13.40, 52.52));
+        assert_eq!(
+            entry.event.repeat_schedule.unwrap().to_string(),
+            "RRULE:FREQ=WEEKLY;BYDAY=FR\nEXDATE:20240510T080000Z"
+        );
+    }
+
+    #[test]
+    fn uuids_are_stable_per_vendor() {
+        let entry = entry(&["DTSTART:20240503T100000Z"], None).unwrap();
+        assert_eq!(entry.uuid("vendor"), entry.uuid("vendor"));
+        assert_ne!(entry.uuid("vendor"), entry.uuid("other"));
+        let ours = CalendarEntry {
+            uid: "0A1B2C3D4E5F60718293A4B5C6D7E8F9@food-truck-finder".to_string(),
+            ..entry
+        };
+        assert_eq!(ours.uuid("vendor"), "0A1B2C3D4E5F60718293A4B5C6D7E8F9");
+    }
+}
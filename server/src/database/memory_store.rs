@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+use crate::database::models::{Event, Item, Menu, OccurrenceOverride, Record, Vendor};
+use crate::database::store::{
+    contains_fragment, page_records, vendor_thing, Listing, Model, PageQuery, Repository, Store,
+    StoreError,
+};
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use serde_json::Value;
+use surrealdb::sql::Thing;
+
+// A store which keeps every table in memory, used to run the API without a SurrealDB server
+// Records are kept as json so one map can hold every table, nothing survives a restart
+#[derive(Default)]
+pub struct MemoryStore {
+    tables: RwLock<HashMap<&'static str, BTreeMap<String, Value>>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+
+    fn read<T: Model>(&self, id: &str) -> Result<Option<T>, StoreError> {
+        let tables = self.tables.read().expect("memory store lock poisoned");
+        match tables.get(T::TABLE).and_then(|table| table.get(id)) {
+            Some(value) => Ok(Some(serde_json::from_value(value.clone())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn read_all<T: Model>(&self) -> Result<Vec<T>, StoreError> {
+        let tables = self.tables.read().expect("memory store lock poisoned");
+        match tables.get(T::TABLE) {
+            Some(table) => Ok(table
+                .values()
+                .map(|value| serde_json::from_value(value.clone()))
+                .collect::<Result<_, _>>()?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn write<T: Model>(&self, record: &T) -> Result<(), StoreError> {
+        let value = serde_json::to_value(record)?;
+        let mut tables = self.tables.write().expect("memory store lock poisoned");
+        tables
+            .entry(T::TABLE)
+            .or_default()
+            .insert(record.uuid().to_string(), value);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: Model> Repository<T> for MemoryStore {
+    async fn get(&self, id: &str) -> Result<Option<T>, StoreError> {
+        self.read(id)
+    }
+
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<T>, StoreError> {
+        let mut records = Vec::new();
+        for id in ids {
+            records.extend(self.read::<T>(id)?);
+        }
+        Ok(records)
+    }
+
+    async fn list(&self) -> Result<Vec<T>, StoreError> {
+        self.read_all()
+    }
+
+    async fn list_by_vendor(&self, vendor_id: &str) -> Result<Vec<T>, StoreError> {
+        let vendor = vendor_thing(vendor_id);
+        Ok(self
+            .read_all::<T>()?
+            .into_iter()
+            .filter(|record| record.vendor().as_ref() == Some(&vendor))
+            .collect())
+    }
+
+    async fn list_by_vendors(&self, vendor_ids: &[String]) -> Result<Vec<T>, StoreError> {
+        let vendors: Vec<Thing> = vendor_ids.iter().map(|id| vendor_thing(id)).collect();
+        Ok(self
+            .read_all::<T>()?
+            .into_iter()
+            .filter(|record| match record.vendor() {
+                Some(vendor) => vendors.contains(&vendor),
+                None => false,
+            })
+            .collect())
+    }
+
+    async fn page(&self, query: &PageQuery) -> Result<Listing<T>, StoreError> {
+        page_records(self.read_all()?, query)
+    }
+
+    async fn containing(
+        &self,
+        fields: &[&'static str],
+        fragments: &[String],
+        limit: usize,
+    ) -> Result<Vec<T>, StoreError> {
+        let mut records = Vec::new();
+        for record in self.read_all::<T>()? {
+            if records.len() == limit {
+                break;
+            }
+            if contains_fragment(&record, fields, fragments)? {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    // The check and the insert happen under one lock so two creates can't both succeed
+    async fn create(&self, record: T) -> Result<Record, StoreError> {
+        let value = serde_json::to_value(&record)?;
+        let mut tables = self.tables.write().expect("memory store lock poisoned");
+        let table = tables.entry(T::TABLE).or_default();
+        if table.contains_key(record.uuid()) {
+            return Err(StoreError::Conflict(format!(
+                "{}:{} already exists",
+                T::TABLE,
+                record.uuid()
+            )));
+        }
+        table.insert(record.uuid().to_string(), value);
+        Ok(Record {
+            id: Thing {
+                tb: T::TABLE.to_string(),
+                id: record.uuid().into(),
+            },
+        })
+    }
+
+    async fn upsert(&self, record: T) -> Result<(), StoreError> {
+        self.write(&record)
+    }
+
+    async fn patch(
+        &self,
+        id: &str,
+        patches: Vec<(String, Value)>,
+    ) -> Result<Option<T>, StoreError> {
+        // Held throughout so a concurrent delete can't be undone by writing the patched record
+        let mut tables = self.tables.write().expect("memory store lock poisoned");
+        let stored = match tables.get_mut(T::TABLE).and_then(|table| table.get_mut(id)) {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+        let mut value = stored.clone();
+        if let Value::Object(fields) = &mut value {
+            for (path, patch) in patches {
+                fields.insert(path.trim_start_matches('/').to_string(), patch);
+            }
+        }
+        // Round tripping through the model rejects patches which don't fit it
+        let record: T = serde_json::from_value(value)?;
+        *stored = serde_json::to_value(&record)?;
+        Ok(Some(record))
+    }
+
+    async fn delete(&self, id: &str) -> Result<Option<T>, StoreError> {
+        let mut tables = self.tables.write().expect("memory store lock poisoned");
+        match tables.get_mut(T::TABLE).and_then(|table| table.remove(id)) {
+            Some(value) => Ok(Some(serde_json::from_value(value)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn items_by_menu(&self, menu_id: &str) -> Result<Option<Vec<Item>>, StoreError> {
+        let menu: Menu = match self.read(menu_id)? {
+            Some(menu) => menu,
+            None => return Ok(None),
+        };
+        let mut items = Vec::new();
+        for item in menu.items.iter() {
+            if let Some(item) = self.read::<Item>(&item.id.to_raw())? {
+                items.push(item);
+            }
+        }
+        Ok(Some(items))
+    }
+
+    async fn vendors_by_email(&self, email: &str) -> Result<Vec<Vendor>, StoreError> {
+        Ok(self
+            .read_all::<Vendor>()?
+            .into_iter()
+            .filter(|vendor| vendor.email == email)
+            .collect())
+    }
+
+    async fn set_override(
+        &self,
+        event_id: &str,
+        date: NaiveDate,
+        over: Option<OccurrenceOverride>,
+    ) -> Result<Option<Event>, StoreError> {
+        // Held throughout so the overrides can't change between reading and writing them
+        let mut tables = self.tables.write().expect("memory store lock poisoned");
+        let stored = match tables
+            .get_mut(Event::TABLE)
+            .and_then(|table| table.get_mut(event_id))
+        {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+        let mut event: Event = serde_json::from_value(stored.clone())?;
+        event.overrides.retain(|existing| existing.date != date);
+        event.overrides.extend(over);
+        event.overrides.sort_by_key(|over| over.date);
+        *stored = serde_json::to_value(&event)?;
+        Ok(Some(event))
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,543 @@
+use std::fmt;
+
+use crate::database::models::ReoccurancePattern;
+use crate::database::recurrence::RecurrenceRule;
+use crate::database::schedule::{parse_datetime, parse_end_in};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use surrealdb::{engine::remote::ws::Client, sql::Thing, Surreal};
+use tracing::{info, warn};
+
+// A set of schema statements applied together, once, in version order
+// Migrations are never edited after release, changes to the schema go in a new migration
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub statements: &'static str,
+    // Changes to existing records which need more than SurrealQL, run after the statements
+    pub rewrite: Option<Rewrite>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rewrite {
+    // Event datetimes in any of the formats parse_datetime accepts are written as RFC 3339
+    EventTimestamps,
+    // Legacy repeat patterns are written as the equivalent RRULE
+    EventRecurrence,
+}
+
+// The tables are schemaless so records can carry extra data, but the fields below are typed and
+//      asserted so records the API can't read are rejected instead of silently stored
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "define tables, fields and indexes",
+        statements: "
+        DEFINE TABLE vendors SCHEMALESS;
+        DEFINE FIELD uuid ON TABLE vendors TYPE string ASSERT $value != '';
+        DEFINE FIELD name ON TABLE vendors TYPE string;
+        DEFINE FIELD description ON TABLE vendors TYPE string;
+        DEFINE FIELD vendor_type ON TABLE vendors TYPE string;
+        DEFINE FIELD email ON TABLE vendors TYPE string;
+        DEFINE FIELD phone_number ON TABLE vendors TYPE string;
+        DEFINE FIELD website ON TABLE vendors TYPE string;
+        DEFINE FIELD events ON TABLE vendors TYPE array<record<events>>;
+        DEFINE FIELD menus ON TABLE vendors TYPE array<record<menus>>;
+        DEFINE FIELD items ON TABLE vendors TYPE array<record<items>>;
+
+        DEFINE TABLE events SCHEMALESS;
+        DEFINE FIELD uuid ON TABLE events TYPE string ASSERT $value != '';
+        DEFINE FIELD name ON TABLE events TYPE string;
+        DEFINE FIELD datetime ON TABLE events TYPE string ASSERT $value != '';
+        DEFINE FIELD location ON TABLE events TYPE string ASSERT $value != '';
+        DEFINE FIELD cord_x ON TABLE events TYPE number ASSERT $value >= -180 AND $value <= 180;
+        DEFINE FIELD cord_y ON TABLE events TYPE number ASSERT $value >= -90 AND $value <= 90;
+        DEFINE FIELD menu ON TABLE events TYPE option<record<menus>>;
+        DEFINE FIELD repeat_end ON TABLE events TYPE string;
+        DEFINE FIELD vendor ON TABLE events TYPE option<record<vendors>>;
+
+        DEFINE TABLE menus SCHEMALESS;
+        DEFINE FIELD uuid ON TABLE menus TYPE string ASSERT $value != '';
+        DEFINE FIELD name ON TABLE menus TYPE string;
+        DEFINE FIELD items ON TABLE menus TYPE array<record<items>>;
+        DEFINE FIELD vendor ON TABLE menus TYPE option<record<vendors>>;
+
+        DEFINE TABLE items SCHEMALESS;
+        DEFINE FIELD uuid ON TABLE items TYPE string ASSERT $value != '';
+        DEFINE FIELD name ON TABLE items TYPE string;
+        DEFINE FIELD description ON TABLE items TYPE string;
+        DEFINE FIELD price ON TABLE items TYPE string;
+        DEFINE FIELD picture ON TABLE items TYPE string;
+        DEFINE FIELD vendor ON TABLE items TYPE option<record<vendors>>;
+
+        DEFINE INDEX events_vendor ON TABLE events COLUMNS vendor;
+        DEFINE INDEX menus_vendor ON TABLE menus COLUMNS vendor;
+        DEFINE INDEX items_vendor ON TABLE items COLUMNS vendor;
+        DEFINE INDEX vendors_email ON TABLE vendors COLUMNS email;
+    ",
+        rewrite: None,
+    },
+    // Events get a time zone and a length, and repeat_end becomes optional instead of holding a
+    //      copy of datetime or an empty string
+    // The defaults fill in timezone and duration_minutes when the rewrite updates each event
+    Migration {
+        version: 2,
+        name: "store event timestamps as RFC 3339 with a time zone and duration",
+        statements: "
+            DEFINE FIELD timezone ON TABLE events TYPE string DEFAULT 'UTC' ASSERT $value != '';
+            DEFINE FIELD duration_minutes ON TABLE events TYPE int DEFAULT 240
+                ASSERT $value >= 1 AND $value <= 1440;
+            DEFINE FIELD repeat_end ON TABLE events TYPE option<string>;
+        ",
+        rewrite: Some(Rewrite::EventTimestamps),
+    },
+    // Changes to single occurrences are stored on their event, see OccurrenceOverride
+    Migration {
+        version: 3,
+        name: "store occurrence overrides on events",
+        statements: "
+            DEFINE FIELD overrides ON TABLE events TYPE array<object> DEFAULT [];
+            UPDATE events SET overrides = [] WHERE overrides = NONE;
+        ",
+        rewrite: None,
+    },
+    // Repeats are stored as RRULE text, see RecurrenceRule
+    Migration {
+        version: 4,
+        name: "store event recurrence as RRULE strings",
+        statements: "
+            DEFINE FIELD repeat_schedule ON TABLE events TYPE option<string>;
+        ",
+        rewrite: Some(Rewrite::EventRecurrence),
+    },
+];
+
+// Where the applied versions are recorded, one record per migration
+const DEFINE_MIGRATIONS_TABLE: &str = "
+    DEFINE TABLE schema_migrations SCHEMAFULL;
+    DEFINE FIELD version ON TABLE schema_migrations TYPE int;
+    DEFINE FIELD name ON TABLE schema_migrations TYPE string;
+    DEFINE FIELD applied_at ON TABLE schema_migrations TYPE datetime;
+";
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Database(Box<surrealdb::Error>),
+    // The database was migrated by a newer build, running against it could corrupt its data
+    NewerSchema { database: i64, supported: i64 },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Database(err) => write!(f, "{err}"),
+            MigrationError::NewerSchema {
+                database,
+                supported,
+            } => write!(
+                f,
+                "The database schema is at version {database} but this build only supports up to version {supported}, upgrade the server"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<surrealdb::Error> for MigrationError {
+    fn from(err: surrealdb::Error) -> Self {
+        MigrationError::Database(Box::new(err))
+    }
+}
+
+const RECORD_VERSION: &str = "CREATE type::thing('schema_migrations', $version) SET version = $version, name = $name, applied_at = time::now()";
+
+#[derive(Deserialize)]
+struct AppliedVersion {
+    version: i64,
+}
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or(0)
+}
+
+// The highest migration recorded in the database, 0 for a fresh database
+pub async fn current_version(db: &Surreal<Client>) -> Result<i64, MigrationError> {
+    let mut response = db
+        .query("SELECT version FROM schema_migrations ORDER BY version DESC LIMIT 1")
+        .await?;
+    let applied: Option<AppliedVersion> = response.take(0)?;
+    Ok(applied.map_or(0, |applied| applied.version))
+}
+
+// Brings the schema up to the latest version, each migration and its record are applied in one
+//      transaction so a failed migration leaves nothing behind and is retried on the next start
+// Rewrites read the records back so they can't share the transaction, they are safe to run
+//      again if the server stops before the version is recorded
+// Two servers migrating at once is safe, the second fails to record the version and retries
+pub async fn migrate(db: &Surreal<Client>) -> Result<i64, MigrationError> {
+    db.query(DEFINE_MIGRATIONS_TABLE).await?.check()?;
+
+    let current = current_version(db).await?;
+    let latest = latest_version();
+    if current > latest {
+        return Err(MigrationError::NewerSchema {
+            database: current,
+            supported: latest,
+        });
+    }
+
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > current)
+    {
+        info!(
+            version = migration.version,
+            "Applying migration {}", migration.name
+        );
+        if migration.rewrite.is_none() {
+            db.query("BEGIN TRANSACTION")
+                .query(migration.statements)
+                .query(RECORD_VERSION)
+                .query("COMMIT TRANSACTION")
+                .bind(("version", migration.version))
+                .bind(("name", migration.name))
+                .await?
+                .check()?;
+            continue;
+        }
+        db.query("BEGIN TRANSACTION")
+            .query(migration.statements)
+            .query("COMMIT TRANSACTION")
+            .await?
+            .check()?;
+        match migration.rewrite {
+            Some(Rewrite::EventTimestamps) => rewrite_event_timestamps(db).await?,
+            Some(Rewrite::EventRecurrence) => rewrite_event_recurrence(db).await?,
+            None => (),
+        }
+        db.query(RECORD_VERSION)
+            .bind(("version", migration.version))
+            .bind(("name", migration.name))
+            .await?
+            .check()?;
+    }
+    Ok(latest)
+}
+
+// region:      -- Rewrites
+
+#[derive(Deserialize)]
+struct LegacyEvent {
+    id: Thing,
+    datetime: String,
+    repeat_end: Option<String>,
+}
+
+// Legacy datetimes had no zone so they are read as UTC, the same as they were scheduled
+// Events whose datetime can't be parsed could never be scheduled, they are moved to
+//      events_unmigrated, see unmigrate
+async fn rewrite_event_timestamps(db: &Surreal<Client>) -> Result<(), MigrationError> {
+    let mut response = db
+        .query("SELECT id, datetime, repeat_end FROM events")
+        .await?;
+    let events: Vec<LegacyEvent> = response.take(0)?;
+
+    for event in events {
+        let (datetime, repeat_end) = match migrated_timestamps(&event) {
+            Some(timestamps) => timestamps,
+            None => {
+                warn!(
+                    event = %event.id,
+                    datetime = event.datetime,
+                    "Moving event with an unparseable datetime to events_unmigrated"
+                );
+                unmigrate(db, event.id).await?;
+                continue;
+            }
+        };
+        let query = match repeat_end {
+            Some(_) => "UPDATE $id SET datetime = $datetime, repeat_end = $repeat_end",
+            None => "UPDATE $id SET datetime = $datetime, repeat_end = NONE",
+        };
+        db.query(query)
+            .bind(("id", event.id))
+            .bind(("datetime", datetime))
+            .bind(("repeat_end", repeat_end))
+            .await?
+            .check()?;
+    }
+    Ok(())
+}
+
+// The event's datetime and repeat_end as RFC 3339, None if the datetime can't be parsed
+// Unparseable ends were already treated as repeating forever so they are dropped
+fn migrated_timestamps(event: &LegacyEvent) -> Option<(String, Option<String>)> {
+    let datetime = parse_datetime(&event.datetime)?;
+    let repeat_end = event
+        .repeat_end
+        .as_deref()
+        .and_then(|repeat_end| parse_end_in(repeat_end, Tz::UTC))
+        .map(|repeat_end| repeat_end.to_rfc3339());
+    Some((datetime.to_rfc3339(), repeat_end))
+}
+
+#[derive(Deserialize)]
+struct LegacyRecurrence {
+    id: Thing,
+    datetime: String,
+    timezone: String,
+    repeat_schedule: Option<StoredSchedule>,
+}
+
+// Events rewritten by an earlier, interrupted run already hold a rule
+// Anything else can't be converted, it is kept so the event can be moved aside
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredSchedule {
+    Legacy(ReoccurancePattern),
+    Rule(RecurrenceRule),
+    Unreadable(serde_json::Value),
+}
+
+// Patterns which fall back to the day of the first occurrence use the day in the event's zone
+// Events whose pattern or datetime can't be read are moved to events_unmigrated, the same as
+//      the timestamp rewrite does
+async fn rewrite_event_recurrence(db: &Surreal<Client>) -> Result<(), MigrationError> {
+    let mut response = db
+        .query("SELECT id, datetime, timezone, repeat_schedule FROM events")
+        .await?;
+    let events: Vec<LegacyRecurrence> = response.take(0)?;
+
+    for event in events {
+        let rule = match migrated_rule(&event) {
+            Ok(rule) => rule,
+            Err(reason) => {
+                warn!(
+                    event = %event.id,
+                    "Moving event with {reason} to events_unmigrated"
+                );
+                unmigrate(db, event.id).await?;
+                continue;
+            }
+        };
+        let query = match rule {
+            Some(_) => "UPDATE $id SET repeat_schedule = $rule",
+            None => "UPDATE $id SET repeat_schedule = NONE",
+        };
+        db.query(query)
+            .bind(("id", event.id))
+            .bind(("rule", rule))
+            .await?
+            .check()?;
+    }
+    Ok(())
+}
+
+// The event's repeat_schedule as RRULE text, None if it doesn't repeat
+// Err describes what can't be converted
+fn migrated_rule(event: &LegacyRecurrence) -> Result<Option<String>, String> {
+    let timezone = event.timezone.parse::<Tz>().unwrap_or(Tz::UTC);
+    // Every datetime was made RFC 3339 by the timestamp rewrite
+    let first = match parse_datetime(&event.datetime) {
+        Some(datetime) => datetime.with_timezone(&timezone).date_naive(),
+        None => return Err(format!("an unparseable datetime {}", event.datetime)),
+    };
+    let rule = match &event.repeat_schedule {
+        Some(StoredSchedule::Legacy(pattern)) => pattern.to_rule(first),
+        Some(StoredSchedule::Rule(rule)) => Some(rule.clone()),
+        Some(StoredSchedule::Unreadable(pattern)) => {
+            return Err(format!("an unconvertible repeat_schedule {pattern}"))
+        }
+        None => None,
+    };
+    Ok(rule.map(|rule| rule.to_string()))
+}
+
+// Moves an event the rewrites can't convert to events_unmigrated under the same key, rather
+//      than deleting it, so it can be fixed and imported again
+async fn unmigrate(db: &Surreal<Client>, id: Thing) -> Result<(), MigrationError> {
+    db.query("BEGIN TRANSACTION")
+        .query("LET $record = (SELECT * OMIT id FROM ONLY $id)")
+        .query("CREATE type::thing('events_unmigrated', $key) CONTENT $record")
+        .query("DELETE $id")
+        .query("COMMIT TRANSACTION")
+        .bind(("key", id.id.to_raw()))
+        .bind(("id", id))
+        .await?
+        .check()?;
+    Ok(())
+}
+
+// endregion:   -- Rewrites
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn legacy_event(datetime: &str, repeat_end: Option<&str>) -> LegacyEvent {
+        LegacyEvent {
+            id: Thing::from(("events", "legacy")),
+            datetime: datetime.to_string(),
+            repeat_end: repeat_end.map(str::to_string),
+        }
+    }
+
+    fn legacy_recurrence(timezone: &str, repeat_schedule: serde_json::Value) -> LegacyRecurrence {
+        LegacyRecurrence {
+            id: Thing::from(("events", "legacy")),
+            // The 4th in UTC but still the 3rd in Detroit
+            datetime: "2024-05-04T02:00:00+00:00".to_string(),
+            timezone: timezone.to_string(),
+            repeat_schedule: serde_json::from_value(repeat_schedule).unwrap(),
+        }
+    }
+
+    #[test]
+    fn versions_are_unique_and_increasing() {
+        for pair in MIGRATIONS.windows(2) {
+            assert!(
+                pair[0].version < pair[1].version,
+                "{} is not before {}",
+                pair[0].version,
+                pair[1].version
+            );
+        }
+        assert_eq!(MIGRATIONS[0].version, 1);
+    }
+
+    #[test]
+    fn latest_version_is_the_last_migration() {
+        assert_eq!(latest_version(), MIGRATIONS.last().unwrap().version);
+        assert_eq!(latest_version(), 4);
+    }
+
+    #[test]
+    fn migration_statements_parse() {
+        for migration in MIGRATIONS {
+            if let Err(err) = surrealdb::sql::parse(migration.statements) {
+                panic!("Migration {} doesn't parse: {err}", migration.version);
+            }
+        }
+    }
+
+    #[test]
+    fn stored_schedules_are_read_as_what_they_hold() {
+        let read = |value: serde_json::Value| serde_json::from_value::<StoredSchedule>(value);
+        assert!(matches!(
+            read(json!("Daily")),
+            Ok(StoredSchedule::Legacy(ReoccurancePattern::Daily))
+        ));
+        assert!(matches!(
+            read(json!({ "Weekly": { "days": ["FRIDAY"], "spacing": 2 } })),
+            Ok(StoredSchedule::Legacy(ReoccurancePattern::Weekly { .. }))
+        ));
+        assert!(matches!(
+            read(json!("FREQ=WEEKLY;BYDAY=FR")),
+            Ok(StoredSchedule::Rule(_))
+        ));
+        assert!(matches!(
+            read(json!("every other friday")),
+            Ok(StoredSchedule::Unreadable(_))
+        ));
+        assert!(matches!(
+            read(json!({ "Weekly": { "days": ["FUNDAY"], "spacing": 1 } })),
+            Ok(StoredSchedule::Unreadable(_))
+        ));
+    }
+
+    #[test]
+    fn legacy_datetimes_become_rfc3339() {
+        for (legacy, migrated) in [
+            ("2024-05-03T10:00:00+02:00", "2024-05-03T10:00:00+02:00"),
+            ("2024-05-03T10:00:00Z", "2024-05-03T10:00:00+00:00"),
+            ("2024-05-03T10:00:00", "2024-05-03T10:00:00+00:00"),
+            ("2024-05-03 10:00:00", "2024-05-03T10:00:00+00:00"),
+            ("2024-05-03T10:00", "2024-05-03T10:00:00+00:00"),
+            ("2024-05-03 10:00", "2024-05-03T10:00:00+00:00"),
+            ("2024-05-03", "2024-05-03T00:00:00+00:00"),
+            (" 2024-05-03 10:00 ", "2024-05-03T10:00:00+00:00"),
+        ] {
+            let (datetime, repeat_end) = migrated_timestamps(&legacy_event(legacy, None))
+                .unwrap_or_else(|| panic!("{legacy} wasn't migrated"));
+            assert_eq!(datetime, migrated, "{legacy}");
+            assert_eq!(repeat_end, None);
+        }
+        assert_eq!(
+            migrated_timestamps(&legacy_event("next friday", None)),
+            None
+        );
+        assert_eq!(migrated_timestamps(&legacy_event("", None)), None);
+    }
+
+    #[test]
+    fn legacy_ends_cover_their_whole_day_and_unparseable_ones_are_dropped() {
+        let migrated = |repeat_end: &str| {
+            migrated_timestamps(&legacy_event("2024-05-03 10:00", Some(repeat_end)))
+                .unwrap()
+                .1
+        };
+        assert_eq!(
+            migrated("2024-12-01"),
+            Some("2024-12-01T23:59:59+00:00".to_string())
+        );
+        assert_eq!(
+            migrated("2024-12-01 18:00"),
+            Some("2024-12-01T18:00:00+00:00".to_string())
+        );
+        // Legacy records held an empty string or a copy of datetime when they didn't end
+        assert_eq!(migrated(""), None);
+        assert_eq!(migrated("whenever"), None);
+    }
+
+    #[test]
+    fn legacy_patterns_become_rules_on_the_day_in_the_event_zone() {
+        // Monthly patterns without a day repeat on the day of the first occurrence
+        let monthly = json!({ "Monthly": { "day_of_month": 0, "spacing": 1 } });
+        assert_eq!(
+            migrated_rule(&legacy_recurrence("America/Detroit", monthly.clone())),
+            Ok(Some("RRULE:FREQ=MONTHLY;BYMONTHDAY=3".to_string()))
+        );
+        assert_eq!(
+            migrated_rule(&legacy_recurrence("UTC", monthly)),
+            Ok(Some("RRULE:FREQ=MONTHLY;BYMONTHDAY=4".to_string()))
+        );
+        assert_eq!(
+            migrated_rule(&legacy_recurrence(
+                "UTC",
+                json!({ "Weekly": { "days": ["FRIDAY", "MONDAY"], "spacing": 2 } })
+            )),
+            Ok(Some("RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=FR,MO".to_string()))
+        );
+        assert_eq!(
+            migrated_rule(&legacy_recurrence("UTC", json!("Daily"))),
+            Ok(Some("RRULE:FREQ=DAILY".to_string()))
+        );
+        assert_eq!(
+            migrated_rule(&legacy_recurrence("UTC", json!("OneTime"))),
+            Ok(None)
+        );
+        assert_eq!(
+            migrated_rule(&legacy_recurrence("UTC", json!(null))),
+            Ok(None)
+        );
+        // Rules written by an interrupted run are kept as they are
+        assert_eq!(
+            migrated_rule(&legacy_recurrence(
+                "UTC",
+                json!("FREQ=MONTHLY;BYMONTHDAY=3")
+            )),
+            Ok(Some("RRULE:FREQ=MONTHLY;BYMONTHDAY=3".to_string()))
+        );
+        assert!(migrated_rule(&legacy_recurrence("UTC", json!("every other friday"))).is_err());
+
+        let mut unparseable = legacy_recurrence("UTC", json!("Daily"));
+        unparseable.datetime = "soon".to_string();
+        assert!(migrated_rule(&unparseable).is_err());
+    }
+}
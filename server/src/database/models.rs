@@ -1,9 +1,14 @@
+use crate::database::recurrence::RecurrenceRule;
+use crate::server::monitoring::GEOCODING_REQUESTS_TOTAL;
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use chrono_tz::Tz;
 use geocoding::{Forward, Openstreetmap, Point};
-use log::warn;
+use metrics::counter;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::fmt;
 use surrealdb::sql::Thing;
+use tracing::warn;
 use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -140,22 +145,69 @@ impl Default for Vendor {
     }
 }
 
+// Occurrences given neither an end nor a duration are assumed to last this long
+pub const DEFAULT_DURATION_MINUTES: u32 = 240;
+// Longer occurrences would run into the next one of a daily event
+pub const MAX_DURATION_MINUTES: u32 = 24 * 60;
+
+// datetime is the start of the first occurrence and each occurrence lasts duration_minutes
+// Repeats follow the wall clock of timezone, so a weekly 10:00 event stays at 10:00 across
+//      daylight saving changes, datetime and repeat_end are kept in the zone's offset
+// repeat_schedule is an RRULE with its EXDATEs, None happens once
+// repeat_end is the latest an occurrence can start, None repeats forever
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Event {
     pub uuid: Cow<'static, str>,
     pub name: Cow<'static, str>,
-    pub datetime: Cow<'static, str>,
+    pub datetime: DateTime<FixedOffset>,
+    pub timezone: Tz,
+    pub duration_minutes: u32,
     pub location: Cow<'static, str>,
     pub cord_x: f64,
     pub cord_y: f64,
     pub menu: Option<Thing>,
-    pub repeat_schedule: Cow<'static, ReoccurancePattern>,
-    pub repeat_end: Cow<'static, str>,
+    pub repeat_schedule: Option<RecurrenceRule>,
+    pub repeat_end: Option<DateTime<FixedOffset>>,
+    pub overrides: Vec<OccurrenceOverride>,
     pub vendor: Option<Thing>,
 }
 
+// Changes to a single occurrence of an event, such as skipping one week of a weekly event
+// Identified by the date the occurrence was scheduled on in the event's time zone, which stays
+//      the same when the occurrence is moved to another time
+//      cancelled       -> The occurrence doesn't happen
+//      start           -> When it happens instead, it still lasts the event's duration
+//      location        -> Where it happens instead, always given along with cord_x and cord_y
+//      note            -> Shown with the occurrence, such as why it moved
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct OccurrenceOverride {
+    pub date: NaiveDate,
+    pub cancelled: bool,
+    pub start: Option<DateTime<FixedOffset>>,
+    pub location: Option<Cow<'static, str>>,
+    pub cord_x: Option<f64>,
+    pub cord_y: Option<f64>,
+    pub note: Option<Cow<'static, str>>,
+}
+
+// A field of a request body which can't be stored, answered with a 422
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidField {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl InvalidField {
+    pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+        InvalidField {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
 impl Event {
-    pub fn new(datetime: String, location: String, vendor: Option<Thing>) -> Self {
+    pub fn new(datetime: DateTime<FixedOffset>, location: String, vendor: Option<Thing>) -> Self {
         Event {
             uuid: Cow::Owned(String::from(
                 Uuid::new_v4()
@@ -163,13 +215,16 @@ impl Event {
                     .encode_upper(&mut uuid::Uuid::encode_buffer()),
             )),
             name: "".into(),
-            datetime: datetime.clone().into(),
+            datetime,
+            timezone: Tz::UTC,
+            duration_minutes: DEFAULT_DURATION_MINUTES,
             location: location.into(),
             cord_x: 0.0,
             cord_y: 0.0,
             menu: None,
-            repeat_schedule: ReoccurancePattern::OneTime.into(),
-            repeat_end: datetime.into(),
+            repeat_schedule: None,
+            repeat_end: None,
+            overrides: Vec::new(),
             vendor: vendor.into(),
         }
     }
@@ -187,34 +242,408 @@ impl Event {
         self.cord_y = y;
         self
     }
+
+    // Moves the event into the zone without changing when it happens
+    pub fn with_timezone(mut self, timezone: Tz) -> Self {
+        self.timezone = timezone;
+        self.datetime = in_zone(self.datetime, timezone);
+        self.repeat_end = self
+            .repeat_end
+            .map(|repeat_end| in_zone(repeat_end, timezone));
+        self
+    }
+
+    // The override for the occurrence scheduled on the date, if it has one
+    pub fn override_on(&self, date: NaiveDate) -> Option<&OccurrenceOverride> {
+        self.overrides.iter().find(|over| over.date == date)
+    }
+
+    // Applies the timing fields of a request body
+    //      datetime            -> RFC 3339 start of the first occurrence
+    //      timezone            -> IANA name such as America/Detroit
+    //      end                 -> RFC 3339 end of the first occurrence, or
+    //      duration_minutes    -> length of every occurrence
+    //      repeat_schedule     -> RRULE such as FREQ=MONTHLY;BYDAY=-1SA, or a legacy pattern
+    //                             object, null for a single occurrence
+    //      repeat_end          -> RFC 3339, null or "" to repeat forever
+    // Fields missing from the body keep their current value so creating and patching are checked
+    //      the same way, nothing is changed unless every field is valid
+    pub fn apply_timing(&mut self, value: &serde_json::Value) -> Result<(), Vec<InvalidField>> {
+        let mut invalid = Vec::new();
+
+        let timezone = match value.get("timezone") {
+            Some(timezone) => match timezone.as_str().and_then(|name| name.parse::<Tz>().ok()) {
+                Some(timezone) => timezone,
+                None => {
+                    invalid.push(InvalidField::new(
+                        "timezone",
+                        "must be an IANA time zone such as America/Detroit",
+                    ));
+                    self.timezone
+                }
+            },
+            None => self.timezone,
+        };
+        let datetime = match value.get("datetime") {
+            Some(datetime) => match rfc3339(datetime) {
+                Some(datetime) => datetime,
+                None => {
+                    invalid.push(InvalidField::new(
+                        "datetime",
+                        "must be an RFC 3339 datetime such as 2024-05-01T17:00:00-04:00",
+                    ));
+                    self.datetime
+                }
+            },
+            None => self.datetime,
+        };
+        let duration_minutes = match (value.get("end"), value.get("duration_minutes")) {
+            (Some(_), Some(_)) => {
+                invalid.push(InvalidField::new(
+                    "end",
+                    "can't be given along with duration_minutes",
+                ));
+                self.duration_minutes
+            }
+            (Some(end), None) => match rfc3339(end) {
+                Some(end) => match u32::try_from((end - datetime).num_minutes()) {
+                    Ok(minutes) if (1..=MAX_DURATION_MINUTES).contains(&minutes) => minutes,
+                    _ => {
+                        invalid.push(InvalidField::new(
+                            "end",
+                            format!(
+                                "must be after datetime and at most {MAX_DURATION_MINUTES} minutes later"
+                            ),
+                        ));
+                        self.duration_minutes
+                    }
+                },
+                None => {
+                    invalid.push(InvalidField::new("end", "must be an RFC 3339 datetime"));
+                    self.duration_minutes
+                }
+            },
+            (None, Some(duration_minutes)) => match duration_minutes.as_u64() {
+                Some(minutes) if (1..=u64::from(MAX_DURATION_MINUTES)).contains(&minutes) => {
+                    minutes as u32
+                }
+                _ => {
+                    invalid.push(InvalidField::new(
+                        "duration_minutes",
+                        format!("must be a whole number between 1 and {MAX_DURATION_MINUTES}"),
+                    ));
+                    self.duration_minutes
+                }
+            },
+            (None, None) => self.duration_minutes,
+        };
+        let repeat_end = match value.get("repeat_end") {
+            Some(serde_json::Value::Null) => None,
+            Some(serde_json::Value::String(repeat_end)) if repeat_end.is_empty() => None,
+            Some(repeat_end) => match rfc3339(repeat_end) {
+                Some(repeat_end) => Some(repeat_end),
+                None => {
+                    invalid.push(InvalidField::new(
+                        "repeat_end",
+                        "must be an RFC 3339 datetime, or null to repeat forever",
+                    ));
+                    self.repeat_end
+                }
+            },
+            None => self.repeat_end,
+        };
+        // Legacy patterns fall back to the day of the first occurrence, so they're converted
+        //      once the new datetime and timezone are known
+        let repeat_schedule = match value.get("repeat_schedule") {
+            Some(serde_json::Value::Null) => None,
+            Some(serde_json::Value::String(rule)) => match rule.parse::<RecurrenceRule>() {
+                Ok(rule) => Some(rule),
+                Err(err) => {
+                    invalid.push(InvalidField::new(
+                        "repeat_schedule",
+                        format!("is not an RRULE: {err}"),
+                    ));
+                    self.repeat_schedule.clone()
+                }
+            },
+            Some(pattern @ serde_json::Value::Object(_)) => {
+                match ReoccurancePattern::parse(pattern) {
+                    Ok(pattern) => {
+                        let first = datetime.with_timezone(&timezone).date_naive();
+                        pattern.to_rule(first)
+                    }
+                    Err(err) => {
+                        invalid.push(InvalidField::new(
+                            "repeat_schedule",
+                            format!("is not a recurrence pattern: {err}"),
+                        ));
+                        self.repeat_schedule.clone()
+                    }
+                }
+            }
+            Some(_) => {
+                invalid.push(InvalidField::new(
+                    "repeat_schedule",
+                    "must be an RRULE such as FREQ=WEEKLY;BYDAY=FR, or null to happen once",
+                ));
+                self.repeat_schedule.clone()
+            }
+            None => self.repeat_schedule.clone(),
+        };
+        if matches!(repeat_end, Some(repeat_end) if repeat_end < datetime) {
+            invalid.push(InvalidField::new(
+                "repeat_end",
+                "must not be before datetime",
+            ));
+        }
+
+        if !invalid.is_empty() {
+            return Err(invalid);
+        }
+        self.timezone = timezone;
+        self.datetime = in_zone(datetime, timezone);
+        self.duration_minutes = duration_minutes;
+        self.repeat_schedule = repeat_schedule;
+        self.repeat_end = repeat_end.map(|repeat_end| in_zone(repeat_end, timezone));
+        Ok(())
+    }
+
+    // Applies the location fields of a request body
+    //      location    -> Non empty address
+    //      cord_x      -> Longitude, given along with cord_y
+    //      cord_y      -> Latitude, given along with cord_x
+    // Returns the address to geocode when the location changed without coordinates, the event's
+    //      coordinates are cleared until then so it isn't shown at its old location
+    pub fn apply_location(
+        &mut self,
+        value: &serde_json::Value,
+    ) -> Result<Option<String>, Vec<InvalidField>> {
+        let mut invalid = Vec::new();
+        let location = match value.get("location") {
+            Some(location) => match location.as_str() {
+                Some(location) if !location.is_empty() => Some(location.to_owned()),
+                _ => {
+                    invalid.push(InvalidField::new("location", "must be a non empty string"));
+                    None
+                }
+            },
+            None => None,
+        };
+        let cords = cords(value, &mut invalid);
+
+        if !invalid.is_empty() {
+            return Err(invalid);
+        }
+        if let Some(location) = &location {
+            self.location = location.clone().into();
+        }
+        match cords {
+            Some((cord_x, cord_y)) => {
+                self.cord_x = cord_x;
+                self.cord_y = cord_y;
+                Ok(None)
+            }
+            None if location.is_some() => {
+                self.cord_x = 0.0;
+                self.cord_y = 0.0;
+                Ok(location)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl OccurrenceOverride {
+    pub fn new(date: NaiveDate) -> Self {
+        OccurrenceOverride {
+            date,
+            ..Default::default()
+        }
+    }
+
+    // An override which changes nothing can be dropped
+    pub fn is_empty(&self) -> bool {
+        !self.cancelled && self.start.is_none() && self.location.is_none() && self.note.is_none()
+    }
+
+    // Applies the fields of a request body, null clears a field and missing fields are kept
+    // A new start is kept in the offset of the event's zone like the event's own datetime
+    pub fn apply(
+        &mut self,
+        value: &serde_json::Value,
+        timezone: Tz,
+    ) -> Result<(), Vec<InvalidField>> {
+        let mut invalid = Vec::new();
+        let mut patched = self.clone();
+
+        if let Some(cancelled) = value.get("cancelled") {
+            match cancelled.as_bool() {
+                Some(cancelled) => patched.cancelled = cancelled,
+                None => invalid.push(InvalidField::new("cancelled", "must be true or false")),
+            }
+        }
+        match value.get("start") {
+            Some(serde_json::Value::Null) => patched.start = None,
+            Some(start) => match rfc3339(start) {
+                Some(start) => patched.start = Some(in_zone(start, timezone)),
+                None => invalid.push(InvalidField::new(
+                    "start",
+                    "must be an RFC 3339 datetime, or null to keep the scheduled start",
+                )),
+            },
+            None => (),
+        }
+        match value.get("location") {
+            Some(serde_json::Value::Null) => {
+                patched.location = None;
+                patched.cord_x = None;
+                patched.cord_y = None;
+            }
+            Some(location) => match location.as_str() {
+                Some(location) if !location.is_empty() => {
+                    patched.location = Some(location.to_owned().into())
+                }
+                _ => invalid.push(InvalidField::new(
+                    "location",
+                    "must be a non empty string, or null to keep the event's location",
+                )),
+            },
+            None => (),
+        }
+        if let Some(cord_x) = coordinate(value, "cord_x", 180.0, &mut invalid) {
+            patched.cord_x = cord_x;
+        }
+        if let Some(cord_y) = coordinate(value, "cord_y", 90.0, &mut invalid) {
+            patched.cord_y = cord_y;
+        }
+        match value.get("note") {
+            Some(serde_json::Value::Null) => patched.note = None,
+            Some(note) => match note.as_str() {
+                Some("") => patched.note = None,
+                Some(note) => patched.note = Some(note.to_owned().into()),
+                None => invalid.push(InvalidField::new("note", "must be a string or null")),
+            },
+            None => (),
+        }
+
+        // A moved occurrence with the event's coordinates would show up in the wrong place
+        let relocated = [
+            patched.location.is_some(),
+            patched.cord_x.is_some(),
+            patched.cord_y.is_some(),
+        ];
+        if invalid.is_empty() && relocated.contains(&true) && relocated.contains(&false) {
+            invalid.push(InvalidField::new(
+                "location",
+                "must be given along with cord_x and cord_y",
+            ));
+        }
+
+        if !invalid.is_empty() {
+            return Err(invalid);
+        }
+        *self = patched;
+        Ok(())
+    }
+}
+
+// A coordinate of a request body between -limit and limit
+// None when it is missing or invalid, Some(None) when it is null
+fn coordinate(
+    value: &serde_json::Value,
+    field: &'static str,
+    limit: f64,
+    invalid: &mut Vec<InvalidField>,
+) -> Option<Option<f64>> {
+    match value.get(field) {
+        Some(serde_json::Value::Null) => Some(None),
+        Some(cord) => match cord.as_f64() {
+            Some(cord) if (-limit..=limit).contains(&cord) => Some(Some(cord)),
+            _ => {
+                invalid.push(InvalidField::new(
+                    field,
+                    format!("must be a number between -{limit} and {limit}"),
+                ));
+                None
+            }
+        },
+        None => None,
+    }
+}
+
+// cord_x and cord_y, which are only valid given together
+fn cords(value: &serde_json::Value, invalid: &mut Vec<InvalidField>) -> Option<(f64, f64)> {
+    match (
+        coordinate(value, "cord_x", 180.0, invalid),
+        coordinate(value, "cord_y", 90.0, invalid),
+    ) {
+        (Some(Some(cord_x)), Some(Some(cord_y))) => Some((cord_x, cord_y)),
+        (None, None) => None,
+        _ => {
+            invalid.push(InvalidField::new(
+                "cord_x",
+                "must be given along with cord_y, both numbers",
+            ));
+            None
+        }
+    }
+}
+
+fn rfc3339(value: &serde_json::Value) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value.as_str()?).ok()
+}
+
+// The same instant written with the offset the zone has at that time
+fn in_zone(datetime: DateTime<FixedOffset>, timezone: Tz) -> DateTime<FixedOffset> {
+    datetime.with_timezone(&timezone).fixed_offset()
 }
 
 impl fmt::Display for Event {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "UUID: {}\nName: {}\nDateTime: {}\nLocation: {}\nMenu: {:?}\nRepeats: {}\nEnds: {}\nVendor: {}", self.uuid, self.name, self.datetime, self.location, self.menu, self.repeat_schedule, self.repeat_end, match &self.vendor {
+        write!(f, "UUID: {}\nName: {}\nDateTime: {}\nTime Zone: {}\nDuration: {} minutes\nLocation: {}\nMenu: {:?}\nRepeats: {}\nEnds: {}\nVendor: {}", self.uuid, self.name, self.datetime.to_rfc3339(), self.timezone, self.duration_minutes, self.location, self.menu, match &self.repeat_schedule {
+        Some(rule) => rule.to_string(),
+        None => "Once".into(),
+        }, match &self.repeat_end {
+        Some(repeat_end) => repeat_end.to_rfc3339(),
+        None => "Never".into(),
+        }, match &self.vendor {
         Some(thing) => thing.id.to_string(),
         None => "".into(),
         })
     }
 }
 
-impl From<serde_json::Value> for Event {
-    fn from(value: serde_json::Value) -> Self {
-        let datetime = match value.get("datetime") {
-            Some(datetime) => match datetime.as_str() {
-                Some(datetime) => datetime.to_owned(),
-                None => return Event::default(),
-            },
-            None => return Event::default(),
-        };
-        let location = match value.get("location") {
-            Some(location) => match location.as_str() {
-                Some(location) => location.to_owned(),
-                None => return Event::default(),
-            },
-            None => return Event::default(),
+// Unlike the other models an event can't fall back to defaults, every problem with the body is
+//      returned so it can be answered with a 422
+// Events without cord_x and cord_y are at 0, 0 until their location is geocoded
+impl TryFrom<serde_json::Value> for Event {
+    type Error = Vec<InvalidField>;
+
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        let mut invalid = Vec::new();
+        if value.get("datetime").is_none() {
+            invalid.push(InvalidField::new("datetime", "is required"));
+        }
+        let location = match value.get("location").and_then(|location| location.as_str()) {
+            Some(location) if !location.is_empty() => location.to_owned(),
+            _ => {
+                invalid.push(InvalidField::new(
+                    "location",
+                    "is required and must be a non empty string",
+                ));
+                String::new()
+            }
         };
-        let mut event = Event::new(datetime, location, None);
+        let mut event = Event::new(Event::default().datetime, location, None);
+        if let Some((cord_x, cord_y)) = cords(&value, &mut invalid) {
+            event = event.with_cords(cord_x, cord_y);
+        }
+        if let Err(timing) = event.apply_timing(&value) {
+            invalid.extend(timing);
+        }
+        if !invalid.is_empty() {
+            return Err(invalid);
+        }
 
         if let Some(vendor_id) = value.get("vendor_id") {
             event.vendor = Some(Thing {
@@ -237,16 +666,7 @@ impl From<serde_json::Value> for Event {
                 None => None,
             };
         }
-        if let Some(repeat_schedule) = value.get("repeat_schedule") {
-            event.repeat_schedule = ReoccurancePattern::from(repeat_schedule.to_owned()).into()
-        }
-        if let Some(repeat_end) = value.get("repeat_end") {
-            match repeat_end.as_str() {
-                Some(repeat_end) => event.repeat_end = repeat_end.to_owned().into(),
-                None => event.repeat_end = event.datetime.clone().to_owned().into(),
-            };
-        }
-        event
+        Ok(event)
     }
 }
 
@@ -255,13 +675,16 @@ impl Default for Event {
         Event {
             uuid: "".into(),
             name: "".into(),
-            datetime: "".into(),
+            datetime: DateTime::UNIX_EPOCH.fixed_offset(),
+            timezone: Tz::UTC,
+            duration_minutes: DEFAULT_DURATION_MINUTES,
             location: "".into(),
             cord_x: 0.0.into(),
             cord_y: 0.0.into(),
             menu: None,
-            repeat_schedule: ReoccurancePattern::None.into(),
-            repeat_end: "".into(),
+            repeat_schedule: None,
+            repeat_end: None,
+            overrides: Vec::new(),
             vendor: None,
         }
     }
@@ -506,7 +929,8 @@ impl From<serde_json::Value> for Month {
         let month = month.to_uppercase();
         match month.as_str() {
             "JANUARY" => Month::January,
-            "FEBUARY" => Month::February,
+            // FEBUARY was accepted for a long time so it still is
+            "FEBRUARY" | "FEBUARY" => Month::February,
             "MARCH" => Month::March,
             "APRIL" => Month::April,
             "MAY" => Month::May,
@@ -545,101 +969,84 @@ impl Into<Cow<'static, ReoccurancePattern>> for ReoccurancePattern {
     }
 }
 
+// Malformed patterns fall back to None, see ReoccurancePattern::parse for the reason
 impl From<serde_json::Value> for ReoccurancePattern {
     fn from(json: serde_json::Value) -> Self {
+        match ReoccurancePattern::parse(&json) {
+            Ok(pattern) => pattern,
+            Err(err) => {
+                warn!("Unable to read recurrence pattern: {err}");
+                ReoccurancePattern::None
+            }
+        }
+    }
+}
+
+impl ReoccurancePattern {
+    // Reads a pattern as the API took it, {"pattern": "Weekly", "days": [...], "spacing": 1}
+    // Returns why the pattern can't be read so the request can be answered with a 422
+    pub fn parse(json: &serde_json::Value) -> Result<Self, String> {
         let pattern = match json.get("pattern") {
-            Some(pattern) => pattern,
-            None => return ReoccurancePattern::None,
-        };
-        let pattern = match pattern.as_str() {
-            Some(pattern) => pattern,
-            None => return ReoccurancePattern::None,
+            Some(pattern) => match pattern.as_str() {
+                Some(pattern) => pattern,
+                None => return Err("pattern must be a string".to_string()),
+            },
+            None => return Err("pattern is required".to_string()),
         };
         match pattern {
-            "OneTime" => ReoccurancePattern::OneTime,
-            "Daily" => ReoccurancePattern::Daily,
+            "None" => Ok(ReoccurancePattern::None),
+            "OneTime" => Ok(ReoccurancePattern::OneTime),
+            "Daily" => Ok(ReoccurancePattern::Daily),
             "Weekly" => {
-                let days = match json.get("days") {
-                    Some(days) => match days.as_array() {
-                        Some(days) => days.iter().map(|day| day.to_owned().into()).collect(),
-                        None => return ReoccurancePattern::None,
-                    },
-                    None => return ReoccurancePattern::None,
+                let days = match json.get("days").and_then(|days| days.as_array()) {
+                    Some(days) => days,
+                    None => return Err("days must be a list of weekdays".to_string()),
                 };
-                let spacing: u32 = match json.get("spacing") {
-                    Some(spacing) => match spacing.as_u64() {
-                        Some(spacing) => match spacing.try_into() {
-                            Ok(spacing) => spacing,
-                            Err(err) => {
-                                println!("Unable to convert u64 to u32: {err:?}");
-                                return ReoccurancePattern::None;
-                            }
-                        },
-                        None => return ReoccurancePattern::None,
-                    },
-                    None => return ReoccurancePattern::None,
-                };
-                ReoccurancePattern::Weekly { days, spacing }
-            }
-            "Monthly" => {
-                let day_of_month: u32 = match json.get("day_of_month") {
-                    Some(day_of_month) => match day_of_month.as_u64() {
-                        Some(day_of_month) => match day_of_month.try_into() {
-                            Ok(day_of_month) => day_of_month,
-                            Err(err) => {
-                                println!("Unable to convert u64 to u32: {err:?}");
-                                return ReoccurancePattern::None;
-                            }
-                        },
-                        None => return ReoccurancePattern::None,
-                    },
-                    None => return ReoccurancePattern::None,
-                };
-                let spacing: u32 = match json.get("spacing") {
-                    Some(spacing) => match spacing.as_u64() {
-                        Some(spacing) => match spacing.try_into() {
-                            Ok(spacing) => spacing,
-                            Err(err) => {
-                                println!("Unable to convert u64 to u32: {err:?}");
-                                return ReoccurancePattern::None;
-                            }
-                        },
-                        None => return ReoccurancePattern::None,
-                    },
-                    None => return ReoccurancePattern::None,
-                };
-                ReoccurancePattern::Monthly {
-                    day_of_month,
-                    spacing,
+                let mut weekdays = Vec::new();
+                for day in days {
+                    match Day::from(day.to_owned()) {
+                        Day::NONE => return Err(format!("{day} is not a weekday")),
+                        weekday => weekdays.push(weekday),
+                    }
                 }
+                Ok(ReoccurancePattern::Weekly {
+                    days: weekdays,
+                    spacing: pattern_number(json, "spacing")?,
+                })
             }
+            "Monthly" => Ok(ReoccurancePattern::Monthly {
+                day_of_month: pattern_number(json, "day_of_month")?,
+                spacing: pattern_number(json, "spacing")?,
+            }),
             "Yearly" => {
                 let month = match json.get("month") {
-                    Some(month) => month.to_owned().into(),
-                    None => return ReoccurancePattern::None,
-                };
-                let day_of_month: u32 = match json.get("day_of_month") {
-                    Some(day_of_month) => match day_of_month.as_u64() {
-                        Some(day_of_month) => match day_of_month.try_into() {
-                            Ok(day_of_month) => day_of_month,
-                            Err(err) => {
-                                println!("Unable to convert u64 to u32: {err:?}");
-                                return ReoccurancePattern::None;
-                            }
-                        },
-                        None => return ReoccurancePattern::None,
+                    Some(month) => match Month::from(month.to_owned()) {
+                        Month::None => return Err(format!("{month} is not a month")),
+                        month => month,
                     },
-                    None => return ReoccurancePattern::None,
+                    None => return Err("month is required".to_string()),
                 };
-                ReoccurancePattern::Yearly {
+                Ok(ReoccurancePattern::Yearly {
                     month,
-                    day_of_month,
-                }
+                    day_of_month: pattern_number(json, "day_of_month")?,
+                })
             }
-            _ => ReoccurancePattern::None,
+            pattern => Err(format!(
+                "{pattern} is not one of None, OneTime, Daily, Weekly, Monthly or Yearly"
+            )),
         }
     }
 }
+
+fn pattern_number(json: &serde_json::Value, field: &str) -> Result<u32, String> {
+    match json.get(field).and_then(|number| number.as_u64()) {
+        Some(number) => match u32::try_from(number) {
+            Ok(number) => Ok(number),
+            Err(_) => Err(format!("{field} is too large")),
+        },
+        None => Err(format!("{field} must be a whole number")),
+    }
+}
 // endregion:   -- Data Structures
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
@@ -672,8 +1079,19 @@ impl From<String> for Location {
         let osm = geocoding::Openstreetmap::new();
         let result = osm.forward(&value);
         let cords = match result {
-            Ok(point_vec) => point_vec[0],
+            Ok(point_vec) => match point_vec.first() {
+                Some(point) => {
+                    counter!(GEOCODING_REQUESTS_TOTAL, "outcome" => "ok").increment(1);
+                    *point
+                }
+                None => {
+                    counter!(GEOCODING_REQUESTS_TOTAL, "outcome" => "no_results").increment(1);
+                    warn!("No coordinates found for {value}");
+                    geocoding::Point::new(0.0, 0.0)
+                }
+            },
             Err(err) => {
+                counter!(GEOCODING_REQUESTS_TOTAL, "outcome" => "error").increment(1);
                 warn!("{}", err);
                 geocoding::Point::new(0.0, 0.0)
             }
@@ -0,0 +1,988 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::database::models::{Day, Month, ReoccurancePattern};
+use crate::database::schedule::resolve_local;
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+// Keeps a typo such as COUNT=1000000 from expanding forever
+const MAX_COUNT: i64 = 10_000;
+const MAX_INTERVAL: i64 = 1_000;
+
+// region:      -- Rules
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+// A BYDAY entry, FR is every friday and -1SA the last saturday of the month or year
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WeekdayNum {
+    pub ordinal: Option<i32>,
+    pub weekday: Weekday,
+}
+
+// A DATE or DATE-TIME value of UNTIL or EXDATE
+//      Date    -> 20241201, covers the whole day
+//      Utc     -> 20241201T170000Z
+//      Local   -> 20241201T120000, on the wall clock of the event's zone
+// Values with a TZID parameter are converted to Utc when they are parsed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuleTime {
+    Date(NaiveDate),
+    Utc(DateTime<Utc>),
+    Local(NaiveDateTime),
+}
+
+// An RFC 5545 recurrence rule along with the EXDATEs removed from it, written as iCalendar
+//      content lines with the RRULE first and then one line per EXDATE
+//      RRULE:FREQ=MONTHLY;BYDAY=1FR,3FR;UNTIL=20241201
+//      EXDATE;VALUE=DATE:20240607
+// A bare FREQ=... is accepted as the RRULE line
+// The time of day always comes from the event's datetime, so the sub daily frequencies, BYHOUR,
+//      BYMINUTE and BYSECOND aren't supported, and neither are BYWEEKNO and BYYEARDAY
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecurrenceRule {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub by_day: Vec<WeekdayNum>,
+    pub by_month_day: Vec<i32>,
+    pub by_month: Vec<u32>,
+    pub by_set_pos: Vec<i32>,
+    pub week_start: Weekday,
+    pub until: Option<RuleTime>,
+    pub count: Option<u32>,
+    pub exdates: Vec<RuleTime>,
+}
+
+impl RecurrenceRule {
+    pub fn new(frequency: Frequency) -> Self {
+        RecurrenceRule {
+            frequency,
+            interval: 1,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+            by_set_pos: Vec::new(),
+            week_start: Weekday::Mon,
+            until: None,
+            count: None,
+            exdates: Vec::new(),
+        }
+    }
+}
+
+// endregion:   -- Rules
+
+// region:      -- Parsing
+
+impl FromStr for RecurrenceRule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut rule: Option<RecurrenceRule> = None;
+        let mut exdates = Vec::new();
+        for line in value.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (name, tzid, content) = content_line(line)?;
+            match name.as_str() {
+                "RRULE" if rule.is_some() => return Err("only one RRULE can be given".into()),
+                "RRULE" => rule = Some(parse_rule(content)?),
+                "EXDATE" => {
+                    for exdate in content.split(',') {
+                        exdates.push(RuleTime::parse(exdate, tzid)?);
+                    }
+                }
+                name => return Err(format!("{name} is not supported, only RRULE and EXDATE")),
+            }
+        }
+        let mut rule = match rule {
+            Some(rule) => rule,
+            None => return Err("an RRULE such as FREQ=WEEKLY;BYDAY=FR is required".into()),
+        };
+        rule.exdates = exdates;
+        Ok(rule)
+    }
+}
+
+// Splits a content line into its name, TZID parameter and value
+// A line without a name such as FREQ=DAILY is taken as the RRULE
+fn content_line(line: &str) -> Result<(String, Option<Tz>, &str), String> {
+    let (head, value) = match line.split_once(':') {
+        Some((head, value)) => (head, value.trim()),
+        None => return Ok(("RRULE".to_string(), None, line)),
+    };
+    let mut parts = head.split(';');
+    let name = parts.next().unwrap_or("").trim().to_uppercase();
+    let mut tzid = None;
+    for parameter in parts {
+        match parameter.split_once('=') {
+            Some((key, zone)) if key.trim().eq_ignore_ascii_case("TZID") => {
+                match zone.trim().parse::<Tz>() {
+                    Ok(zone) => tzid = Some(zone),
+                    Err(_) => return Err(format!("{zone} is not an IANA time zone")),
+                }
+            }
+            // VALUE=DATE and VALUE=DATE-TIME are told apart by the value itself
+            Some(_) => (),
+            None => return Err(format!("{parameter} is not a NAME=VALUE parameter")),
+        }
+    }
+    Ok((name, tzid, value))
+}
+
+fn parse_rule(value: &str) -> Result<RecurrenceRule, String> {
+    let mut parts: Vec<(String, &str)> = Vec::new();
+    for part in value
+        .split(';')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+    {
+        let (name, value) = match part.split_once('=') {
+            Some((name, value)) => (name.trim().to_uppercase(), value.trim()),
+            None => return Err(format!("{part} is not a NAME=VALUE rule part")),
+        };
+        if parts.iter().any(|(seen, _)| *seen == name) {
+            return Err(format!("{name} is given more than once"));
+        }
+        parts.push((name, value));
+    }
+
+    let frequency = match parts.iter().find(|(name, _)| name == "FREQ") {
+        Some((_, frequency)) => match frequency.to_uppercase().as_str() {
+            "DAILY" => Frequency::Daily,
+            "WEEKLY" => Frequency::Weekly,
+            "MONTHLY" => Frequency::Monthly,
+            "YEARLY" => Frequency::Yearly,
+            _ => {
+                return Err(format!(
+                    "FREQ={frequency} is not supported, use DAILY, WEEKLY, MONTHLY or YEARLY"
+                ))
+            }
+        },
+        None => return Err("FREQ is required".into()),
+    };
+
+    let mut rule = RecurrenceRule::new(frequency);
+    for (name, value) in parts {
+        match name.as_str() {
+            "FREQ" => (),
+            "INTERVAL" => rule.interval = integer(&name, value, 1, MAX_INTERVAL)? as u32,
+            "COUNT" => rule.count = Some(integer(&name, value, 1, MAX_COUNT)? as u32),
+            "UNTIL" => rule.until = Some(RuleTime::parse(value, None)?),
+            "WKST" => rule.week_start = weekday(value)?,
+            "BYDAY" => {
+                for day in value.split(',') {
+                    rule.by_day.push(WeekdayNum::parse(day)?);
+                }
+            }
+            "BYMONTHDAY" => {
+                for day in value.split(',') {
+                    rule.by_month_day.push(nonzero(&name, day, 31)?);
+                }
+            }
+            "BYMONTH" => {
+                for month in value.split(',') {
+                    rule.by_month.push(integer(&name, month, 1, 12)? as u32);
+                }
+            }
+            "BYSETPOS" => {
+                for position in value.split(',') {
+                    rule.by_set_pos.push(nonzero(&name, position, 366)?);
+                }
+            }
+            name => return Err(format!("{name} is not supported")),
+        }
+    }
+
+    if rule.until.is_some() && rule.count.is_some() {
+        return Err("UNTIL and COUNT can't both be given".into());
+    }
+    let ordinals: Vec<i32> = rule.by_day.iter().filter_map(|day| day.ordinal).collect();
+    match rule.frequency {
+        Frequency::Daily | Frequency::Weekly if !ordinals.is_empty() => {
+            return Err(
+                "BYDAY can only have ordinals such as 1FR with FREQ=MONTHLY or FREQ=YEARLY".into(),
+            )
+        }
+        Frequency::Monthly if ordinals.iter().any(|ordinal| ordinal.abs() > 5) => {
+            return Err("BYDAY ordinals must be between -5 and 5 with FREQ=MONTHLY".into())
+        }
+        _ => (),
+    }
+    if rule.frequency == Frequency::Weekly && !rule.by_month_day.is_empty() {
+        return Err("BYMONTHDAY can't be used with FREQ=WEEKLY".into());
+    }
+    Ok(rule)
+}
+
+fn integer(name: &str, value: &str, min: i64, max: i64) -> Result<i64, String> {
+    match value.trim().parse::<i64>() {
+        Ok(number) if (min..=max).contains(&number) => Ok(number),
+        _ => Err(format!(
+            "{name} must be between {min} and {max}, not {value}"
+        )),
+    }
+}
+
+// Between -limit and limit but not 0, negative values count back from the end
+fn nonzero(name: &str, value: &str, limit: i64) -> Result<i32, String> {
+    match value.trim().trim_start_matches('+').parse::<i64>() {
+        Ok(number) if number != 0 && number.abs() <= limit => Ok(number as i32),
+        _ => Err(format!(
+            "{name} must be between 1 and {limit} or -{limit} and -1, not {value}"
+        )),
+    }
+}
+
+fn weekday(value: &str) -> Result<Weekday, String> {
+    match value.trim().to_uppercase().as_str() {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        _ => Err(format!(
+            "{value} is not a day, use MO, TU, WE, TH, FR, SA or SU"
+        )),
+    }
+}
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+impl WeekdayNum {
+    fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let split = value.len().saturating_sub(2);
+        if !value.is_char_boundary(split) {
+            return Err(format!("{value} is not a day such as FR or 1FR"));
+        }
+        let (ordinal, day) = value.split_at(split);
+        let ordinal = match ordinal {
+            "" => None,
+            ordinal => Some(nonzero("BYDAY", ordinal, 53)?),
+        };
+        Ok(WeekdayNum {
+            ordinal,
+            weekday: weekday(day)?,
+        })
+    }
+}
+
+impl RuleTime {
+    fn parse(value: &str, tzid: Option<Tz>) -> Result<Self, String> {
+        let value = value.trim();
+        let invalid = || {
+            format!("{value} is not a date such as 20241201 or a datetime such as 20241201T170000Z")
+        };
+        if value.len() == 8 {
+            return match NaiveDate::parse_from_str(value, "%Y%m%d") {
+                Ok(date) => Ok(RuleTime::Date(date)),
+                Err(_) => Err(invalid()),
+            };
+        }
+        let (local, utc) = match value.strip_suffix('Z') {
+            Some(local) => (local, true),
+            None => (value, false),
+        };
+        let local = match NaiveDateTime::parse_from_str(local, "%Y%m%dT%H%M%S") {
+            Ok(local) => local,
+            Err(_) => return Err(invalid()),
+        };
+        match (utc, tzid) {
+            (true, _) => Ok(RuleTime::Utc(local.and_utc())),
+            (false, Some(zone)) => match resolve_local(zone, local) {
+                Some(datetime) => Ok(RuleTime::Utc(datetime.with_timezone(&Utc))),
+                None => Err(invalid()),
+            },
+            (false, None) => Ok(RuleTime::Local(local)),
+        }
+    }
+
+    // The date it falls on in the zone
+    pub fn local_date(&self, zone: Tz) -> NaiveDate {
+        match self {
+            RuleTime::Date(date) => *date,
+            RuleTime::Utc(datetime) => datetime.with_timezone(&zone).date_naive(),
+            RuleTime::Local(local) => local.date(),
+        }
+    }
+
+    // The latest an occurrence can start and still be on or before it, a date covers the whole day
+    pub fn latest_start(&self, zone: Tz) -> Option<DateTime<FixedOffset>> {
+        match self {
+            RuleTime::Date(date) => date
+                .succ_opt()
+                .and_then(|next| resolve_local(zone, next.and_time(NaiveTime::MIN)))
+                .map(|next| next - Duration::seconds(1)),
+            RuleTime::Utc(datetime) => Some(datetime.fixed_offset()),
+            RuleTime::Local(local) => resolve_local(zone, *local),
+        }
+    }
+}
+
+// endregion:   -- Parsing
+
+// region:      -- Writing
+
+impl fmt::Display for Frequency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Frequency::Daily => write!(f, "DAILY"),
+            Frequency::Weekly => write!(f, "WEEKLY"),
+            Frequency::Monthly => write!(f, "MONTHLY"),
+            Frequency::Yearly => write!(f, "YEARLY"),
+        }
+    }
+}
+
+impl fmt::Display for WeekdayNum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.ordinal {
+            Some(ordinal) => write!(f, "{ordinal}{}", weekday_code(self.weekday)),
+            None => write!(f, "{}", weekday_code(self.weekday)),
+        }
+    }
+}
+
+impl fmt::Display for RuleTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleTime::Date(date) => write!(f, "{}", date.format("%Y%m%d")),
+            RuleTime::Utc(datetime) => write!(f, "{}", datetime.format("%Y%m%dT%H%M%SZ")),
+            RuleTime::Local(local) => write!(f, "{}", local.format("%Y%m%dT%H%M%S")),
+        }
+    }
+}
+
+impl fmt::Display for RecurrenceRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RRULE:FREQ={}", self.frequency)?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if !self.by_day.is_empty() {
+            write!(f, ";BYDAY={}", join(&self.by_day))?;
+        }
+        if !self.by_month_day.is_empty() {
+            write!(f, ";BYMONTHDAY={}", join(&self.by_month_day))?;
+        }
+        if !self.by_month.is_empty() {
+            write!(f, ";BYMONTH={}", join(&self.by_month))?;
+        }
+        if !self.by_set_pos.is_empty() {
+            write!(f, ";BYSETPOS={}", join(&self.by_set_pos))?;
+        }
+        if self.week_start != Weekday::Mon {
+            write!(f, ";WKST={}", weekday_code(self.week_start))?;
+        }
+        if let Some(until) = &self.until {
+            write!(f, ";UNTIL={until}")?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={count}")?;
+        }
+        for exdate in self.exdates.iter() {
+            match exdate {
+                RuleTime::Date(_) => write!(f, "\nEXDATE;VALUE=DATE:{exdate}")?,
+                _ => write!(f, "\nEXDATE:{exdate}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn join<T: fmt::Display>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// Rules are stored and sent as their text so they read the same everywhere
+impl Serialize for RecurrenceRule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for RecurrenceRule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let rule = String::deserialize(deserializer)?;
+        rule.parse().map_err(de::Error::custom)
+    }
+}
+
+// endregion:   -- Writing
+
+// region:      -- Expansion
+
+impl RecurrenceRule {
+    // Every date the rule repeats on between from and to inclusive, in order
+    // first is the date of the event's first occurrence, it is always included and counted even
+    //      when the rule wouldn't produce it
+    // EXDATEs are counted towards COUNT before they are removed, as RFC 5545 requires
+    // Nothing after the date of UNTIL in the zone is returned, the time of day it gives is left
+    //      to the caller, see schedule::scheduled
+    pub fn dates(
+        &self,
+        first: NaiveDate,
+        zone: Tz,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Vec<NaiveDate> {
+        let excluded: Vec<NaiveDate> = self
+            .exdates
+            .iter()
+            .map(|exdate| exdate.local_date(zone))
+            .collect();
+        let to = match &self.until {
+            Some(until) => to.min(until.local_date(zone)),
+            None => to,
+        };
+
+        // With a COUNT every occurrence has to be counted from the first, otherwise the periods
+        //      before the window can be skipped
+        let mut period = match self.count {
+            Some(_) => 0,
+            None => self.periods_before(first, from),
+        };
+        let mut counted: u32 = 0;
+        let mut dates = Vec::new();
+        loop {
+            let (period_start, mut candidates) = self.period(first, period);
+            if period_start > to {
+                break;
+            }
+            if period == 0 && !candidates.contains(&first) {
+                candidates.push(first);
+                candidates.sort();
+            }
+            for date in candidates.into_iter().filter(|date| *date >= first) {
+                if date > to {
+                    return dates;
+                }
+                counted += 1;
+                if date >= from && !excluded.contains(&date) {
+                    dates.push(date);
+                }
+                if self.count.is_some_and(|count| counted >= count) {
+                    return dates;
+                }
+            }
+            period += 1;
+        }
+        dates
+    }
+
+    // How many whole intervals lie between the first period and the one containing from, less one
+    //      to be safe at the edges
+    fn periods_before(&self, first: NaiveDate, from: NaiveDate) -> i64 {
+        let elapsed = match self.frequency {
+            Frequency::Daily => (from - first).num_days(),
+            Frequency::Weekly => (from - week_start(first, self.week_start)).num_days() / 7,
+            Frequency::Monthly => month_index(from) - month_index(first),
+            Frequency::Yearly => i64::from(from.year() - first.year()),
+        };
+        (elapsed / i64::from(self.interval) - 1).max(0)
+    }
+
+    // The start of the period interval * index periods after the first and the dates in it,
+    //      sorted with BYSETPOS applied
+    fn period(&self, first: NaiveDate, index: i64) -> (NaiveDate, Vec<NaiveDate>) {
+        let step = index * i64::from(self.interval);
+        let (start, mut dates) = match self.frequency {
+            Frequency::Daily => {
+                let date = first + Duration::days(step);
+                let dates = if self.limits_day(date) {
+                    vec![date]
+                } else {
+                    Vec::new()
+                };
+                (date, dates)
+            }
+            Frequency::Weekly => {
+                let start = week_start(first, self.week_start) + Duration::weeks(step);
+                let weekdays: Vec<Weekday> = match self.by_day.is_empty() {
+                    true => vec![first.weekday()],
+                    false => self.by_day.iter().map(|day| day.weekday).collect(),
+                };
+                let dates = weekdays
+                    .into_iter()
+                    .map(|weekday| start + Duration::days(days_after(self.week_start, weekday)))
+                    .filter(|date| self.limits_month(date.month()))
+                    .collect();
+                (start, dates)
+            }
+            Frequency::Monthly => {
+                let month = month_index(first) + step;
+                let year = month.div_euclid(12) as i32;
+                let month = month.rem_euclid(12) as u32 + 1;
+                let start = NaiveDate::from_ymd_opt(year, month, 1).unwrap_or(NaiveDate::MAX);
+                let dates = match self.limits_month(month) {
+                    true => self.month_dates(first, year, month),
+                    false => Vec::new(),
+                };
+                (start, dates)
+            }
+            Frequency::Yearly => {
+                let year = first.year() + step as i32;
+                let start = NaiveDate::from_ymd_opt(year, 1, 1).unwrap_or(NaiveDate::MAX);
+                (start, self.year_dates(first, year))
+            }
+        };
+        dates.sort();
+        dates.dedup();
+        if !self.by_set_pos.is_empty() {
+            let mut selected: Vec<NaiveDate> = self
+                .by_set_pos
+                .iter()
+                .filter_map(|position| nth(&dates, *position))
+                .collect();
+            selected.sort();
+            selected.dedup();
+            dates = selected;
+        }
+        (start, dates)
+    }
+
+    // The dates of a month from BYMONTHDAY, or BYDAY when there is no BYMONTHDAY, or the day of
+    //      the first occurrence when there are neither
+    // BYDAY only limits BYMONTHDAY when both are given
+    fn month_dates(&self, first: NaiveDate, year: i32, month: u32) -> Vec<NaiveDate> {
+        let days = match days_in_month(year, month) {
+            Some(days) => days,
+            None => return Vec::new(),
+        };
+        if !self.by_month_day.is_empty() {
+            return self
+                .by_month_day
+                .iter()
+                .filter_map(|day| month_day(*day, days))
+                .filter_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+                .filter(|date| self.limits_weekday(date.weekday()))
+                .collect();
+        }
+        if !self.by_day.is_empty() {
+            let start = NaiveDate::from_ymd_opt(year, month, 1);
+            let end = NaiveDate::from_ymd_opt(year, month, days);
+            return match (start, end) {
+                (Some(start), Some(end)) => self.weekdays_between(start, end),
+                _ => Vec::new(),
+            };
+        }
+        NaiveDate::from_ymd_opt(year, month, first.day())
+            .into_iter()
+            .collect()
+    }
+
+    // BYMONTH picks the months and each is expanded like a monthly rule
+    // Without BYMONTH, BYMONTHDAY applies to every month and BYDAY ordinals count through the
+    //      whole year, so 20MO is the 20th monday of the year
+    fn year_dates(&self, first: NaiveDate, year: i32) -> Vec<NaiveDate> {
+        if !self.by_month.is_empty() {
+            return self
+                .by_month
+                .iter()
+                .flat_map(|month| self.month_dates(first, year, *month))
+                .collect();
+        }
+        if !self.by_month_day.is_empty() {
+            return (1..=12)
+                .flat_map(|month| self.month_dates(first, year, month))
+                .collect();
+        }
+        if !self.by_day.is_empty() {
+            let start = NaiveDate::from_ymd_opt(year, 1, 1);
+            let end = NaiveDate::from_ymd_opt(year, 12, 31);
+            return match (start, end) {
+                (Some(start), Some(end)) => self.weekdays_between(start, end),
+                _ => Vec::new(),
+            };
+        }
+        NaiveDate::from_ymd_opt(year, first.month(), first.day())
+            .into_iter()
+            .collect()
+    }
+
+    // The dates from start to end inclusive matching BYDAY, ordinals pick one of the matching
+    //      weekdays counting from the start, or from the end when negative
+    fn weekdays_between(&self, start: NaiveDate, end: NaiveDate) -> Vec<NaiveDate> {
+        let mut dates = Vec::new();
+        for day in self.by_day.iter() {
+            let first = start + Duration::days(days_after(start.weekday(), day.weekday));
+            let matching: Vec<NaiveDate> =
+                first.iter_weeks().take_while(|date| *date <= end).collect();
+            match day.ordinal {
+                Some(ordinal) => dates.extend(nth(&matching, ordinal)),
+                None => dates.extend(matching),
+            }
+        }
+        dates
+    }
+
+    // Whether a daily occurrence on the date passes BYMONTH, BYMONTHDAY and BYDAY
+    fn limits_day(&self, date: NaiveDate) -> bool {
+        let days = days_in_month(date.year(), date.month()).unwrap_or(31);
+        self.limits_month(date.month())
+            && self.limits_weekday(date.weekday())
+            && (self.by_month_day.is_empty()
+                || self
+                    .by_month_day
+                    .iter()
+                    .any(|day| month_day(*day, days) == Some(date.day())))
+    }
+
+    fn limits_month(&self, month: u32) -> bool {
+        self.by_month.is_empty() || self.by_month.contains(&month)
+    }
+
+    fn limits_weekday(&self, weekday: Weekday) -> bool {
+        self.by_day.is_empty() || self.by_day.iter().any(|day| day.weekday == weekday)
+    }
+}
+
+// The start of the week containing the date, weeks start on week_start
+fn week_start(date: NaiveDate, week_start: Weekday) -> NaiveDate {
+    date - Duration::days(days_after(week_start, date.weekday()))
+}
+
+// Days from one weekday forward to the next, 0 to 6
+fn days_after(from: Weekday, to: Weekday) -> i64 {
+    i64::from((to.num_days_from_monday() + 7 - from.num_days_from_monday()) % 7)
+}
+
+fn month_index(date: NaiveDate) -> i64 {
+    i64::from(date.year()) * 12 + i64::from(date.month0())
+}
+
+fn days_in_month(year: i32, month: u32) -> Option<u32> {
+    let next = match month {
+        12 => NaiveDate::from_ymd_opt(year + 1, 1, 1),
+        _ => NaiveDate::from_ymd_opt(year, month + 1, 1),
+    }?;
+    Some(next.pred_opt()?.day())
+}
+
+// A BYMONTHDAY as a day of a month with the given number of days, -1 is the last day
+fn month_day(day: i32, days: u32) -> Option<u32> {
+    let day = match day {
+        day if day > 0 => day,
+        day => days as i32 + 1 + day,
+    };
+    match u32::try_from(day) {
+        Ok(day) if (1..=days).contains(&day) => Some(day),
+        _ => None,
+    }
+}
+
+// The position'th of the values counting from 1, or back from the end when negative
+fn nth<T: Copy>(values: &[T], position: i32) -> Option<T> {
+    let index = match position {
+        position if position > 0 => position as usize - 1,
+        position => values.len().checked_sub(position.unsigned_abs() as usize)?,
+    };
+    values.get(index).copied()
+}
+
+// endregion:   -- Expansion
+
+// region:      -- Legacy patterns
+
+impl ReoccurancePattern {
+    // The equivalent rule, None for patterns which don't repeat
+    // The patterns moved days a month doesn't have to its last day, BYSETPOS=-1 over the
+    //      possible days does the same, so the 31st becomes BYMONTHDAY=28,29,30,31;BYSETPOS=-1
+    // A day_of_month of 0 or a Month::None used the first occurrence's, as did weekly without days
+    pub fn to_rule(&self, first: NaiveDate) -> Option<RecurrenceRule> {
+        match self {
+            ReoccurancePattern::None | ReoccurancePattern::OneTime => None,
+            ReoccurancePattern::Daily => Some(RecurrenceRule::new(Frequency::Daily)),
+            ReoccurancePattern::Weekly { days, spacing } => {
+                let mut rule = RecurrenceRule::new(Frequency::Weekly);
+                rule.interval = (*spacing).max(1);
+                for weekday in days.iter().filter_map(day_weekday) {
+                    let day = WeekdayNum {
+                        ordinal: None,
+                        weekday,
+                    };
+                    if !rule.by_day.contains(&day) {
+                        rule.by_day.push(day);
+                    }
+                }
+                Some(rule)
+            }
+            ReoccurancePattern::Monthly {
+                day_of_month,
+                spacing,
+            } => {
+                let mut rule = RecurrenceRule::new(Frequency::Monthly);
+                rule.interval = (*spacing).max(1);
+                clamp_day(&mut rule, *day_of_month, first);
+                Some(rule)
+            }
+            ReoccurancePattern::Yearly {
+                month,
+                day_of_month,
+            } => {
+                let mut rule = RecurrenceRule::new(Frequency::Yearly);
+                rule.by_month = vec![month_number(month).unwrap_or(first.month())];
+                clamp_day(&mut rule, *day_of_month, first);
+                Some(rule)
+            }
+        }
+    }
+}
+
+fn clamp_day(rule: &mut RecurrenceRule, day_of_month: u32, first: NaiveDate) {
+    let day = match day_of_month {
+        0 => first.day(),
+        day => day.min(31),
+    } as i32;
+    if day <= 28 {
+        rule.by_month_day = vec![day];
+    } else {
+        rule.by_month_day = (28..=day).collect();
+        rule.by_set_pos = vec![-1];
+    }
+}
+
+fn day_weekday(day: &Day) -> Option<Weekday> {
+    match day {
+        Day::NONE => None,
+        Day::MONDAY => Some(Weekday::Mon),
+        Day::TUESDAY => Some(Weekday::Tue),
+        Day::WEDNESDAY => Some(Weekday::Wed),
+        Day::THURSDAY => Some(Weekday::Thu),
+        Day::FRIDAY => Some(Weekday::Fri),
+        Day::SATURDAY => Some(Weekday::Sat),
+        Day::SUNDAY => Some(Weekday::Sun),
+    }
+}
+
+fn month_number(month: &Month) -> Option<u32> {
+    match month {
+        Month::None => None,
+        Month::January => Some(1),
+        Month::February => Some(2),
+        Month::March => Some(3),
+        Month::April => Some(4),
+        Month::May => Some(5),
+        Month::June => Some(6),
+        Month::July => Some(7),
+        Month::August => Some(8),
+        Month::September => Some(9),
+        Month::October => Some(10),
+        Month::November => Some(11),
+        Month::December => Some(12),
+    }
+}
+
+// endregion:   -- Legacy patterns
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    fn rule(value: &str) -> RecurrenceRule {
+        value.parse().unwrap()
+    }
+
+    fn dates(rule: &RecurrenceRule, first: &str, to: &str) -> Vec<NaiveDate> {
+        rule.dates(date(first), Tz::UTC, date(first), date(to))
+    }
+
+    #[test]
+    fn written_rules_parse_back_the_same() {
+        for text in [
+            "RRULE:FREQ=DAILY",
+            "RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,FR;WKST=SU",
+            "RRULE:FREQ=MONTHLY;BYDAY=1FR,-1SA;UNTIL=20241201",
+            "RRULE:FREQ=MONTHLY;BYMONTHDAY=28,29,30,31;BYSETPOS=-1;COUNT=12",
+            "RRULE:FREQ=YEARLY;BYMONTHDAY=29;BYMONTH=2;UNTIL=20300101T120000Z",
+            "RRULE:FREQ=WEEKLY;UNTIL=20241201T170000\nEXDATE;VALUE=DATE:20240607\nEXDATE:20240614T100000Z",
+        ] {
+            let parsed = rule(text);
+            assert_eq!(parsed.to_string(), text);
+            assert_eq!(rule(&parsed.to_string()), parsed);
+        }
+    }
+
+    #[test]
+    fn rules_are_normalised_when_written() {
+        let parsed = rule("freq=weekly;byday=fr;interval=1");
+        assert_eq!(parsed.to_string(), "RRULE:FREQ=WEEKLY;BYDAY=FR");
+        // An EXDATE with a TZID is kept as the UTC instant it names
+        let parsed = rule("FREQ=DAILY\nEXDATE;TZID=Europe/Berlin:20240607T100000");
+        assert_eq!(
+            parsed.to_string(),
+            "RRULE:FREQ=DAILY\nEXDATE:20240607T080000Z"
+        );
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        for text in [
+            "",
+            "FREQ=HOURLY",
+            "FREQ=DAILY;COUNT=2;UNTIL=20241201",
+            "FREQ=WEEKLY;BYDAY=1FR",
+            "FREQ=WEEKLY;BYMONTHDAY=1",
+            "FREQ=MONTHLY;BYMONTHDAY=32",
+            "FREQ=DAILY;FREQ=WEEKLY",
+            "FREQ=DAILY\nRDATE:20240101",
+        ] {
+            assert!(text.parse::<RecurrenceRule>().is_err(), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn count_includes_the_first_occurrence() {
+        assert_eq!(
+            dates(
+                &rule("FREQ=WEEKLY;BYDAY=FR;COUNT=3"),
+                "2024-05-03",
+                "2024-12-31"
+            ),
+            vec![date("2024-05-03"), date("2024-05-10"), date("2024-05-17")]
+        );
+        // The first occurrence is counted even when the rule wouldn't produce it
+        assert_eq!(
+            dates(
+                &rule("FREQ=WEEKLY;BYDAY=FR;COUNT=2"),
+                "2024-05-01",
+                "2024-12-31"
+            ),
+            vec![date("2024-05-01"), date("2024-05-03")]
+        );
+    }
+
+    #[test]
+    fn count_is_kept_outside_the_window() {
+        let rule = rule("FREQ=DAILY;COUNT=5");
+        assert_eq!(
+            rule.dates(
+                date("2024-05-01"),
+                Tz::UTC,
+                date("2024-05-04"),
+                date("2024-12-31")
+            ),
+            vec![date("2024-05-04"), date("2024-05-05")]
+        );
+    }
+
+    #[test]
+    fn until_is_inclusive() {
+        assert_eq!(
+            dates(
+                &rule("FREQ=DAILY;UNTIL=20240503"),
+                "2024-05-01",
+                "2024-12-31"
+            ),
+            vec![date("2024-05-01"), date("2024-05-02"), date("2024-05-03")]
+        );
+    }
+
+    #[test]
+    fn exdates_are_removed_but_counted() {
+        let rule = rule("FREQ=WEEKLY;COUNT=3\nEXDATE;VALUE=DATE:20240510");
+        assert_eq!(
+            dates(&rule, "2024-05-03", "2024-12-31"),
+            vec![date("2024-05-03"), date("2024-05-17")]
+        );
+    }
+
+    #[test]
+    fn last_weekday_of_the_month_with_bysetpos() {
+        let rule = rule("FREQ=MONTHLY;BYDAY=MO,TU,WE,TH,FR;BYSETPOS=-1");
+        assert_eq!(
+            dates(&rule, "2024-01-31", "2024-04-30"),
+            vec![
+                date("2024-01-31"),
+                date("2024-02-29"),
+                date("2024-03-29"),
+                date("2024-04-30")
+            ]
+        );
+    }
+
+    #[test]
+    fn legacy_monthly_late_days_fall_back_in_february() {
+        for (day, leap, common) in [
+            (29, "2024-02-29", "2023-02-28"),
+            (30, "2024-02-29", "2023-02-28"),
+            (31, "2024-02-29", "2023-02-28"),
+        ] {
+            let pattern = ReoccurancePattern::Monthly {
+                day_of_month: day,
+                spacing: 1,
+            };
+            for (first, february) in [("2024-01-01", leap), ("2023-01-01", common)] {
+                let rule = pattern.to_rule(date(first)).unwrap();
+                let found = rule.dates(
+                    date(first),
+                    Tz::UTC,
+                    date(february).with_day(1).unwrap(),
+                    date(february),
+                );
+                assert_eq!(found, vec![date(february)], "day {day} from {first}");
+            }
+        }
+    }
+
+    #[test]
+    fn legacy_yearly_late_days_fall_back_in_february() {
+        for day in [29, 30, 31] {
+            let pattern = ReoccurancePattern::Yearly {
+                month: Month::February,
+                day_of_month: day,
+            };
+            let rule = pattern.to_rule(date("2023-01-01")).unwrap();
+            assert_eq!(
+                dates(&rule, "2023-01-01", "2024-12-31"),
+                vec![date("2023-01-01"), date("2023-02-28"), date("2024-02-29")],
+                "day {day}"
+            );
+        }
+    }
+
+    #[test]
+    fn legacy_patterns_without_a_day_use_the_first_occurrence() {
+        let pattern = ReoccurancePattern::Monthly {
+            day_of_month: 0,
+            spacing: 2,
+        };
+        let rule = pattern.to_rule(date("2024-01-15")).unwrap();
+        assert_eq!(
+            rule.to_string(),
+            "RRULE:FREQ=MONTHLY;INTERVAL=2;BYMONTHDAY=15"
+        );
+        assert!(ReoccurancePattern::OneTime
+            .to_rule(date("2024-01-15"))
+            .is_none());
+    }
+}
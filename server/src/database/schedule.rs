@@ -0,0 +1,539 @@
+use crate::database::models::Event;
+use chrono::{
+    DateTime, Duration, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, TimeZone,
+};
+use chrono_tz::Tz;
+use serde::Serialize;
+use std::borrow::Cow;
+
+// A single concrete time an event happens at, with the event's override for it applied
+//      date        -> The date it was scheduled on in the event's zone, identifies the
+//                     occurrence even after it is moved
+//      end         -> start plus the event's duration
+//      location    -> Where this occurrence happens, the event's location unless it was moved
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Occurrence {
+    pub date: NaiveDate,
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    pub cancelled: bool,
+    pub location: Cow<'static, str>,
+    pub cord_x: f64,
+    pub cord_y: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<Cow<'static, str>>,
+    pub event: Event,
+}
+
+impl Occurrence {
+    // The occurrence scheduled on date at start, changed by the event's override for the date
+    pub fn new(date: NaiveDate, start: DateTime<FixedOffset>, event: &Event) -> Self {
+        let mut occurrence = Occurrence {
+            date,
+            start,
+            end: start,
+            cancelled: false,
+            location: event.location.clone(),
+            cord_x: event.cord_x,
+            cord_y: event.cord_y,
+            note: None,
+            event: event.clone(),
+        };
+        if let Some(over) = event.override_on(date) {
+            occurrence.cancelled = over.cancelled;
+            occurrence.start = over.start.unwrap_or(start);
+            if let (Some(location), Some(cord_x), Some(cord_y)) =
+                (&over.location, over.cord_x, over.cord_y)
+            {
+                occurrence.location = location.clone();
+                occurrence.cord_x = cord_x;
+                occurrence.cord_y = cord_y;
+            }
+            occurrence.note = over.note.clone();
+        }
+        occurrence.end = occurrence.start + Duration::minutes(i64::from(event.duration_minutes));
+        occurrence
+    }
+
+    // Open from start up to but not including end
+    pub fn is_open_at(&self, at: DateTime<FixedOffset>) -> bool {
+        self.start <= at && at < self.end
+    }
+}
+
+// region:      -- Parsing
+
+// Parses the datetimes of query parameters and imported data, RFC 3339 is preferred but the
+//      formats found in the imported data are accepted as well
+// Datetimes without an offset are assumed to be UTC
+pub fn parse_datetime(value: &str) -> Option<DateTime<FixedOffset>> {
+    parse_datetime_in(value, Tz::UTC)
+}
+
+// Like parse_datetime except datetimes without an offset are on the wall clock of the zone
+pub fn parse_datetime_in(value: &str, zone: Tz) -> Option<DateTime<FixedOffset>> {
+    let value = value.trim();
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Some(datetime);
+    }
+    for format in [
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M",
+    ] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(value, format) {
+            return resolve_local(zone, naive);
+        }
+    }
+    match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(date) => resolve_local(zone, date.and_time(NaiveTime::MIN)),
+        Err(_) => None,
+    }
+}
+
+// Like parse_datetime_in except a bare date covers the whole day, so an event repeating until
+//      2024-12-01 still happens on the first
+pub fn parse_end_in(value: &str, zone: Tz) -> Option<DateTime<FixedOffset>> {
+    match NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d") {
+        Ok(date) => date
+            .succ_opt()
+            .and_then(|next| resolve_local(zone, next.and_time(NaiveTime::MIN)))
+            .map(|next| next - Duration::seconds(1)),
+        Err(_) => parse_datetime_in(value, zone),
+    }
+}
+
+// The instant a wall clock time happens at in the zone
+// Times skipped when the clocks go forward are moved an hour later, past the gap, and times
+//      which happen twice when the clocks go back use the first
+pub fn resolve_local(zone: Tz, local: NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+    match zone.from_local_datetime(&local) {
+        LocalResult::Single(datetime) | LocalResult::Ambiguous(datetime, _) => {
+            Some(datetime.fixed_offset())
+        }
+        LocalResult::None => zone
+            .from_local_datetime(&(local + Duration::hours(1)))
+            .earliest()
+            .map(|datetime| datetime.fixed_offset()),
+    }
+}
+
+// endregion:   -- Parsing
+
+// region:      -- Expansion
+
+// How far ahead next_occurrence looks
+pub const NEXT_OCCURRENCE_DAYS: i64 = 366;
+
+// Returns every occurrence of the event starting inside [from, to), in order
+// Cancelled occurrences are left out and moved occurrences are found by their new start
+pub fn occurrences(
+    event: &Event,
+    from: DateTime<FixedOffset>,
+    to: DateTime<FixedOffset>,
+) -> Vec<Occurrence> {
+    let mut found: Vec<Occurrence> = scheduled(event, from, to)
+        .into_iter()
+        .filter(|(date, _)| match event.override_on(*date) {
+            Some(over) => over.start.is_none(),
+            None => true,
+        })
+        .map(|(date, start)| Occurrence::new(date, start, event))
+        .collect();
+    // An occurrence can be moved to any time, so its scheduled date may be far outside the window
+    for over in event.overrides.iter() {
+        match over.start {
+            Some(start) if start >= from && start < to => (),
+            _ => continue,
+        }
+        if let Some(occurrence) = occurrence_on(event, over.date) {
+            found.push(occurrence);
+        }
+    }
+    found.retain(|occurrence| !occurrence.cancelled);
+    found.sort_by_key(|occurrence| occurrence.start);
+    found
+}
+
+// The occurrence scheduled on the date in the event's zone, including if it was cancelled
+// None if the event isn't scheduled on the date
+pub fn occurrence_on(event: &Event, date: NaiveDate) -> Option<Occurrence> {
+    scheduled_on(event, date).map(|start| Occurrence::new(date, start, event))
+}
+
+// When the event was scheduled to start on the date in its zone, before any override
+// None if the event isn't scheduled on the date
+pub fn scheduled_on(event: &Event, date: NaiveDate) -> Option<DateTime<FixedOffset>> {
+    let midnight = resolve_local(event.timezone, date.and_time(NaiveTime::MIN))?;
+    scheduled(
+        event,
+        midnight - Duration::days(1),
+        midnight + Duration::days(2),
+    )
+    .into_iter()
+    .find(|(scheduled_date, _)| *scheduled_date == date)
+    .map(|(_, start)| start)
+}
+
+// The first occurrence of the event starting at or after from, None if it doesn't happen again
+//      within NEXT_OCCURRENCE_DAYS
+// Looks a week ahead and doubles the window until an occurrence is found, so only the dates up to
+//      the next occurrence are expanded and only that occurrence is built
+pub fn next_occurrence(event: &Event, from: DateTime<FixedOffset>) -> Option<Occurrence> {
+    let limit = from + Duration::days(NEXT_OCCURRENCE_DAYS);
+    let mut window_from = from;
+    let mut days = 7;
+    while window_from < limit {
+        let to = limit.min(from + Duration::days(days));
+        // The date and scheduled start of the first occurrence starting in the window, by the
+        //      start it has after its override
+        let scheduled = scheduled(event, window_from, to)
+            .into_iter()
+            .filter(|(date, _)| match event.override_on(*date) {
+                Some(over) => !over.cancelled && over.start.is_none(),
+                None => true,
+            })
+            .map(|(date, start)| (start, date, start));
+        let moved = event.overrides.iter().filter_map(|over| match over.start {
+            Some(start) if !over.cancelled && start >= window_from && start < to => {
+                scheduled_on(event, over.date).map(|scheduled| (start, over.date, scheduled))
+            }
+            _ => None,
+        });
+        if let Some((_, date, start)) = scheduled.chain(moved).min() {
+            return Some(Occurrence::new(date, start, event));
+        }
+        window_from = to;
+        days *= 2;
+    }
+    None
+}
+
+// The date and start of every time the event is scheduled to start inside [from, to), in order,
+//      before any overrides
+// Occurrences before the event's datetime or after its repeat_end or the rule's UNTIL are never
+//      returned
+fn scheduled(
+    event: &Event,
+    from: DateTime<FixedOffset>,
+    to: DateTime<FixedOffset>,
+) -> Vec<(NaiveDate, DateTime<FixedOffset>)> {
+    let start = event.datetime;
+    let zone = event.timezone;
+    let until = match &event.repeat_schedule {
+        Some(rule) => rule.until.and_then(|until| until.latest_start(zone)),
+        None => None,
+    };
+    let end = [event.repeat_end, until, Some(to)]
+        .into_iter()
+        .flatten()
+        .min()
+        .unwrap_or(to);
+    if end < from || start >= to {
+        return Vec::new();
+    }
+
+    // Recurrences are calculated on the wall clock of the event's zone so they keep the same
+    //      local start time when daylight saving begins or ends
+    let local_start = start.with_timezone(&zone);
+    let first = local_start.date_naive();
+    let time = local_start.time();
+    let from_date = from.with_timezone(&zone).date_naive();
+    let to_date = to.with_timezone(&zone).date_naive();
+
+    // The dates are padded by a day on each side for offsets, they are filtered down exactly below
+    let dates: Vec<NaiveDate> = match &event.repeat_schedule {
+        Some(rule) => rule.dates(
+            first,
+            zone,
+            from_date - Duration::days(1),
+            to_date + Duration::days(1),
+        ),
+        None => vec![first],
+    };
+
+    dates
+        .into_iter()
+        .filter_map(|date| {
+            // The first occurrence is exactly datetime, even if its wall clock time is ambiguous
+            if date == first {
+                Some((date, start))
+            } else {
+                resolve_local(zone, date.and_time(time)).map(|occurrence| (date, occurrence))
+            }
+        })
+        .filter(|(_, occurrence)| {
+            *occurrence >= start && *occurrence >= from && *occurrence < to && *occurrence <= end
+        })
+        .collect()
+}
+
+// Expands every event and returns the occurrences of all of them sorted by start
+pub fn expand(
+    events: &[Event],
+    from: DateTime<FixedOffset>,
+    to: DateTime<FixedOffset>,
+) -> Vec<Occurrence> {
+    let mut expanded: Vec<Occurrence> = events
+        .iter()
+        .flat_map(|event| occurrences(event, from, to))
+        .collect();
+    expanded.sort_by_key(|occurrence| occurrence.start);
+    expanded
+}
+
+// Returns the occurrence of each event in progress at the given time, sorted by start
+// Only occurrences which started within one duration of at can still be open, so only that
+//      window is expanded for each event
+pub fn open_at(events: &[Event], at: DateTime<FixedOffset>) -> Vec<Occurrence> {
+    let mut open: Vec<Occurrence> = events
+        .iter()
+        .flat_map(|event| {
+            let from = at - Duration::minutes(i64::from(event.duration_minutes));
+            occurrences(event, from, at + Duration::nanoseconds(1))
+        })
+        .filter(|occurrence| occurrence.is_open_at(at))
+        .collect();
+    open.sort_by_key(|occurrence| occurrence.start);
+    open
+}
+
+// endregion:   -- Expansion
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::{Day, OccurrenceOverride, ReoccurancePattern};
+
+    fn at(value: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(value).unwrap()
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(value, "%Y-%m-%d").unwrap()
+    }
+
+    fn event(datetime: &str, zone: Tz, rule: Option<&str>) -> Event {
+        let mut event =
+            Event::new(at(datetime), "Main Street".to_string(), None).with_timezone(zone);
+        event.repeat_schedule = rule.map(|rule| rule.parse().unwrap());
+        event
+    }
+
+    fn dates(event: &Event, from: &str, to: &str) -> Vec<NaiveDate> {
+        occurrences(event, at(from), at(to))
+            .into_iter()
+            .map(|occurrence| occurrence.date)
+            .collect()
+    }
+
+    #[test]
+    fn monthly_on_the_31st_skips_short_months() {
+        let event = event(
+            "2024-01-31T12:00:00Z",
+            Tz::UTC,
+            Some("FREQ=MONTHLY;BYMONTHDAY=31"),
+        );
+        assert_eq!(
+            dates(&event, "2024-01-01T00:00:00Z", "2024-06-01T00:00:00Z"),
+            vec![date("2024-01-31"), date("2024-03-31"), date("2024-05-31")]
+        );
+    }
+
+    #[test]
+    fn legacy_monthly_moves_to_the_last_day_of_short_months() {
+        let pattern = ReoccurancePattern::Monthly {
+            day_of_month: 31,
+            spacing: 1,
+        };
+        let mut event = event("2023-12-31T12:00:00Z", Tz::UTC, None);
+        event.repeat_schedule = pattern.to_rule(date("2023-12-31"));
+        assert_eq!(
+            dates(&event, "2024-01-01T00:00:00Z", "2024-05-01T00:00:00Z"),
+            vec![
+                date("2024-01-31"),
+                date("2024-02-29"),
+                date("2024-03-31"),
+                date("2024-04-30")
+            ]
+        );
+        assert_eq!(
+            dates(&event, "2025-02-01T00:00:00Z", "2025-03-01T00:00:00Z"),
+            vec![date("2025-02-28")]
+        );
+    }
+
+    #[test]
+    fn yearly_on_a_leap_day_only_happens_in_leap_years() {
+        let event = event("2024-02-29T09:00:00Z", Tz::UTC, Some("FREQ=YEARLY"));
+        assert_eq!(
+            dates(&event, "2024-01-01T00:00:00Z", "2033-01-01T00:00:00Z"),
+            vec![date("2024-02-29"), date("2028-02-29"), date("2032-02-29")]
+        );
+    }
+
+    #[test]
+    fn spacing_skips_periods() {
+        let rule = ReoccurancePattern::Weekly {
+            days: vec![Day::FRIDAY],
+            spacing: 2,
+        }
+        .to_rule(date("2024-05-03"));
+        let mut fortnightly = event("2024-05-03T10:00:00Z", Tz::UTC, None);
+        fortnightly.repeat_schedule = rule;
+        assert_eq!(
+            dates(&fortnightly, "2024-05-01T00:00:00Z", "2024-06-08T00:00:00Z"),
+            vec![date("2024-05-03"), date("2024-05-17"), date("2024-05-31")]
+        );
+
+        let quarterly = event(
+            "2024-01-15T10:00:00Z",
+            Tz::UTC,
+            Some("FREQ=MONTHLY;INTERVAL=3"),
+        );
+        assert_eq!(
+            dates(&quarterly, "2024-02-01T00:00:00Z", "2025-01-01T00:00:00Z"),
+            vec![date("2024-04-15"), date("2024-07-15"), date("2024-10-15")]
+        );
+    }
+
+    #[test]
+    fn repeats_keep_the_wall_clock_across_daylight_saving() {
+        let event = event(
+            "2024-03-01T10:00:00-05:00",
+            chrono_tz::America::New_York,
+            Some("FREQ=WEEKLY"),
+        );
+        let starts: Vec<String> = occurrences(
+            &event,
+            at("2024-03-01T00:00:00Z"),
+            at("2024-03-16T00:00:00Z"),
+        )
+        .into_iter()
+        .map(|occurrence| occurrence.start.to_rfc3339())
+        .collect();
+        assert_eq!(
+            starts,
+            vec![
+                "2024-03-01T10:00:00-05:00",
+                "2024-03-08T10:00:00-05:00",
+                "2024-03-15T10:00:00-04:00"
+            ]
+        );
+    }
+
+    #[test]
+    fn repeat_end_is_the_last_start() {
+        let mut event = event("2024-05-01T10:00:00Z", Tz::UTC, Some("FREQ=DAILY"));
+        event.repeat_end = Some(at("2024-05-03T10:00:00Z"));
+        assert_eq!(
+            dates(&event, "2024-04-01T00:00:00Z", "2024-06-01T00:00:00Z"),
+            vec![date("2024-05-01"), date("2024-05-02"), date("2024-05-03")]
+        );
+    }
+
+    #[test]
+    fn occurrence_on_only_finds_scheduled_dates() {
+        let mut event = event("2024-05-03T10:00:00Z", Tz::UTC, Some("FREQ=WEEKLY"));
+        let mut cancelled = OccurrenceOverride::new(date("2024-05-10"));
+        cancelled.cancelled = true;
+        event.overrides.push(cancelled);
+
+        assert!(occurrence_on(&event, date("2024-05-04")).is_none());
+        assert!(occurrence_on(&event, date("2024-04-26")).is_none());
+        let occurrence = occurrence_on(&event, date("2024-05-10")).unwrap();
+        assert!(occurrence.cancelled);
+        assert_eq!(
+            dates(&event, "2024-05-01T00:00:00Z", "2024-05-18T00:00:00Z"),
+            vec![date("2024-05-03"), date("2024-05-17")]
+        );
+    }
+
+    #[test]
+    fn moved_occurrences_are_found_by_their_new_start() {
+        let mut event = event("2024-05-03T10:00:00Z", Tz::UTC, Some("FREQ=WEEKLY"));
+        let mut moved = OccurrenceOverride::new(date("2024-05-10"));
+        moved.start = Some(at("2024-06-01T10:00:00Z"));
+        event.overrides.push(moved);
+
+        assert_eq!(
+            dates(&event, "2024-05-08T00:00:00Z", "2024-05-12T00:00:00Z"),
+            Vec::<NaiveDate>::new()
+        );
+        let found = occurrences(
+            &event,
+            at("2024-06-01T00:00:00Z"),
+            at("2024-06-02T00:00:00Z"),
+        );
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].date, date("2024-05-10"));
+        assert_eq!(
+            found[0].end,
+            at("2024-06-01T10:00:00Z") + Duration::minutes(i64::from(event.duration_minutes))
+        );
+    }
+
+    #[test]
+    fn open_at_covers_start_up_to_end() {
+        let mut late = event("2024-05-03T23:00:00Z", Tz::UTC, Some("FREQ=DAILY"));
+        late.duration_minutes = 120;
+        let events = vec![late];
+
+        assert_eq!(open_at(&events, at("2024-05-03T22:59:59Z")).len(), 0);
+        assert_eq!(open_at(&events, at("2024-05-03T23:00:00Z")).len(), 1);
+        // Still open past midnight, on the occurrence scheduled the day before
+        let open = open_at(&events, at("2024-05-05T00:30:00Z"));
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].date, date("2024-05-04"));
+        assert_eq!(open_at(&events, at("2024-05-05T01:00:00Z")).len(), 0);
+    }
+
+    // The first occurrence of expanding the whole window next_occurrence looks through
+    fn expanded_next(event: &Event, from: &str) -> Option<Occurrence> {
+        let from = at(from);
+        occurrences(event, from, from + Duration::days(NEXT_OCCURRENCE_DAYS))
+            .into_iter()
+            .next()
+    }
+
+    #[test]
+    fn next_occurrence_is_the_first_expanded_one() {
+        let mut weekly = event("2024-05-03T10:00:00Z", Tz::UTC, Some("FREQ=WEEKLY"));
+        let mut cancelled = OccurrenceOverride::new(date("2024-05-10"));
+        cancelled.cancelled = true;
+        let mut later = OccurrenceOverride::new(date("2024-05-17"));
+        later.start = Some(at("2024-05-30T10:00:00Z"));
+        // Moved from months away to before the next scheduled occurrence
+        let mut earlier = OccurrenceOverride::new(date("2024-09-06"));
+        earlier.start = Some(at("2024-05-22T10:00:00Z"));
+        weekly.overrides = vec![cancelled, later, earlier];
+        let yearly = event("2024-02-29T10:00:00Z", Tz::UTC, Some("FREQ=YEARLY"));
+        let finished = event("2024-05-03T10:00:00Z", Tz::UTC, Some("FREQ=DAILY;COUNT=3"));
+
+        for (event, from) in [
+            (&weekly, "2024-01-01T00:00:00Z"),
+            (&weekly, "2024-05-03T10:00:00Z"),
+            (&weekly, "2024-05-03T10:00:01Z"),
+            (&weekly, "2024-05-22T10:00:01Z"),
+            (&weekly, "2024-05-30T09:00:00Z"),
+            (&yearly, "2024-03-01T00:00:00Z"),
+            (&yearly, "2027-03-01T00:00:00Z"),
+            (&finished, "2024-05-04T12:00:00Z"),
+            (&finished, "2024-05-06T00:00:00Z"),
+        ] {
+            assert_eq!(
+                next_occurrence(event, at(from)),
+                expanded_next(event, from),
+                "{from}"
+            );
+        }
+
+        let next = next_occurrence(&weekly, at("2024-05-03T10:00:01Z")).unwrap();
+        assert_eq!(next.date, date("2024-09-06"));
+        assert_eq!(next.start, at("2024-05-22T10:00:00Z"));
+        // Leap days more than a year away aren't looked for
+        assert!(next_occurrence(&yearly, at("2024-03-01T00:00:00Z")).is_none());
+        assert!(next_occurrence(&finished, at("2024-05-06T00:00:00Z")).is_none());
+    }
+}
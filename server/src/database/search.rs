@@ -0,0 +1,513 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+use crate::database::models::{Item, Menu, Vendor};
+use serde::Serialize;
+
+// How much a match in each field counts, names say more about a record than descriptions
+const NAME_WEIGHT: f64 = 3.0;
+const TYPE_WEIGHT: f64 = 2.0;
+const DESCRIPTION_WEIGHT: f64 = 1.0;
+
+// How well a word of the record has to match a word of the query, and what each kind is worth
+//      exact       -> the same word
+//      prefix      -> the query is the start of the word, so "taq" finds "taqueria"
+//      typo        -> within one edit, or two for words of 8 or more letters
+const EXACT_MATCH: f64 = 1.0;
+const PREFIX_MATCH: f64 = 0.8;
+const TYPO_MATCH: f64 = 0.6;
+
+// The text fields searched on each kind of record
+pub const VENDOR_FIELDS: &[&str] = &["name", "vendor_type", "description"];
+pub const MENU_FIELDS: &[&str] = &["name"];
+pub const ITEM_FIELDS: &[&str] = &["name", "description"];
+
+// Vendors are also found through what they sell, at a discount to matching the vendor itself
+const SOLD_BY_WEIGHT: f64 = 0.5;
+
+// Matches in highlighted fields are wrapped in these, everything else in the field is escaped
+//      so the highlight can be inserted as html
+const MARK_START: &str = "<mark>";
+const MARK_END: &str = "</mark>";
+
+// A record which matched the query
+//      score       -> higher is a better match, only comparable within one search
+//      highlights  -> each field which matched with the matching words marked
+//      via         -> for vendors, the names of their menus and items which matched
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub uuid: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<String>,
+    pub score: f64,
+    pub highlights: BTreeMap<&'static str, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub via: Vec<String>,
+}
+
+// Hits grouped by the kind of record, each group sorted best match first
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SearchResults {
+    pub query: String,
+    pub vendors: Vec<SearchHit>,
+    pub menus: Vec<SearchHit>,
+    pub items: Vec<SearchHit>,
+}
+
+// Searches vendor names, types and descriptions, menu names and item names and descriptions
+// A record matches when any word of the query matches, records matching more of the words rank
+//      first and ties are broken by score and then name
+// limit applies to each group separately
+pub fn search(
+    query: &str,
+    vendors: &[Vendor],
+    menus: &[Menu],
+    items: &[Item],
+    limit: usize,
+) -> SearchResults {
+    let terms: Vec<String> = words(query).into_iter().map(|(word, _)| word).collect();
+
+    let mut item_hits: Vec<Ranked> = items
+        .iter()
+        .filter_map(|item| {
+            rank(
+                &terms,
+                &item.uuid,
+                &item.name,
+                owner(&item.vendor),
+                &[
+                    ("name", &item.name, NAME_WEIGHT),
+                    ("description", &item.description, DESCRIPTION_WEIGHT),
+                ],
+            )
+        })
+        .collect();
+    let mut menu_hits: Vec<Ranked> = menus
+        .iter()
+        .filter_map(|menu| {
+            rank(
+                &terms,
+                &menu.uuid,
+                &menu.name,
+                owner(&menu.vendor),
+                &[("name", &menu.name, NAME_WEIGHT)],
+            )
+        })
+        .collect();
+
+    // The best menu or item match of each vendor, along with the names of everything which matched
+    let mut sold: HashMap<&str, (Ranked, Vec<String>)> = HashMap::new();
+    for hit in item_hits.iter().chain(menu_hits.iter()) {
+        let vendor = match &hit.hit.vendor {
+            Some(vendor) => vendor.as_str(),
+            None => continue,
+        };
+        match sold.get_mut(vendor) {
+            Some((best, names)) => {
+                names.push(hit.hit.name.clone());
+                if hit.cmp(best) == Ordering::Less {
+                    *best = hit.clone();
+                }
+            }
+            None => {
+                sold.insert(vendor, (hit.clone(), vec![hit.hit.name.clone()]));
+            }
+        }
+    }
+
+    let mut vendor_hits: Vec<Ranked> = vendors
+        .iter()
+        .filter_map(|vendor| {
+            let own = rank(
+                &terms,
+                &vendor.uuid,
+                &vendor.name,
+                None,
+                &[
+                    ("name", &vendor.name, NAME_WEIGHT),
+                    ("vendor_type", &vendor.vendor_type, TYPE_WEIGHT),
+                    ("description", &vendor.description, DESCRIPTION_WEIGHT),
+                ],
+            );
+            let sold = sold.get(vendor.uuid.as_ref());
+            let mut ranked = match (own, sold) {
+                (Some(own), _) => own,
+                (None, Some((best, _))) => Ranked {
+                    matched_terms: best.matched_terms,
+                    hit: SearchHit {
+                        uuid: vendor.uuid.to_string(),
+                        name: vendor.name.to_string(),
+                        vendor: None,
+                        score: 0.0,
+                        highlights: BTreeMap::new(),
+                        via: Vec::new(),
+                    },
+                },
+                (None, None) => return None,
+            };
+            if let Some((best, names)) = sold {
+                ranked.matched_terms = ranked.matched_terms.max(best.matched_terms);
+                ranked.hit.score += best.hit.score * SOLD_BY_WEIGHT;
+                ranked.hit.via = names.clone();
+                ranked.hit.via.sort();
+            }
+            Some(ranked)
+        })
+        .collect();
+
+    SearchResults {
+        query: query.to_string(),
+        vendors: best(&mut vendor_hits, limit),
+        menus: best(&mut menu_hits, limit),
+        items: best(&mut item_hits, limit),
+    }
+}
+
+// Lowercase pieces of the query's words, any record search can match contains one of them in a
+//      searched field, so stores can narrow down what is ranked without ranking it themselves
+// Exact and prefix matches contain the whole word, a typo within n edits leaves one of 2n + 1
+//      pieces of the word untouched since every edit, even a swap, touches at most two of them
+pub fn fragments(query: &str) -> Vec<String> {
+    let mut fragments: Vec<String> = Vec::new();
+    for (term, _) in words(query) {
+        let letters: Vec<char> = term.chars().collect();
+        let pieces = 2 * allowed_edits(letters.len()) + 1;
+        for piece in 0..pieces {
+            let start = piece * letters.len() / pieces;
+            let end = (piece + 1) * letters.len() / pieces;
+            fragments.push(letters[start..end].iter().collect());
+        }
+    }
+    fragments.sort();
+    fragments.dedup();
+    // Text containing a fragment contains every fragment inside it, only the shortest is needed
+    let shortest: Vec<String> = fragments
+        .iter()
+        .filter(|fragment| {
+            !fragments
+                .iter()
+                .any(|other| other != *fragment && fragment.contains(other.as_str()))
+        })
+        .cloned()
+        .collect();
+    shortest
+}
+
+#[derive(Debug, Clone)]
+struct Ranked {
+    matched_terms: usize,
+    hit: SearchHit,
+}
+
+impl Ranked {
+    // Better matches sort first
+    fn cmp(&self, other: &Ranked) -> Ordering {
+        other
+            .matched_terms
+            .cmp(&self.matched_terms)
+            .then_with(|| other.hit.score.total_cmp(&self.hit.score))
+            .then_with(|| self.hit.name.cmp(&other.hit.name))
+    }
+}
+
+fn best(hits: &mut Vec<Ranked>, limit: usize) -> Vec<SearchHit> {
+    hits.sort_by(Ranked::cmp);
+    hits.drain(..)
+        .take(limit)
+        .map(|ranked| ranked.hit)
+        .collect()
+}
+
+fn owner(vendor: &Option<surrealdb::sql::Thing>) -> Option<String> {
+    vendor.as_ref().map(|vendor| vendor.id.to_raw())
+}
+
+// Scores a record against every term, returning None if no term matched any of its fields
+// Each term counts once, for the field it matched best
+fn rank(
+    terms: &[String],
+    uuid: &str,
+    name: &str,
+    vendor: Option<String>,
+    fields: &[(&'static str, &str, f64)],
+) -> Option<Ranked> {
+    let fields: Vec<(&'static str, &str, f64, Vec<Word>)> = fields
+        .iter()
+        .map(|(field, text, weight)| (*field, *text, *weight, words(text)))
+        .collect();
+
+    let mut matched_terms = 0;
+    let mut score = 0.0;
+    // The ranges of the words which matched in each field
+    let mut marks: BTreeMap<&'static str, Vec<Range<usize>>> = BTreeMap::new();
+    for term in terms {
+        let mut best_score = 0.0;
+        for (field, _, weight, words) in fields.iter() {
+            for (word, range) in words {
+                let quality = match_quality(term, word);
+                if quality > 0.0 {
+                    marks.entry(field).or_default().push(range.clone());
+                }
+                best_score = f64::max(best_score, quality * weight);
+            }
+        }
+        if best_score > 0.0 {
+            matched_terms += 1;
+            score += best_score;
+        }
+    }
+    if matched_terms == 0 {
+        return None;
+    }
+
+    let highlights = fields
+        .iter()
+        .filter_map(|(field, text, _, _)| {
+            let ranges = marks.get(field)?;
+            Some((*field, highlight(text, ranges)))
+        })
+        .collect();
+    Some(Ranked {
+        matched_terms,
+        hit: SearchHit {
+            uuid: uuid.to_string(),
+            name: name.to_string(),
+            vendor,
+            score,
+            highlights,
+            via: Vec::new(),
+        },
+    })
+}
+
+// How well a word from a record matches a term from the query, 0 if it doesn't
+fn match_quality(term: &str, word: &str) -> f64 {
+    if term == word {
+        return EXACT_MATCH;
+    }
+    let term_length = term.chars().count();
+    if term_length >= 2 && word.starts_with(term) {
+        return PREFIX_MATCH;
+    }
+    let allowed_edits = allowed_edits(term_length);
+    if allowed_edits > 0 && edit_distance(term, word, allowed_edits) <= allowed_edits {
+        return TYPO_MATCH;
+    }
+    0.0
+}
+
+// How many typos a term of the length can have and still match
+// Short words are too easy to reach with a typo, "cat" would match "car", "hat" and "bat"
+fn allowed_edits(term_length: usize) -> usize {
+    match term_length {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+// The number of single letter insertions, deletions, substitutions and swaps of neighbouring
+//      letters needed to turn a into b, anything over limit is reported as limit + 1
+fn edit_distance(a: &str, b: &str, limit: usize) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > limit {
+        return limit + 1;
+    }
+
+    let mut before_previous: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let substitution = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + substitution);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before_previous[j - 2] + 1);
+            }
+        }
+        // Every path through this row is already too long
+        if current.iter().all(|distance| *distance > limit) {
+            return limit + 1;
+        }
+        before_previous = previous;
+        previous = current;
+    }
+    previous[b.len()].min(limit + 1)
+}
+
+// A lowercased word and where it is in the text it came from
+type Word = (String, Range<usize>);
+
+// The lowercased words of the text with where each one is in the text
+// Words are runs of letters and digits, so "Smoke-house BBQ!" is smoke, house and bbq
+fn words(text: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut start: Option<usize> = None;
+    for (index, character) in text.char_indices() {
+        match (character.is_alphanumeric(), start) {
+            (true, None) => start = Some(index),
+            (false, Some(word_start)) => {
+                words.push((text[word_start..index].to_lowercase(), word_start..index));
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    if let Some(word_start) = start {
+        words.push((text[word_start..].to_lowercase(), word_start..text.len()));
+    }
+    words
+}
+
+// The text with every range wrapped in a mark and everything else html escaped
+fn highlight(text: &str, ranges: &[Range<usize>]) -> String {
+    let mut ranges = ranges.to_vec();
+    ranges.sort_by_key(|range| range.start);
+    ranges.dedup();
+
+    let mut highlighted = String::with_capacity(text.len());
+    let mut position = 0;
+    for range in ranges {
+        if range.start < position {
+            continue;
+        }
+        highlighted.push_str(&escape(&text[position..range.start]));
+        highlighted.push_str(MARK_START);
+        highlighted.push_str(&escape(&text[range.clone()]));
+        highlighted.push_str(MARK_END);
+        position = range.end;
+    }
+    highlighted.push_str(&escape(&text[position..]));
+    highlighted
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::store::{contains_fragment, vendor_thing};
+
+    fn vendor(name: &str) -> Vendor {
+        Vendor::new(name.to_string())
+    }
+
+    fn names(hits: &[SearchHit]) -> Vec<&str> {
+        hits.iter().map(|hit| hit.name.as_str()).collect()
+    }
+
+    #[test]
+    fn exact_matches_beat_prefixes_which_beat_typos() {
+        let vendors = [vendor("Taca Stand"), vendor("Tacoria"), vendor("Taco Town")];
+        let results = search("taco", &vendors, &[], &[], 10);
+        assert_eq!(
+            names(&results.vendors),
+            vec!["Taco Town", "Tacoria", "Taca Stand"]
+        );
+        let scores: Vec<f64> = results.vendors.iter().map(|hit| hit.score).collect();
+        assert_eq!(
+            scores,
+            vec![
+                EXACT_MATCH * NAME_WEIGHT,
+                PREFIX_MATCH * NAME_WEIGHT,
+                TYPO_MATCH * NAME_WEIGHT
+            ]
+        );
+    }
+
+    #[test]
+    fn swapped_letters_are_one_typo() {
+        let items = [Item::new("Tacos".to_string(), None)];
+        let results = search("tacso", &[], &[], &items, 10);
+        assert_eq!(names(&results.items), vec!["Tacos"]);
+        assert_eq!(results.items[0].highlights["name"], "<mark>Tacos</mark>");
+        // Short words don't match with typos
+        assert!(search("tca", &[], &[], &items, 10).items.is_empty());
+    }
+
+    #[test]
+    fn highlights_escape_everything_around_the_mark() {
+        let vendors = [vendor("<script>Tacos</script> & \"Co\"")];
+        let results = search("tacos", &vendors, &[], &[], 10);
+        assert_eq!(
+            results.vendors[0].highlights["name"],
+            "&lt;script&gt;<mark>Tacos</mark>&lt;/script&gt; &amp; &quot;Co&quot;"
+        );
+    }
+
+    #[test]
+    fn menu_and_item_hits_group_under_their_vendor() {
+        let seller = vendor("Blue Van");
+        let owner = Some(vendor_thing(&seller.uuid));
+        let mut menu = Menu::new(owner.clone());
+        menu.name = "Burrito Combos".into();
+        let items = [
+            Item::new("Burrito".to_string(), owner),
+            Item::new("Burrito".to_string(), None),
+        ];
+        let results = search(
+            "burrito",
+            &[seller.clone(), vendor("Red Van")],
+            &[menu],
+            &items,
+            10,
+        );
+        assert_eq!(results.items.len(), 2);
+        assert_eq!(
+            results.menus[0].vendor.as_deref(),
+            Some(seller.uuid.as_ref())
+        );
+        assert_eq!(names(&results.vendors), vec!["Blue Van"]);
+        let hit = &results.vendors[0];
+        assert_eq!(hit.via, vec!["Burrito", "Burrito Combos"]);
+        assert_eq!(hit.score, EXACT_MATCH * NAME_WEIGHT * SOLD_BY_WEIGHT);
+        assert!(hit.highlights.is_empty());
+    }
+
+    #[test]
+    fn everything_search_matches_contains_a_fragment() {
+        let items: Vec<Item> = ["Tacos", "Taqueria Special", "Chilaquiles Verdes", "Elote"]
+            .iter()
+            .map(|name| Item::new(name.to_string(), None))
+            .collect();
+        for query in [
+            "tacso",
+            "taq",
+            "chilaqiules",
+            "chilaquliesx",
+            "elote",
+            "elot",
+        ] {
+            let fragments = fragments(query);
+            let results = search(query, &[], &[], &items, 10);
+            assert!(!results.items.is_empty(), "{query}");
+            for hit in results.items.iter() {
+                let item = items.iter().find(|item| item.uuid == hit.uuid).unwrap();
+                assert!(
+                    contains_fragment(item, ITEM_FIELDS, &fragments).unwrap(),
+                    "{query} matched {} but none of {fragments:?}",
+                    hit.name
+                );
+            }
+        }
+        assert_eq!(fragments("Taco taco"), vec!["a", "co", "t"]);
+    }
+}
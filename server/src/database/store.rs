@@ -0,0 +1,413 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::database::models::{Event, Item, Menu, OccurrenceOverride, Record, Vendor};
+use crate::database::schedule::parse_datetime;
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use geoutils::Location;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use surrealdb::sql::Thing;
+
+// The records stored by the API, each one lives in its own table keyed by its uuid
+pub trait Model: Serialize + DeserializeOwned + Clone + Send + Sync + 'static {
+    const TABLE: &'static str;
+
+    // The fields the record is serialized with, which collections can be projected to
+    const FIELDS: &'static [&'static str];
+
+    fn uuid(&self) -> &str;
+
+    // The vendor which owns the record, vendors own themselves
+    fn vendor(&self) -> Option<Thing>;
+}
+
+impl Model for Vendor {
+    const TABLE: &'static str = "vendors";
+    const FIELDS: &'static [&'static str] = &[
+        "uuid",
+        "name",
+        "description",
+        "vendor_type",
+        "email",
+        "phone_number",
+        "website",
+        "events",
+        "menus",
+        "items",
+    ];
+
+    fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    fn vendor(&self) -> Option<Thing> {
+        Some(vendor_thing(&self.uuid))
+    }
+}
+
+impl Model for Event {
+    const TABLE: &'static str = "events";
+    const FIELDS: &'static [&'static str] = &[
+        "uuid",
+        "name",
+        "datetime",
+        "timezone",
+        "duration_minutes",
+        "location",
+        "cord_x",
+        "cord_y",
+        "menu",
+        "repeat_schedule",
+        "repeat_end",
+        "overrides",
+        "vendor",
+    ];
+
+    fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    fn vendor(&self) -> Option<Thing> {
+        self.vendor.clone()
+    }
+}
+
+impl Model for Menu {
+    const TABLE: &'static str = "menus";
+    const FIELDS: &'static [&'static str] = &["uuid", "name", "items", "vendor"];
+
+    fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    fn vendor(&self) -> Option<Thing> {
+        self.vendor.clone()
+    }
+}
+
+impl Model for Item {
+    const TABLE: &'static str = "items";
+    const FIELDS: &'static [&'static str] =
+        &["uuid", "name", "description", "price", "picture", "vendor"];
+
+    fn uuid(&self) -> &str {
+        &self.uuid
+    }
+
+    fn vendor(&self) -> Option<Thing> {
+        self.vendor.clone()
+    }
+}
+
+pub fn vendor_thing(vendor_id: &str) -> Thing {
+    Thing {
+        tb: "vendors".into(),
+        id: vendor_id.into(),
+    }
+}
+
+// region:      -- Paging
+
+// What records are ordered by before their uuid
+//      Name        -> Case insensitively
+//      Datetime    -> The instant the event starts, whatever its offset
+//      Distance    -> Meters from the query's origin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortField {
+    Name,
+    Datetime,
+    Distance,
+}
+
+// A page of one table, as asked for by a collection endpoint
+//      vendor      -> Only the records owned by the vendor
+//      limit       -> The most records on the page
+//      after       -> The uuid of the last record on the previous page
+//      sort        -> Ordered by this and then by uuid so pages are stable, by uuid alone without it
+//      descending  -> Both orders are reversed
+//      origin      -> The latitude and longitude Distance is measured from
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageQuery {
+    pub vendor: Option<String>,
+    pub limit: usize,
+    pub after: Option<String>,
+    pub sort: Option<SortField>,
+    pub descending: bool,
+    pub origin: Option<(f64, f64)>,
+}
+
+// The records on a page, total counts every record the query pages through
+// next_cursor is the after of the next page, only set when there are more records
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing<T> {
+    pub records: Vec<T>,
+    pub next_cursor: Option<String>,
+    pub total: usize,
+}
+
+// Pages records which are already in memory, the same way the stores page their tables
+pub fn page_records<T: Model>(
+    records: Vec<T>,
+    query: &PageQuery,
+) -> Result<Listing<T>, StoreError> {
+    let vendor = query.vendor.as_deref().map(vendor_thing);
+    let mut records: Vec<(Value, T)> = records
+        .into_iter()
+        .filter(|record| vendor.is_none() || record.vendor() == vendor)
+        .map(|record| Ok((serde_json::to_value(&record)?, record)))
+        .collect::<Result<_, StoreError>>()?;
+    let total = records.len();
+
+    records.sort_by(|(a, _), (b, _)| {
+        let ordering = match query.sort {
+            Some(SortField::Name) => text(a, "name").cmp(&text(b, "name")),
+            Some(SortField::Datetime) => compare_datetime(a, b),
+            Some(SortField::Distance) => match query.origin {
+                Some(origin) => distance(a, origin).total_cmp(&distance(b, origin)),
+                None => Ordering::Equal,
+            },
+            None => Ordering::Equal,
+        }
+        .then_with(|| uuid(a).cmp(uuid(b)));
+        match query.descending {
+            true => ordering.reverse(),
+            false => ordering,
+        }
+    });
+
+    let start = match &query.after {
+        Some(after) => match records.iter().position(|(record, _)| uuid(record) == after) {
+            Some(position) => position + 1,
+            None => return Err(StoreError::unknown_cursor()),
+        },
+        None => 0,
+    };
+    let records: Vec<T> = records
+        .into_iter()
+        .skip(start)
+        .take(query.limit)
+        .map(|(_, record)| record)
+        .collect();
+    let next_cursor = match start + records.len() < total {
+        true => records.last().map(|record| record.uuid().to_string()),
+        false => None,
+    };
+    Ok(Listing {
+        records,
+        next_cursor,
+        total,
+    })
+}
+
+fn uuid(record: &Value) -> &str {
+    record.get("uuid").and_then(Value::as_str).unwrap_or("")
+}
+
+fn text(record: &Value, field: &str) -> String {
+    record
+        .get(field)
+        .and_then(Value::as_str)
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+// Records without a valid datetime are sorted after those with one
+fn compare_datetime(a: &Value, b: &Value) -> Ordering {
+    let a = a
+        .get("datetime")
+        .and_then(Value::as_str)
+        .and_then(parse_datetime);
+    let b = b
+        .get("datetime")
+        .and_then(Value::as_str)
+        .and_then(parse_datetime);
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+// Records without coordinates are treated as infinitely far away
+fn distance(record: &Value, (lat, lon): (f64, f64)) -> f64 {
+    match (
+        record.get("cord_y").and_then(Value::as_f64),
+        record.get("cord_x").and_then(Value::as_f64),
+    ) {
+        (Some(cord_y), Some(cord_x)) => Location::new(cord_y, cord_x)
+            .haversine_distance_to(&Location::new(lat, lon))
+            .meters(),
+        _ => f64::INFINITY,
+    }
+}
+
+// endregion:   -- Paging
+
+// Whether one of the fragments is in one of the record's text fields, ignoring case
+// Fragments are expected to be lowercase already
+pub fn contains_fragment<T: Model>(
+    record: &T,
+    fields: &[&'static str],
+    fragments: &[String],
+) -> Result<bool, StoreError> {
+    let record = serde_json::to_value(record)?;
+    Ok(fields.iter().any(|field| {
+        let text = text(&record, field);
+        fragments
+            .iter()
+            .any(|fragment| text.contains(fragment.as_str()))
+    }))
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    Database(Box<surrealdb::Error>),
+    Conflict(String),
+    Serialization(String),
+    // The record was rejected by the schema
+    Invalid { field: String, message: String },
+    // The database can't be reached, the request can be retried later
+    Unavailable(String),
+    // The database can't be used by this build, such as its schema being newer
+    Incompatible(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Database(err) => write!(f, "{err}"),
+            StoreError::Invalid { field, message } => write!(f, "{field}: {message}"),
+            StoreError::Conflict(message)
+            | StoreError::Serialization(message)
+            | StoreError::Unavailable(message)
+            | StoreError::Incompatible(message) => {
+                write!(f, "{message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl StoreError {
+    // The after of a page isn't the uuid of any record the query pages through
+    pub fn unknown_cursor() -> Self {
+        StoreError::Invalid {
+            field: "after".to_string(),
+            message: "does not match any record, restart from the first page".to_string(),
+        }
+    }
+}
+
+impl From<surrealdb::Error> for StoreError {
+    fn from(err: surrealdb::Error) -> Self {
+        // Remote errors only come back as strings so the message has to be checked
+        match &err {
+            surrealdb::Error::Db(surrealdb::error::Db::RecordExists { thing }) => {
+                StoreError::Conflict(format!("{thing} already exists"))
+            }
+            surrealdb::Error::Api(surrealdb::error::Api::Query(message))
+                if message.contains("already exists") =>
+            {
+                StoreError::Conflict(message.to_owned())
+            }
+            // Schema violations read "Found ... for field `name`, with record ..., but ..."
+            surrealdb::Error::Api(surrealdb::error::Api::Query(message))
+                if message.contains("for field `") =>
+            {
+                StoreError::Invalid {
+                    field: message.split('`').nth(1).unwrap_or("body").to_string(),
+                    message: message.to_owned(),
+                }
+            }
+            surrealdb::Error::Api(
+                surrealdb::error::Api::Ws(_) | surrealdb::error::Api::ConnectionUninitialised,
+            ) => StoreError::Unavailable(err.to_string()),
+            _ => StoreError::Database(Box::new(err)),
+        }
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(err: serde_json::Error) -> Self {
+        StoreError::Serialization(err.to_string())
+    }
+}
+
+// CRUD access to a single table
+#[async_trait]
+pub trait Repository<T: Model>: Send + Sync {
+    async fn get(&self, id: &str) -> Result<Option<T>, StoreError>;
+
+    // The records stored under the uuids in one read, uuids which don't exist are skipped
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<T>, StoreError>;
+
+    async fn list(&self) -> Result<Vec<T>, StoreError>;
+
+    // Every record owned by the vendor
+    async fn list_by_vendor(&self, vendor_id: &str) -> Result<Vec<T>, StoreError>;
+
+    // Every record owned by any of the vendors, in one read
+    async fn list_by_vendors(&self, vendor_ids: &[String]) -> Result<Vec<T>, StoreError>;
+
+    // One page of the table, only that page is read
+    async fn page(&self, query: &PageQuery) -> Result<Listing<T>, StoreError>;
+
+    // At most limit records with one of the fragments in one of the text fields, ignoring case
+    // Narrows down what search ranks so it doesn't read the whole table, see contains_fragment
+    async fn containing(
+        &self,
+        fields: &[&'static str],
+        fragments: &[String],
+        limit: usize,
+    ) -> Result<Vec<T>, StoreError>;
+
+    // Records are stored under their uuid, storing a uuid which is taken is a conflict
+    async fn create(&self, record: T) -> Result<Record, StoreError>;
+
+    // Creates the record or replaces the one stored under its uuid, so imports can be rerun
+    async fn upsert(&self, record: T) -> Result<(), StoreError>;
+
+    // Replaces each top level field named by a json pointer such as /name with its value
+    // Returns None without creating anything if the record doesn't exist
+    async fn patch(&self, id: &str, patches: Vec<(String, Value)>)
+        -> Result<Option<T>, StoreError>;
+
+    async fn delete(&self, id: &str) -> Result<Option<T>, StoreError>;
+}
+
+// Everything the API needs from a database
+// Implemented by SurrealStore for production and MemoryStore for running without a database
+#[async_trait]
+pub trait Store:
+    Repository<Vendor> + Repository<Event> + Repository<Menu> + Repository<Item> + Send + Sync
+{
+    // Returns None if the menu doesn't exist, links to deleted items are skipped
+    async fn items_by_menu(&self, menu_id: &str) -> Result<Option<Vec<Item>>, StoreError>;
+
+    async fn vendors_by_email(&self, email: &str) -> Result<Vec<Vendor>, StoreError>;
+
+    // Replaces the event's override of the occurrence on date, or removes it when over is None
+    // Done in one write so overrides of other occurrences written at the same time are kept
+    // Returns None if the event doesn't exist
+    async fn set_override(
+        &self,
+        event_id: &str,
+        date: NaiveDate,
+        over: Option<OccurrenceOverride>,
+    ) -> Result<Option<Event>, StoreError>;
+
+    // A round trip to the database, used by the readiness check
+    async fn ping(&self) -> Result<(), StoreError>;
+
+    // Ends the session during shutdown, requests made afterwards fail as unavailable
+    async fn close(&self) {}
+
+    // Resolves if the store can never become available, the server shuts down when it does
+    async fn failed(&self) -> String {
+        std::future::pending().await
+    }
+}
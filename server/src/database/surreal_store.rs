@@ -0,0 +1,652 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::config::{DatabaseConfig, Secret};
+use crate::database::migrations::{self, MigrationError};
+use crate::database::models::{Event, Item, OccurrenceOverride, Record, Vendor};
+use crate::database::store::{
+    vendor_thing, Listing, Model, PageQuery, Repository, SortField, Store, StoreError,
+};
+use crate::server::monitoring::{DB_ERRORS_TOTAL, DB_QUERY_DURATION_SECONDS};
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use metrics::{counter, histogram};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use surrealdb::{
+    engine::remote::ws::{Client, Ws},
+    opt::auth::Root,
+    sql::Thing,
+    Surreal,
+};
+use tokio::sync::{watch, Notify};
+use tokio::time::{sleep, timeout};
+use tracing::{error, info, warn};
+
+// How long to wait between connection attempts at startup, doubling after each failure
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// How often the connection is checked once it has been made and how long the check can take
+// The client reconnects dropped WebSockets by itself but requests sent while it does so wait
+//      until it succeeds, the check lets requests fail fast with a 503 in the meantime
+const HEALTH_INTERVAL: Duration = Duration::from_secs(5);
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Replaces the override on $date with the ones in $added, at most one, in a single write
+// Dates are stored as strings such as 2024-05-01 so they sort in order
+const SET_OVERRIDE: &str = "UPDATE type::thing($table, $id) SET overrides = (\
+        SELECT * FROM array::concat(overrides[WHERE date != $date], $added) ORDER BY date\
+    ) WHERE uuid != NONE";
+
+// Requests taking longer than this are treated as the database being unavailable
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+pub struct SurrealStore {
+    // Taken on close, the client closes the WebSocket once the last copy of it is dropped
+    db: Arc<RwLock<Option<Surreal<Client>>>>,
+    available: Arc<AtomicBool>,
+    // Stops the connection supervisor during close
+    closing: Arc<Notify>,
+    // Set when the database can never be used by this build, see Store::failed
+    failure: Arc<watch::Sender<Option<String>>>,
+}
+
+impl SurrealStore {
+    // Returns straight away and connects in the background so the API can start before the
+    //      database, requests are answered with 503 until the connection is made
+    pub fn connect(config: DatabaseConfig) -> Self {
+        let store = SurrealStore {
+            db: Arc::new(RwLock::new(Some(Surreal::init()))),
+            available: Arc::new(AtomicBool::new(false)),
+            closing: Arc::new(Notify::new()),
+            failure: Arc::new(watch::channel(None).0),
+        };
+        tokio::spawn(supervise(store.clone(), config));
+        store
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+
+    // A copy of the client, None once the store has been closed
+    fn client(&self) -> Option<Surreal<Client>> {
+        self.db
+            .read()
+            .expect("database client lock poisoned")
+            .clone()
+    }
+
+    // Runs the operation with the client unless the database is known to be down or was closed
+    // Connection errors and timeouts mark the database as down until the next health check passes
+    // The latency and any error are recorded against the operation and table
+    async fn guard<R, Q, F>(
+        &self,
+        operation: &'static str,
+        table: &'static str,
+        query: Q,
+    ) -> Result<R, StoreError>
+    where
+        Q: FnOnce(Surreal<Client>) -> F + Send,
+        F: Future<Output = Result<R, StoreError>> + Send,
+    {
+        let db = match self.client() {
+            Some(db) if self.is_available() => db,
+            _ => {
+                counter!(DB_ERRORS_TOTAL, "operation" => operation, "table" => table, "kind" => "unavailable")
+                    .increment(1);
+                return Err(StoreError::Unavailable(
+                    "The database is not connected".to_string(),
+                ));
+            }
+        };
+        let start = Instant::now();
+        let result = match timeout(QUERY_TIMEOUT, query(db)).await {
+            Ok(result) => result,
+            Err(_) => Err(StoreError::Unavailable(
+                "The database did not respond in time".to_string(),
+            )),
+        };
+        histogram!(DB_QUERY_DURATION_SECONDS, "operation" => operation, "table" => table)
+            .record(start.elapsed().as_secs_f64());
+
+        if let Err(err) = &result {
+            let kind = match err {
+                StoreError::Database(_) => "database",
+                StoreError::Conflict(_) => "conflict",
+                StoreError::Serialization(_) => "serialization",
+                StoreError::Invalid { .. } => "invalid",
+                StoreError::Unavailable(_) => "unavailable",
+                StoreError::Incompatible(_) => "incompatible",
+            };
+            counter!(DB_ERRORS_TOTAL, "operation" => operation, "table" => table, "kind" => kind)
+                .increment(1);
+            if kind == "unavailable" {
+                self.set_available(false);
+            }
+        }
+        result
+    }
+
+    fn set_available(&self, available: bool) {
+        if self.available.swap(available, Ordering::Relaxed) != available {
+            match available {
+                true => info!("The database is available"),
+                false => warn!("The database is unavailable, requests will fail until it returns"),
+            }
+        }
+    }
+}
+
+// Connects with exponential backoff and then watches the connection until the store is closed
+// The client is only held while it is used so closing the store can drop the last copy
+async fn supervise(store: SurrealStore, config: DatabaseConfig) {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut connected = false;
+    loop {
+        let db = match store.client() {
+            Some(db) => db,
+            None => return,
+        };
+        match connect_once(&db, &config, &mut connected).await {
+            Ok(()) => break,
+            // Retrying can't fix an incompatible schema, the server is stopped instead
+            Err(StoreError::Incompatible(reason)) => {
+                error!("{reason}");
+                store.failure.send_replace(Some(reason));
+                return;
+            }
+            Err(err) => {
+                warn!(
+                    "Failed to connect to the database at {}, retrying in {backoff:?}: {err}",
+                    config.endpoint
+                );
+                tokio::select! {
+                    _ = sleep(backoff) => {}
+                    _ = store.closing.notified() => return,
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+    store.set_available(true);
+
+    loop {
+        tokio::select! {
+            _ = sleep(HEALTH_INTERVAL) => {}
+            _ = store.closing.notified() => return,
+        }
+        let db = match store.client() {
+            Some(db) => db,
+            None => return,
+        };
+        let healthy = matches!(timeout(HEALTH_TIMEOUT, db.health()).await, Ok(Ok(())));
+        store.set_available(healthy);
+    }
+}
+
+// The WebSocket can only be opened once, later attempts only retry the steps after it
+async fn connect_once(
+    db: &Surreal<Client>,
+    config: &DatabaseConfig,
+    connected: &mut bool,
+) -> Result<(), StoreError> {
+    if !*connected {
+        db.connect::<Ws>(config.endpoint.as_str()).await?;
+        *connected = true;
+    }
+    db.signin(Root {
+        username: config.username.as_deref().unwrap_or_default(),
+        password: config
+            .password
+            .as_ref()
+            .map(Secret::expose)
+            .unwrap_or_default(),
+    })
+    .await?;
+    db.use_ns(config.namespace.as_str())
+        .use_db(config.database.as_str())
+        .await?;
+    match migrations::migrate(db).await {
+        Ok(version) => {
+            info!(version, "The database schema is up to date");
+            Ok(())
+        }
+        Err(MigrationError::Database(err)) => Err(StoreError::from(*err)),
+        Err(err) => Err(StoreError::Incompatible(err.to_string())),
+    }
+}
+
+#[async_trait]
+impl<T: Model> Repository<T> for SurrealStore {
+    async fn get(&self, id: &str) -> Result<Option<T>, StoreError> {
+        self.guard("get", T::TABLE, |db| async move {
+            Ok(db.select((T::TABLE, id)).await?)
+        })
+        .await
+    }
+
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<T>, StoreError> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.guard("get_many", T::TABLE, |db| async move {
+            let things: Vec<Thing> = ids
+                .iter()
+                .map(|id| Thing {
+                    tb: T::TABLE.into(),
+                    id: id.as_str().into(),
+                })
+                .collect();
+            let mut response = db
+                .query("SELECT * FROM $things")
+                .bind(("things", things))
+                .await?;
+            Ok(response.take(0)?)
+        })
+        .await
+    }
+
+    async fn list(&self) -> Result<Vec<T>, StoreError> {
+        self.guard("list", T::TABLE, |db| async move {
+            Ok(db.select(T::TABLE).await?)
+        })
+        .await
+    }
+
+    async fn list_by_vendor(&self, vendor_id: &str) -> Result<Vec<T>, StoreError> {
+        self.guard("list_by_vendor", T::TABLE, |db| async move {
+            let mut response = db
+                .query("SELECT * FROM type::table($table) WHERE vendor = $vendor")
+                .bind(("table", T::TABLE))
+                .bind(("vendor", vendor_thing(vendor_id)))
+                .await?;
+            Ok(response.take(0)?)
+        })
+        .await
+    }
+
+    async fn list_by_vendors(&self, vendor_ids: &[String]) -> Result<Vec<T>, StoreError> {
+        if vendor_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.guard("list_by_vendors", T::TABLE, |db| async move {
+            let vendors: Vec<Thing> = vendor_ids.iter().map(|id| vendor_thing(id)).collect();
+            let mut response = db
+                .query("SELECT * FROM type::table($table) WHERE vendor IN $vendors")
+                .bind(("table", T::TABLE))
+                .bind(("vendors", vendors))
+                .await?;
+            Ok(response.take(0)?)
+        })
+        .await
+    }
+
+    // The cursor is looked up in the same query, the page starts after its place in the order
+    async fn page(&self, query: &PageQuery) -> Result<Listing<T>, StoreError> {
+        self.guard("page", T::TABLE, |db| async move {
+            let (lat, lon) = query.origin.unwrap_or_default();
+            let mut response = db
+                .query(page_statements(query))
+                .bind(("table", T::TABLE))
+                .bind(("vendor", query.vendor.as_deref().map(vendor_thing)))
+                .bind(("after", query.after.as_deref()))
+                // One more than the page is read to know whether there is a next page
+                .bind(("limit", query.limit + 1))
+                .bind(("lat", lat))
+                .bind(("lon", lon))
+                .await?;
+            let mut index = 0;
+            if query.after.is_some() {
+                let cursor: Option<String> = response.take(1)?;
+                if cursor.is_none() {
+                    return Err(StoreError::unknown_cursor());
+                }
+                index = 2;
+            }
+            let mut records: Vec<T> = response.take(index)?;
+            let count: Option<Count> = response.take(index + 1)?;
+
+            let next_cursor = match records.len() > query.limit {
+                true => {
+                    records.truncate(query.limit);
+                    records.last().map(|record| record.uuid().to_string())
+                }
+                false => None,
+            };
+            Ok(Listing {
+                records,
+                next_cursor,
+                total: count.map_or(0, |count| count.count),
+            })
+        })
+        .await
+    }
+
+    async fn containing(
+        &self,
+        fields: &[&'static str],
+        fragments: &[String],
+        limit: usize,
+    ) -> Result<Vec<T>, StoreError> {
+        if fields.is_empty() || fragments.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.guard("containing", T::TABLE, |db| async move {
+            let mut query = db
+                .query(containing_statement(fields, fragments.len()))
+                .bind(("table", T::TABLE))
+                .bind(("limit", limit));
+            for (index, fragment) in fragments.iter().enumerate() {
+                query = query.bind((format!("fragment{index}"), fragment.as_str()));
+            }
+            let mut response = query.await?;
+            Ok(response.take(0)?)
+        })
+        .await
+    }
+
+    async fn create(&self, record: T) -> Result<Record, StoreError> {
+        self.guard("create", T::TABLE, |db| async move {
+            let record_option: Option<Record> = db
+                .create((T::TABLE, record.uuid().to_string()))
+                .content(record)
+                .await?;
+            match record_option {
+                Some(record) => Ok(record),
+                None => Err(StoreError::Serialization(format!(
+                    "No record was returned after creating it in {}",
+                    T::TABLE
+                ))),
+            }
+        })
+        .await
+    }
+
+    // SurrealDB creates missing records on update, which is what an upsert wants
+    async fn upsert(&self, record: T) -> Result<(), StoreError> {
+        self.guard("upsert", T::TABLE, |db| async move {
+            let _: Option<T> = db
+                .update((T::TABLE, record.uuid().to_string()))
+                .content(record)
+                .await?;
+            Ok(())
+        })
+        .await
+    }
+
+    // SurrealDB creates missing records on update, the WHERE skips them instead since every
+    //      stored record has a uuid
+    // Every field is replaced by the one statement so the record is never left half patched
+    async fn patch(
+        &self,
+        id: &str,
+        patches: Vec<(String, Value)>,
+    ) -> Result<Option<T>, StoreError> {
+        self.guard("patch", T::TABLE, |db| async move {
+            let operations: Vec<Value> = patches
+                .into_iter()
+                .map(|(path, value)| json!({ "op": "replace", "path": path, "value": value }))
+                .collect();
+            let mut response = db
+                .query("UPDATE type::thing($table, $id) PATCH $operations WHERE uuid != NONE")
+                .bind(("table", T::TABLE))
+                .bind(("id", id))
+                .bind(("operations", operations))
+                .await?;
+            Ok(response.take(0)?)
+        })
+        .await
+    }
+
+    async fn delete(&self, id: &str) -> Result<Option<T>, StoreError> {
+        self.guard("delete", T::TABLE, |db| async move {
+            Ok(db.delete((T::TABLE, id)).await?)
+        })
+        .await
+    }
+}
+
+#[derive(Deserialize)]
+struct Count {
+    count: usize,
+}
+
+// The statements of Repository::page, records are ordered by the sort key and then uuid
+//      LET $cursor = ...;      -> The previous page's last record, only with after
+//      RETURN $cursor.uuid;    -> NONE if it doesn't exist
+//      SELECT ...;             -> The page
+//      SELECT count() ...;     -> How many records are paged through
+fn page_statements(query: &PageQuery) -> String {
+    // Matches how MemoryStore sorts, see page_records
+    let key = match query.sort {
+        Some(SortField::Name) => "string::lowercase(name)",
+        Some(SortField::Datetime) => "type::datetime(datetime)",
+        Some(SortField::Distance) => {
+            "geo::distance(type::point(cord_x, cord_y), type::point($lon, $lat))"
+        }
+        None => "uuid",
+    };
+    let (order, past) = match query.descending {
+        true => ("DESC", "<"),
+        false => ("ASC", ">"),
+    };
+
+    let mut statements = String::new();
+    let mut filters = Vec::new();
+    if query.vendor.is_some() {
+        filters.push("vendor = $vendor".to_string());
+    }
+    // The cursor has to be one of the records paged through as well
+    let total_filter = match filters.is_empty() {
+        true => String::new(),
+        false => format!(" WHERE {}", filters.join(" AND ")),
+    };
+    if query.after.is_some() {
+        statements.push_str(&format!(
+            "LET $cursor = (SELECT uuid, {key} AS sort_key FROM type::thing($table, $after){total_filter})[0];\n\
+             RETURN $cursor.uuid;\n"
+        ));
+        filters.push(format!(
+            "({key} {past} $cursor.sort_key OR ({key} = $cursor.sort_key AND uuid {past} $cursor.uuid))"
+        ));
+    }
+    let page_filter = match filters.is_empty() {
+        true => String::new(),
+        false => format!(" WHERE {}", filters.join(" AND ")),
+    };
+    statements.push_str(&format!(
+        "SELECT *, {key} AS sort_key FROM type::table($table){page_filter} \
+         ORDER BY sort_key {order}, uuid {order} LIMIT $limit;\n\
+         SELECT count() FROM type::table($table){total_filter} GROUP ALL;"
+    ));
+    statements
+}
+
+// The statement of Repository::containing, each fragment is bound as $fragment0, $fragment1 and
+//      so on, only the field names are written into the statement and they come from the code
+// Matches how MemoryStore compares, see contains_fragment
+fn containing_statement(fields: &[&'static str], fragments: usize) -> String {
+    let filters: Vec<String> = fields
+        .iter()
+        .flat_map(|field| {
+            (0..fragments).map(move |index| {
+                format!("string::contains(string::lowercase({field} ?? ''), $fragment{index})")
+            })
+        })
+        .collect();
+    format!(
+        "SELECT * FROM type::table($table) WHERE {} LIMIT $limit;",
+        filters.join(" OR ")
+    )
+}
+
+#[derive(Deserialize)]
+struct MenuItems {
+    items: Vec<Option<Item>>,
+}
+
+#[async_trait]
+impl Store for SurrealStore {
+    // The items are fetched through the record links stored on the menu so only they are read
+    async fn items_by_menu(&self, menu_id: &str) -> Result<Option<Vec<Item>>, StoreError> {
+        self.guard("items_by_menu", "menus", |db| async move {
+            let mut response = db
+                .query("SELECT items FROM $menu FETCH items")
+                .bind((
+                    "menu",
+                    Thing {
+                        tb: "menus".into(),
+                        id: menu_id.into(),
+                    },
+                ))
+                .await?;
+            let menu_items: Option<MenuItems> = response.take(0)?;
+            Ok(menu_items.map(|menu_items| menu_items.items.into_iter().flatten().collect()))
+        })
+        .await
+    }
+
+    async fn vendors_by_email(&self, email: &str) -> Result<Vec<Vendor>, StoreError> {
+        self.guard("vendors_by_email", "vendors", |db| async move {
+            let mut response = db
+                .query("SELECT * FROM vendors WHERE email = $email")
+                .bind(("email", email))
+                .await?;
+            Ok(response.take(0)?)
+        })
+        .await
+    }
+
+    async fn set_override(
+        &self,
+        event_id: &str,
+        date: NaiveDate,
+        over: Option<OccurrenceOverride>,
+    ) -> Result<Option<Event>, StoreError> {
+        self.guard("set_override", Event::TABLE, |db| async move {
+            let added: Vec<OccurrenceOverride> = over.into_iter().collect();
+            let mut response = db
+                .query(SET_OVERRIDE)
+                .bind(("table", Event::TABLE))
+                .bind(("id", event_id))
+                .bind(("date", date.to_string()))
+                .bind(("added", json!(added)))
+                .await?;
+            Ok(response.take(0)?)
+        })
+        .await
+    }
+
+    // Signs out so the server drops the session and then drops the client, which sends the
+    //      WebSocket a close frame once the last copy is gone
+    // Requests still running keep their copy until they finish, main only closes the store once
+    //      the connections have drained
+    async fn close(&self) {
+        self.closing.notify_one();
+        let was_available = self.available.swap(false, Ordering::Relaxed);
+        let db = match self
+            .db
+            .write()
+            .expect("database client lock poisoned")
+            .take()
+        {
+            Some(db) => db,
+            None => return,
+        };
+        if was_available {
+            match timeout(HEALTH_TIMEOUT, db.invalidate()).await {
+                Ok(Ok(())) => info!("Closed the database session"),
+                Ok(Err(err)) => warn!("Failed to close the database session: {err}"),
+                Err(_) => warn!("Timed out closing the database session"),
+            }
+        }
+        drop(db);
+        info!("Closed the database connection");
+    }
+
+    async fn failed(&self) -> String {
+        let mut failure = self.failure.subscribe();
+        let reason = match failure.wait_for(Option::is_some).await {
+            Ok(reason) => reason.clone(),
+            // The sender lives as long as the store so this can't happen
+            Err(_) => None,
+        };
+        match reason {
+            Some(reason) => reason,
+            None => std::future::pending().await,
+        }
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        self.guard("ping", "none", |db| async move {
+            db.query("RETURN true").await?.check()?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page_query(sort: Option<SortField>, vendor: bool, after: bool) -> PageQuery {
+        PageQuery {
+            vendor: vendor.then(|| "vendor".to_string()),
+            limit: 10,
+            after: after.then(|| "cursor".to_string()),
+            sort,
+            descending: false,
+            origin: None,
+        }
+    }
+
+    #[test]
+    fn page_statements_parse() {
+        let sorts = [
+            None,
+            Some(SortField::Name),
+            Some(SortField::Datetime),
+            Some(SortField::Distance),
+        ];
+        for sort in sorts {
+            for (vendor, after) in [(false, false), (true, false), (false, true), (true, true)] {
+                let statements = page_statements(&page_query(sort, vendor, after));
+                if let Err(err) = surrealdb::sql::parse(&statements) {
+                    panic!("{statements}\n{err}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn containing_statement_parses() {
+        let statement = containing_statement(&["name", "description"], 3);
+        if let Err(err) = surrealdb::sql::parse(&statement) {
+            panic!("{statement}\n{err}");
+        }
+        assert!(statement.contains("string::lowercase(description ?? ''), $fragment2"));
+    }
+
+    #[test]
+    fn set_override_parses() {
+        if let Err(err) = surrealdb::sql::parse(SET_OVERRIDE) {
+            panic!("{SET_OVERRIDE}\n{err}");
+        }
+    }
+
+    #[test]
+    fn page_statements_skip_the_cursor_lookup_on_the_first_page() {
+        let first = page_statements(&page_query(Some(SortField::Name), true, false));
+        assert!(!first.contains("$cursor"));
+        assert!(first.contains("WHERE vendor = $vendor"));
+        let next = page_statements(&page_query(Some(SortField::Name), false, true));
+        assert!(next.starts_with("LET $cursor"));
+        assert!(next.contains("ORDER BY sort_key ASC, uuid ASC LIMIT $limit"));
+    }
+}
@@ -1,22 +1,70 @@
+mod cli;
+mod config;
 mod database;
 mod server;
 mod utils;
 
-use crate::server::app;
+use crate::cli::{Cli, Command};
+use crate::config::{Config, Mode};
+use crate::server::{app, logging, shutdown};
 use axum::{http::Uri, response::Redirect, Router};
-use axum_server::tls_openssl::OpenSSLConfig;
-use std::net::SocketAddr;
+use axum_server::{tls_openssl::OpenSSLConfig, Handle};
+use clap::Parser;
+use tracing::{debug, error, info};
 
 #[tokio::main]
 async fn main() {
-    let app = match app::make_app().await {
+    let cli = Cli::parse();
+    let mode = match cli.command {
+        Some(Command::Serve) | None => Mode::Serve,
+        Some(Command::Import(_)) | Some(Command::Export(_)) => Mode::Command,
+    };
+    let config = match Config::load(mode) {
+        Ok(config) => config,
+        Err(err) => panic!("{err}"),
+    };
+    if let Err(err) = logging::init(&config.logging) {
+        panic!("{err}");
+    }
+    // Secrets in the config print as [redacted]
+    debug!(?config, "Loaded configuration");
+
+    match cli.command {
+        Some(Command::Import(args)) => {
+            let imported = cli::import::run(args, &config).await;
+            logging::flush();
+            if let Err(err) = imported {
+                error!("{err}");
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Export(args)) => {
+            let exported = cli::export::run(args, &config).await;
+            logging::flush();
+            if let Err(err) = exported {
+                error!("{err}");
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Serve) | None => serve(config).await,
+    }
+}
+
+async fn serve(config: Config) {
+    let listen = config.server.listen;
+    let tls = match config.server.tls() {
+        Some((cert, key)) => match OpenSSLConfig::from_pem_file(cert, key) {
+            Ok(tls) => Some(tls),
+            Err(err) => panic!("The TLS certificate or key could not be loaded\n{err}"),
+        },
+        None => None,
+    };
+
+    let shutdown_timeout = config.server.shutdown_timeout();
+
+    let state = app::make_state(config);
+    let app = match app::make_app(state.clone()).await {
         Ok(app) => app,
-        // Failing here causes a panic since if the api cant talk to the server its pointless for
-        // the program to continue executing
-        // However this is not desirable functionality
-        // While a panic will only occur at launch it may be an issue, especially after reboots if
-        // the SurrealDB server takes longer to launch than the API
-        // TODO: fix later
         Err(err) => panic!(
             "The server could not be created due to the following err\n{}",
             err
@@ -24,14 +72,45 @@ async fn main() {
     };
     // region:      -- Start Server
 
-    //let config = OpenSSLConfig::from_pem_file("certs/ssc/cert.pem", "certs/ssc/key.pem").unwrap();
+    // Serving returns once a signal has been received and the connections have drained
+    let handle = Handle::new();
+    let stopped = shutdown::on_signal(handle.clone(), state.store.clone(), shutdown_timeout);
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
-    println!("Listening on {addr}");
-    axum_server::bind(addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    let served = match tls {
+        Some(tls) => {
+            info!("Listening on https://{listen}");
+            axum_server::bind_openssl(listen, tls)
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await
+        }
+        None => {
+            info!("Listening on http://{listen}");
+            axum_server::bind(listen)
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await
+        }
+    };
+    if let Err(err) = served {
+        error!("The server stopped unexpectedly: {err}");
+    }
 
     // endregion:   -- Start Server
+
+    // region:      -- Shutdown
+
+    state.store.close().await;
+    // The shutdown task has only finished if it was what stopped the server
+    let failure = match stopped.is_finished() {
+        true => stopped.await.ok().flatten(),
+        false => None,
+    };
+    info!("Shutdown complete");
+    logging::flush();
+    if failure.is_some() {
+        std::process::exit(1);
+    }
+
+    // endregion:   -- Shutdown
 }
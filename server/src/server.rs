@@ -1,8 +1,18 @@
 #[path = "server/app.rs"]
 pub mod app;
+#[path = "server/error.rs"]
+pub mod error;
 #[path = "server/handlers.rs"]
 pub mod handlers;
+#[path = "server/logging.rs"]
+pub mod logging;
 #[path = "server/middleware.rs"]
 pub mod middleware;
+#[path = "server/monitoring.rs"]
+pub mod monitoring;
+#[path = "server/pagination.rs"]
+pub mod pagination;
+#[path = "server/shutdown.rs"]
+pub mod shutdown;
 #[path = "server/state.rs"]
 pub mod state;
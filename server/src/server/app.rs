@@ -1,28 +1,41 @@
+use crate::config::{Config, CorsConfig, DatabaseConfig, StoreKind};
+use crate::database::{memory_store::MemoryStore, store::Store, surreal_store::SurrealStore};
 use crate::server::{
-    handlers,
-    middleware::{authenticator, authorizer},
+    handlers, logging,
+    middleware::{authenticator, authorizer, require_admin},
+    monitoring,
     state::AppState,
 };
 use axum::{
-    http::{header::CONTENT_TYPE, Method},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        HeaderValue, Method,
+    },
     middleware,
     routing::{get, post},
     Router,
 };
-use color_eyre::{eyre::bail, Result};
-use std::env;
-use surrealdb::{
-    engine::remote::ws::{Client, Ws},
-    opt::auth::Root,
-    Surreal,
-};
-use tower_http::cors::{Any, CorsLayer};
+use color_eyre::Result;
+use std::sync::Arc;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+// Connects the store picked in the config, main keeps a copy so the store can be closed once the
+//      server has stopped
+pub fn make_state(config: Config) -> AppState {
+    // The recorder has to exist before anything records a metric
+    monitoring::handle();
 
-pub async fn make_app() -> Result<Router> {
+    AppState {
+        store: make_store(&config.database),
+        config: Arc::new(config),
+    }
+}
+
+pub async fn make_app(state: AppState) -> Result<Router> {
     let cors = CorsLayer::new()
         .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::PATCH])
-        .allow_origin(Any)
-        .allow_headers([CONTENT_TYPE]);
+        .allow_origin(allowed_origins(&state.config.cors))
+        .allow_headers([CONTENT_TYPE, AUTHORIZATION]);
 
     // The valid endpoints for the API
     // Endpoints which can be accessed without authorization marked with *
@@ -34,13 +47,12 @@ pub async fn make_app() -> Result<Router> {
     let endpoints = Router::new()
         // Routes dealing with vendor resources
         // Get -> All vendors*
+        //          GeoJSON with format=geojson or Accept: application/geo+json, each vendor at
+        //              where its next occurrence is
         // Post -> Creates a new vendor
-        //          This is a special route which is governed by a seperate authorization agent
+        //          Vendors are normally created when they first log in through /auth/token
         //          Since a new vendor cannot verify themselves, and a vendor shouldn't be able to
-        //              create a new vendor
-        //          This route takes an Facebook Oauth string and can be freely called
-        //          WARNING: This route needs to do verification to ensure it is not used by
-        //          malicious individuals
+        //              create a new vendor, this route is restricted to admins
         //
         // Else -> 404
         .route(
@@ -49,8 +61,27 @@ pub async fn make_app() -> Result<Router> {
         )
         // Routes dealing with general event resources
         // Get -> All events*
+        //          GeoJSON with format=geojson or Accept: application/geo+json
         // Else -> 404
         .route("/events", get(handlers::get::get_events))
+        // Routes dealing with the expanded schedule of every event
+        // Get -> Every occurrence between the from and to query parameters*
+        // Else -> 404
+        .route("/events/occurrences", get(handlers::get::get_occurrences))
+        // Get -> Every occurrence in progress at the at query parameter, defaults to now*
+        // Else -> 404
+        .route("/events/open", get(handlers::get::get_open))
+        // Get -> Every occurrence between from and to within radius_m of lat and lon*
+        //          GeoJSON with format=geojson or Accept: application/geo+json
+        // Else -> 404
+        .route("/events/nearby", get(handlers::get::get_nearby))
+        // Get -> Every event as an iCalendar feed, only those within radius_m of near if given*
+        // Else -> 404
+        .route("/events/calendar.ics", get(handlers::get::get_calendar))
+        // Get -> Vendors, menus and items matching the q query parameter, grouped by kind*
+        //          Vendors also match through the menus and items they sell
+        // Else -> 404
+        .route("/search", get(handlers::get::get_search))
         // Routes dealing with specific vendor resources
         // Get -> Specific vendor*
         // Delete -> Specific vendor
@@ -77,6 +108,19 @@ pub async fn make_app() -> Result<Router> {
                 .delete(handlers::delete::delete_event)
                 .patch(handlers::patch::patch_event),
         )
+        // Routes dealing with single occurrences of specific events, :date is the date the
+        //      occurrence was scheduled on such as 2024-05-07
+        // Get -> Specific occurrence with any changes to it applied*
+        // Delete -> Cancels the occurrence of an event belonging to authorized vendor
+        // Patch -> Cancels, moves or relocates the occurrence of an event belonging to authorized
+        //          vendor
+        // Else -> 404
+        .route(
+            "/events/:event_id/occurrences/:date",
+            get(handlers::get::get_occurrence)
+                .delete(handlers::delete::delete_occurrence)
+                .patch(handlers::patch::patch_occurrence),
+        )
         // Routes dealing with specific menus
         // Get -> Specific menu
         // Delete -> Specific menu belonging to authorized vendor
@@ -107,6 +151,18 @@ pub async fn make_app() -> Result<Router> {
             "/vendors/:vendor_id/events",
             get(handlers::get::get_events).post(handlers::post::post_event),
         )
+        // Post -> Creates or updates events for authorized vendor from an iCalendar file
+        // Else -> 404
+        .route(
+            "/vendors/:vendor_id/events/import",
+            post(handlers::post::import_calendar),
+        )
+        // Get -> All events belonging to specific vendor as an iCalendar feed*
+        // Else -> 404
+        .route(
+            "/vendors/:vendor_id/calendar.ics",
+            get(handlers::get::get_calendar),
+        )
         // Get -> All menus belonging to specific vendor
         // Post -> Creates a new menu for authorized vendor
         // Else -> 404
@@ -123,70 +179,60 @@ pub async fn make_app() -> Result<Router> {
         )
         .layer(
             tower::ServiceBuilder::new()
-                .layer(middleware::from_fn(authenticator))
-                .layer(middleware::from_fn(authorizer)),
+                .layer(middleware::from_fn_with_state(state.clone(), authenticator))
+                .layer(middleware::from_fn_with_state(state.clone(), authorizer)),
         );
 
-    let api = Router::new().nest("/api", endpoints).layer(cors);
+    // Routes for operating the service, every method needs an admin token
+    // Get -> Every vendor, item, menu and event as json, or one table as csv
+    // Else -> 404
+    let admin = Router::new()
+        .route("/export", get(handlers::admin::export))
+        .layer(middleware::from_fn_with_state(state.clone(), require_admin));
+
+    let api = Router::new()
+        .nest("/api", endpoints.nest("/admin", admin))
+        .layer(cors);
     let auth = Router::new().route("/auth/token", post(crate::utils::auth::token));
 
-    let app = Router::new().merge(api).merge(auth).with_state(AppState {
-        db: match db_connect().await {
-            Ok(db) => db,
-            Err(err) => return Err(bail!(err)), // FIX : The database could not be created, if this happens a
-                                                // panic is undesirable but likely, add correcting code
-                                                // later
-        },
-    });
+    // Probes for the orchestrator, kept outside the api so they never need a token
+    // /healthz -> The process is alive
+    // /readyz  -> The database, and optionally the geocoder, can be reached
+    // /version -> The version and commit the server was built from
+    // /metrics -> Prometheus metrics
+    let health = Router::new()
+        .route("/healthz", get(handlers::health::healthz))
+        .route("/readyz", get(handlers::health::readyz))
+        .route("/version", get(handlers::health::version))
+        .route("/metrics", get(monitoring::metrics));
+
+    let app = Router::new()
+        .merge(api)
+        .merge(auth)
+        .merge(health)
+        .with_state(state);
+    let app = logging::layer(app.layer(middleware::from_fn(monitoring::track)));
     Ok(app)
 }
 
-pub async fn db_connect() -> Result<Surreal<Client>> {
-    let db;
-
-    match get_db_creds() {
-        Ok(response) => {
-            db = Surreal::new::<Ws>("localhost:8000").await?;
-            db.signin(Root {
-                username: &response[0],
-                password: &response[1],
-            })
-            .await?;
-        }
-        Err(_) => return Err(bail!("Failed to connect to database")),
+// The origins were validated when the config was loaded, * anywhere in the list allows any origin
+fn allowed_origins(config: &CorsConfig) -> AllowOrigin {
+    if config.origins.iter().any(|origin| origin == "*") {
+        return Any.into();
     }
-
-    // Sets up the database info, will just use defaults if the env variables aren't set
-    // Including this in the source shouldn't be a security risk since its just database names
-    db.use_ns(match env::var("DBNS") {
-        Ok(namespace) => namespace.to_owned(),
-        Err(_) => "food_truck_finder".to_string(),
-    })
-    .use_db(match env::var("DBNM") {
-        Ok(database_name) => database_name.to_owned(),
-        Err(_) => "ftf_db".to_string(),
-    })
-    .await?;
-
-    Ok(db)
+    AllowOrigin::list(
+        config
+            .origins
+            .iter()
+            .filter_map(|origin| HeaderValue::from_str(origin).ok()),
+    )
 }
 
-fn get_db_creds() -> Result<Vec<String>> {
-    let username = match env::var("DBUN") {
-        Ok(uname) => uname,
-        Err(_) => {
-            return Err(bail!(
-                "Database username environment variable could not be resolved"
-            ))
-        }
-    };
-    let password = match env::var("DBPW") {
-        Ok(pword) => pword,
-        Err(_) => {
-            return Err(bail!(
-                "Database password environment variable could not be resolved"
-            ))
-        }
-    };
-    Ok(vec![username, password])
+// Picks the store backing the API, see database.store in the config
+// SurrealDB is connected to in the background, see SurrealStore::connect
+pub fn make_store(config: &DatabaseConfig) -> Arc<dyn Store> {
+    match config.store {
+        StoreKind::Memory => Arc::new(MemoryStore::new()),
+        StoreKind::Surreal => Arc::new(SurrealStore::connect(config.clone())),
+    }
 }
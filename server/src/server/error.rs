@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::database::models::InvalidField;
+use crate::database::store::StoreError;
+use axum::{
+    extract::{rejection::JsonRejection, FromRequest},
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use chrono::NaiveDate;
+use serde::Serialize;
+use serde_json::Value;
+use tracing::error;
+
+// Matches how often the store checks whether the database has come back
+pub const RETRY_AFTER_SECS: &str = "5";
+
+// The error type returned by every handler and middleware in the API
+// Each variant maps to a single status code and is rendered as the same json body
+//      { "error": { "code": "not_found", "message": "...", "fields": [...] } }
+// so clients can match on the code rather than parsing messages
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    Validation(Vec<FieldError>),
+    Database(Box<StoreError>),
+    // The database is down, answered with a Retry-After header
+    Unavailable(String),
+    Internal(String),
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: &str) -> Self {
+        FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<FieldError>,
+}
+
+impl ApiError {
+    pub fn not_found(table: &str, id: &str) -> Self {
+        ApiError::NotFound(format!("{table}:{id} does not exist"))
+    }
+
+    pub fn no_occurrence(event_id: &str, date: NaiveDate) -> Self {
+        ApiError::NotFound(format!("events:{event_id} has no occurrence on {date}"))
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    // Stable machine readable identifier for the error, clients should match on this
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::Database(_) => "database_error",
+            ApiError::Unavailable(_) => "service_unavailable",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::Unavailable(message)
+            | ApiError::Internal(message) => write!(f, "{message}"),
+            ApiError::Validation(fields) => write!(
+                f,
+                "Invalid fields: {}",
+                fields
+                    .iter()
+                    .map(|field| field.field.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(", ")
+            ),
+            ApiError::Database(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let code = self.code();
+        // Internal details are logged but never sent to the client
+        let message = match &self {
+            ApiError::Database(err) => {
+                error!("Database error: {err}");
+                "The database could not complete the request".to_string()
+            }
+            ApiError::Internal(message) => {
+                error!("Internal error: {message}");
+                "The server could not complete the request".to_string()
+            }
+            _ => self.to_string(),
+        };
+        let unavailable = matches!(self, ApiError::Unavailable(_));
+        let fields = match self {
+            ApiError::Validation(fields) => fields,
+            _ => Vec::new(),
+        };
+        let body = ErrorBody {
+            code,
+            message,
+            fields,
+        };
+        let mut response = (status, Json(HashMap::from([("error", body)]))).into_response();
+        if unavailable {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from_static(RETRY_AFTER_SECS));
+        }
+        response
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(err: StoreError) -> Self {
+        match err {
+            StoreError::Conflict(message) => ApiError::Conflict(message),
+            StoreError::Unavailable(message) => ApiError::Unavailable(message),
+            StoreError::Invalid { field, message } => {
+                ApiError::Validation(vec![FieldError::new(&field, &message)])
+            }
+            _ => ApiError::Database(Box::new(err)),
+        }
+    }
+}
+
+impl From<Vec<InvalidField>> for ApiError {
+    fn from(invalid: Vec<InvalidField>) -> Self {
+        ApiError::Validation(
+            invalid
+                .iter()
+                .map(|invalid| FieldError::new(invalid.field, &invalid.message))
+                .collect(),
+        )
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+// Json extractor which rejects malformed bodies with an ApiError instead of axum's plain text
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct ApiJson<T>(pub T);
+
+// Returns a path parameter or a 400 if the route was called without it
+pub fn get_param<'a>(params: &'a HashMap<String, String>, name: &str) -> Result<&'a str, ApiError> {
+    match params.get(name) {
+        Some(param) => Ok(param.as_str()),
+        None => Err(ApiError::BadRequest(format!(
+            "Missing path parameter {name}"
+        ))),
+    }
+}
+
+// Reads a date path parameter such as 2024-05-07
+pub fn parse_date(params: &HashMap<String, String>, name: &str) -> Result<NaiveDate, ApiError> {
+    let date = get_param(params, name)?;
+    match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        Ok(date) => Ok(date),
+        Err(_) => Err(ApiError::Validation(vec![FieldError::new(
+            name,
+            "must be a date such as 2024-05-07",
+        )])),
+    }
+}
+
+// Checks that each of the given fields is present in the body as a non empty string
+pub fn require_strings(json: &Value, fields: &[&str]) -> Result<(), ApiError> {
+    let missing: Vec<FieldError> = fields
+        .iter()
+        .filter(|field| {
+            !matches!(json.get(**field).and_then(|value| value.as_str()), Some(value) if !value.is_empty())
+        })
+        .map(|field| FieldError::new(field, "is required and must be a non empty string"))
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(ApiError::Validation(missing))
+    }
+}
+
+// Reads a numeric query parameter and checks it is within min and max
+// Parameters without a default are required
+pub fn parse_number(
+    query: &HashMap<String, String>,
+    name: &str,
+    default: Option<f64>,
+    min: f64,
+    max: f64,
+) -> Result<f64, ApiError> {
+    let number = match (query.get(name), default) {
+        (Some(number), _) => match number.parse::<f64>() {
+            Ok(number) if number.is_finite() => number,
+            _ => {
+                return Err(ApiError::Validation(vec![FieldError::new(
+                    name,
+                    "must be a number",
+                )]))
+            }
+        },
+        (None, Some(default)) => default,
+        (None, None) => {
+            return Err(ApiError::Validation(vec![FieldError::new(
+                name,
+                "is required",
+            )]))
+        }
+    };
+    if number < min || number > max {
+        return Err(ApiError::Validation(vec![FieldError::new(
+            name,
+            &format!("must be between {min} and {max}"),
+        )]));
+    }
+    Ok(number)
+}
+
+// Reads a whole number query parameter and checks it is within min and max
+// Parameters without a default are required
+pub fn parse_integer(
+    query: &HashMap<String, String>,
+    name: &str,
+    default: Option<usize>,
+    min: usize,
+    max: usize,
+) -> Result<usize, ApiError> {
+    let number = match (query.get(name), default) {
+        (Some(number), _) => match number.trim().parse::<usize>() {
+            Ok(number) => number,
+            Err(_) => {
+                return Err(ApiError::Validation(vec![FieldError::new(
+                    name,
+                    "must be a whole number",
+                )]))
+            }
+        },
+        (None, Some(default)) => default,
+        (None, None) => {
+            return Err(ApiError::Validation(vec![FieldError::new(
+                name,
+                "is required",
+            )]))
+        }
+    };
+    if number < min || number > max {
+        return Err(ApiError::Validation(vec![FieldError::new(
+            name,
+            &format!("must be between {min} and {max}"),
+        )]));
+    }
+    Ok(number)
+}
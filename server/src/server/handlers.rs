@@ -1,7 +1,11 @@
+#[path = "handlers/admin.rs"]
+pub mod admin;
 #[path = "handlers/delete.rs"]
 pub mod delete;
 #[path = "handlers/get.rs"]
 pub mod get;
+#[path = "handlers/health.rs"]
+pub mod health;
 #[path = "handlers/patch.rs"]
 pub mod patch;
 #[path = "handlers/post.rs"]
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use crate::database::export::{self, Export, Format, Table, JSON_FILE};
+use crate::server::error::{ApiError, FieldError};
+use crate::server::state;
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+use futures_util::StreamExt;
+
+// Csv exports use the same delimiter as `server import` expects by default
+const CSV_DELIMITER: u8 = b'|';
+
+// Streams a snapshot of the dataset which `server import` can read back
+//      format  -> json for every table in one document, the default, or csv for one table
+//      table   -> vendors, items, menus or events, required with csv
+pub async fn export(
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<state::AppState>,
+) -> Result<Response, ApiError> {
+    let format = match query.get("format").map(|format| format.parse::<Format>()) {
+        None => Format::Json,
+        Some(Ok(format)) => format,
+        Some(Err(err)) => return Err(ApiError::Validation(vec![FieldError::new("format", &err)])),
+    };
+    let table = match query.get("table").map(|table| table.parse::<Table>()) {
+        None => None,
+        Some(Ok(table)) => Some(table),
+        Some(Err(err)) => return Err(ApiError::Validation(vec![FieldError::new("table", &err)])),
+    };
+
+    let (table, content_type, file) = match (format, table) {
+        (Format::Json, _) => (None, "application/json", JSON_FILE),
+        (Format::Csv, Some(table)) => (Some(table), "text/csv; charset=utf-8", table.file()),
+        (Format::Csv, None) => {
+            return Err(ApiError::Validation(vec![FieldError::new(
+                "table",
+                "is required when format is csv",
+            )]))
+        }
+    };
+
+    // The first part is read before responding so a store which can't be read gets an error
+    //      response, the rest is read a page at a time as the client reads the body and failing
+    //      part way through ends the body early
+    let mut export = Export::new(state.store.clone(), CSV_DELIMITER);
+    let first = match table {
+        Some(table) => export.next_csv(table).await?,
+        None => export.next_json().await?,
+    };
+    let body = Body::from_stream(
+        futures_util::stream::iter(first.map(Ok)).chain(export::stream(export, table)),
+    );
+    Ok((
+        [
+            (CONTENT_TYPE, content_type.to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{file}\""),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
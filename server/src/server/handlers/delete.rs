@@ -1,109 +1,72 @@
 use std::collections::HashMap;
 
 use crate::database::models::{Event, Item, Menu, Vendor};
+use crate::database::schedule::Occurrence;
+use crate::server::error::{get_param, parse_date, ApiError};
+use crate::server::handlers::patch::override_occurrence;
 use crate::server::state;
 use axum::extract::{Path, State};
-use axum::response::IntoResponse;
 use axum::response::Json;
+use serde_json::json;
 
 // TODO: Test for bugs
 pub async fn delete_vendor(
     Path(params): Path<HashMap<String, String>>,
     State(state): State<state::AppState>,
-) -> impl IntoResponse {
-    println!("->> {:<12} - handler vendor_remove - {params:?}", "HANDLER");
-
-    if let Some(vendor_id) = params.get("vendor_id") {
-        let db_resp = state.db.delete(("vendors", vendor_id)).await;
-        let vendor: Vendor = match db_resp {
-            Ok(vendor_option) => match vendor_option {
-                Some(vendor) => vendor,
-                None => {
-                    return Json::default();
-                }
-            },
-            Err(err) => {
-                println!("Failed in result match\n{err:?}");
-                return Json::default();
-            }
-        };
-        return Json(vendor);
+) -> Result<Json<Vendor>, ApiError> {
+    let vendor_id = get_param(&params, "vendor_id")?;
+    let vendor_option: Option<Vendor> = state.store.delete(vendor_id).await?;
+    match vendor_option {
+        Some(vendor) => Ok(Json(vendor)),
+        None => Err(ApiError::not_found("vendors", vendor_id)),
     }
-    Json::default()
 }
 
 // TODO: Test for bugs
 pub async fn delete_event(
     Path(params): Path<HashMap<String, String>>,
     State(state): State<state::AppState>,
-) -> impl IntoResponse {
-    println!("->> {:<12} - handler event_remove - {params:?}", "HANDLER");
-    if let Some(event_id) = params.get("event_id") {
-        let db_resp = state.db.delete(("events", event_id)).await;
-
-        let event: Event = match db_resp {
-            Ok(event_option) => match event_option {
-                Some(event) => event,
-                None => return Json::default(),
-            },
-            Err(err) => {
-                println!("Failed to delete event: {err:?}");
-                return Json::default();
-            }
-        };
-
-        return Json(event);
+) -> Result<Json<Event>, ApiError> {
+    let event_id = get_param(&params, "event_id")?;
+    let event_option: Option<Event> = state.store.delete(event_id).await?;
+    match event_option {
+        Some(event) => Ok(Json(event)),
+        None => Err(ApiError::not_found("events", event_id)),
     }
-    Json::default()
+}
+
+// Cancels a single occurrence of the event, patching cancelled back to false restores it
+pub async fn delete_occurrence(
+    Path(params): Path<HashMap<String, String>>,
+    State(state): State<state::AppState>,
+) -> Result<Json<Occurrence>, ApiError> {
+    let event_id = get_param(&params, "event_id")?;
+    let date = parse_date(&params, "date")?;
+    override_occurrence(&state, event_id, date, &json!({ "cancelled": true })).await
 }
 
 // TODO: Test for bugs
 pub async fn delete_menu(
     Path(params): Path<HashMap<String, String>>,
     State(state): State<state::AppState>,
-) -> impl IntoResponse {
-    println!("->> {:<12} - handler menu_remove - {params:?}", "HANDLER");
-    if let Some(menu_id) = params.get("menu_id") {
-        let db_resp = state.db.delete(("menus", menu_id)).await;
-
-        let menu: Menu = match db_resp {
-            Ok(menu_option) => match menu_option {
-                Some(menu) => menu,
-                None => return Json::default(),
-            },
-            Err(err) => {
-                println!("Failed to delete menu: {err:?}");
-                return Json::default();
-            }
-        };
-
-        return Json(menu);
+) -> Result<Json<Menu>, ApiError> {
+    let menu_id = get_param(&params, "menu_id")?;
+    let menu_option: Option<Menu> = state.store.delete(menu_id).await?;
+    match menu_option {
+        Some(menu) => Ok(Json(menu)),
+        None => Err(ApiError::not_found("menus", menu_id)),
     }
-    Json::default()
 }
 
 // TODO: Test for bugs
 pub async fn delete_item(
     Path(params): Path<HashMap<String, String>>,
     State(state): State<state::AppState>,
-) -> impl IntoResponse {
-    println!("->> {:<12} - handler item_remove - {params:?}", "HANDLER");
-
-    if let Some(item_id) = params.get("item_id") {
-        let db_resp = state.db.delete(("items", item_id)).await;
-
-        let item: Item = match db_resp {
-            Ok(item_option) => match item_option {
-                Some(item) => item,
-                None => return Json::default(),
-            },
-            Err(err) => {
-                println!("Failed to delete item: {err:?}");
-                return Json::default();
-            }
-        };
-
-        return Json(item);
+) -> Result<Json<Item>, ApiError> {
+    let item_id = get_param(&params, "item_id")?;
+    let item_option: Option<Item> = state.store.delete(item_id).await?;
+    match item_option {
+        Some(item) => Ok(Json(item)),
+        None => Err(ApiError::not_found("items", item_id)),
     }
-    Json::default()
 }
@@ -1,217 +1,564 @@
 use std::collections::HashMap;
 
+use crate::database::geo;
+use crate::database::geojson::{self, FeatureCollection};
+use crate::database::ical;
 use crate::database::models::{Event, Item, Menu, Vendor};
+use crate::database::schedule::{self, parse_datetime, Occurrence};
+use crate::database::search::{self, SearchResults};
+use crate::database::store::{page_records, Listing, SortField};
+use crate::server::error::{
+    get_param, parse_date, parse_integer, parse_number, ApiError, FieldError,
+};
+use crate::server::pagination::{Page, PageParams};
 use crate::server::state;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
 use axum::http::HeaderMap;
-use axum::response::IntoResponse;
-use axum::response::Json;
+use axum::response::{IntoResponse, Json, Response};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use surrealdb::sql::Thing;
 
-// TODO: Test for bugs
+// A single vendor is returned as it is, the collection a page at a time
+// Responds with a GeoJSON Feature or FeatureCollection instead when asked for, see
+//      wants_geojson
 pub async fn get_vendors(
     Path(params): Path<HashMap<String, String>>,
-    State(state): State<state::AppState>,
+    Query(query): Query<HashMap<String, String>>,
     headers: HeaderMap,
-) -> impl IntoResponse {
-    for header in headers {
-        println!("{header:?}");
-    }
+    State(state): State<state::AppState>,
+) -> Result<Response, ApiError> {
+    let geojson = wants_geojson(&query, &headers)?;
+    let now = Utc::now().fixed_offset();
     if let Some(vendor_id) = params.get("vendor_id") {
-        let vendor_result: Result<Option<Vendor>, surrealdb::Error> =
-            state.db.select(("vendors", vendor_id)).await;
-        let vendor_option = match vendor_result {
-            Ok(vendor_option) => vendor_option,
-            Err(_) => return Json::default(),
-        };
+        let vendor_option: Option<Vendor> = state.store.get(vendor_id).await?;
         let vendor = match vendor_option {
             Some(vendor) => vendor,
-            None => return Json::default(),
+            None => return Err(ApiError::not_found("vendors", vendor_id)),
         };
-        return Json(vec![vendor]);
-    }
-    let vendor_vec_result: Result<Vec<Vendor>, surrealdb::Error> = state.db.select("vendors").await;
-    let vendor_vec = match vendor_vec_result {
-        Ok(vendor_vec) => vendor_vec,
-        Err(err) => {
-            println!("Failed in result match\n{:?}", err);
-            return Json::default();
+        if !geojson {
+            return Ok(Json(vendor).into_response());
         }
-    };
-    Json(vendor_vec)
+        let events: Vec<Event> = state.store.list_by_vendor(&vendor.uuid).await?;
+        let properties = match serde_json::to_value(&vendor) {
+            Ok(Value::Object(properties)) => properties,
+            _ => Map::new(),
+        };
+        let feature = geojson::vendor_feature(&vendor, properties, &events, now);
+        return Ok(geojson_response(feature));
+    }
+
+    let page_params = PageParams::from_query::<Vendor>(&query, &[SortField::Name])?;
+    let listing: Listing<Vendor> = state.store.page(&page_params.query(None)).await?;
+    if !geojson {
+        return Ok(Json(Page::new(listing, &page_params)?).into_response());
+    }
+
+    // The events of every vendor on the page are read together and then grouped by vendor
+    let vendor_ids: Vec<String> = listing
+        .records
+        .iter()
+        .map(|vendor| vendor.uuid.to_string())
+        .collect();
+    let page_events: Vec<Event> = state.store.list_by_vendors(&vendor_ids).await?;
+    let mut events_by_vendor: HashMap<String, Vec<Event>> = HashMap::new();
+    for event in page_events {
+        if let Some(vendor) = &event.vendor {
+            events_by_vendor
+                .entry(vendor.id.to_raw())
+                .or_default()
+                .push(event);
+        }
+    }
+
+    let mut features = Vec::new();
+    for vendor in listing.records.iter() {
+        let events = events_by_vendor
+            .get(vendor.uuid.as_ref())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        // The properties are the vendor's fields as projected by the fields query parameter
+        let mut properties = match serde_json::to_value(vendor) {
+            Ok(properties) => properties,
+            Err(err) => {
+                return Err(ApiError::Internal(format!(
+                    "Failed to serialize vendor: {err}"
+                )))
+            }
+        };
+        page_params.project(&mut properties);
+        let properties = match properties {
+            Value::Object(properties) => properties,
+            _ => Map::new(),
+        };
+        features.push(geojson::vendor_feature(vendor, properties, events, now));
+    }
+    let mut collection = FeatureCollection::new(features);
+    collection.next_cursor = listing.next_cursor;
+    collection.total = Some(listing.total);
+    Ok(geojson_response(collection))
 }
 
-// TODO: Test for bugs
+// A single event is returned as it is, every event or a vendor's events a page at a time
+// Responds with a GeoJSON Feature or FeatureCollection instead when asked for, see
+//      wants_geojson
 pub async fn get_events(
     Path(params): Path<HashMap<String, String>>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
     State(state): State<state::AppState>,
-) -> impl IntoResponse {
-    println!("->> HANDLER - Events GET - {params:<60?}");
-    if let Some(vendor_id) = params.get("vendor_id") {
-        let events_vec_result: Result<Vec<Event>, surrealdb::Error> =
-            state.db.select("events").await;
-        let events_vec = match events_vec_result {
-            Ok(events_vec) => events_vec,
-            Err(err) => {
-                println!("Failed in result match\n{err:?}");
-                return Json::default();
-            }
+) -> Result<Response, ApiError> {
+    let geojson = wants_geojson(&query, &headers)?;
+    let now = Utc::now().fixed_offset();
+    if let Some(event_id) = params.get("event_id") {
+        let event_option: Option<Event> = state.store.get(event_id).await?;
+        let event = match event_option {
+            Some(event) => event,
+            None => return Err(ApiError::not_found("events", event_id)),
         };
-        let events_vec: Vec<Event> = events_vec
-            .iter()
-            .map(|e| e.clone())
-            .filter(|event| match &event.vendor {
-                Some(vendor) => vendor.id.to_string() == vendor_id.clone(),
-                None => false,
-            })
-            .collect();
-        return Json(events_vec);
-    } else if let Some(event_id) = params.get("event_id") {
-        let event_result: Result<Option<Event>, surrealdb::Error> =
-            state.db.select(("events", event_id)).await;
-        let event_option = match event_result {
-            Ok(event_option) => event_option,
+        if !geojson {
+            return Ok(Json(event).into_response());
+        }
+        let vendors = page_vendors(&state, std::slice::from_ref(&event)).await?;
+        let properties = match serde_json::to_value(&event) {
+            Ok(Value::Object(properties)) => properties,
+            _ => Map::new(),
+        };
+        let feature = geojson::event_feature(&event, properties, &vendors_by_uuid(&vendors), now);
+        return Ok(geojson_response(feature));
+    }
+
+    let page_params = PageParams::from_query::<Event>(
+        &query,
+        &[SortField::Name, SortField::Datetime, SortField::Distance],
+    )?;
+    let vendor_id = params.get("vendor_id").map(String::as_str);
+    let listing: Listing<Event> = state.store.page(&page_params.query(vendor_id)).await?;
+    if !geojson {
+        return Ok(Json(Page::new(listing, &page_params)?).into_response());
+    }
+
+    let vendors = page_vendors(&state, &listing.records).await?;
+    let vendors = vendors_by_uuid(&vendors);
+    let mut features = Vec::new();
+    for event in listing.records.iter() {
+        // The properties are the event's fields as projected by the fields query parameter
+        let mut properties = match serde_json::to_value(event) {
+            Ok(properties) => properties,
             Err(err) => {
-                println!("Failed in result match\n{:?}", err);
-                return Json::default();
+                return Err(ApiError::Internal(format!(
+                    "Failed to serialize event: {err}"
+                )))
             }
         };
-        let event = match event_option {
-            Some(event) => event,
-            None => return Json::default(),
+        page_params.project(&mut properties);
+        let properties = match properties {
+            Value::Object(properties) => properties,
+            _ => Map::new(),
         };
-        return Json(vec![event]);
-    }
-    let event_vec_result: Result<Vec<Event>, surrealdb::Error> = state.db.select("events").await;
-    let event_vec = match event_vec_result {
-        Ok(event_vec) => event_vec,
-        Err(err) => {
-            println!("{:?}", err);
-            return Json::default();
+        features.push(geojson::event_feature(event, properties, &vendors, now));
+    }
+    let mut collection = FeatureCollection::new(features);
+    collection.next_cursor = listing.next_cursor;
+    collection.total = Some(listing.total);
+    Ok(geojson_response(collection))
+}
+
+// Returns every occurrence of every event between from and to, sorted by start time
+// from defaults to now and to defaults to a week after from
+pub async fn get_occurrences(
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<state::AppState>,
+) -> Result<Json<Vec<Occurrence>>, ApiError> {
+    let (from, to) = parse_window(&query)?;
+    let events: Vec<Event> = state.store.list().await?;
+    Ok(Json(schedule::expand(&events, from, to)))
+}
+
+// Returns the occurrence of the event scheduled on the date, with its override applied
+// Cancelled occurrences are returned with cancelled set so they can be restored
+pub async fn get_occurrence(
+    Path(params): Path<HashMap<String, String>>,
+    State(state): State<state::AppState>,
+) -> Result<Json<Occurrence>, ApiError> {
+    let event_id = get_param(&params, "event_id")?;
+    let date = parse_date(&params, "date")?;
+    let event_option: Option<Event> = state.store.get(event_id).await?;
+    let event = match event_option {
+        Some(event) => event,
+        None => return Err(ApiError::not_found("events", event_id)),
+    };
+    match schedule::occurrence_on(&event, date) {
+        Some(occurrence) => Ok(Json(occurrence)),
+        None => Err(ApiError::no_occurrence(event_id, date)),
+    }
+}
+
+// Returns every occurrence in progress at the at query parameter, which defaults to now
+// Occurrences are open from their start up to but not including their end
+pub async fn get_open(
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<state::AppState>,
+) -> Result<Json<Vec<Occurrence>>, ApiError> {
+    let at = match query.get("at") {
+        Some(at) => match parse_datetime(at) {
+            Some(at) => at,
+            None => {
+                return Err(ApiError::Validation(vec![FieldError::new(
+                    "at",
+                    "must be an RFC 3339 datetime",
+                )]))
+            }
+        },
+        None => Utc::now().fixed_offset(),
+    };
+    let events: Vec<Event> = state.store.list().await?;
+    Ok(Json(schedule::open_at(&events, at)))
+}
+
+// Returns every occurrence between from and to within radius_m meters of lat and lon
+// Results are sorted nearest first and include their distance
+// radius_m defaults to 5km, the window defaults the same way as get_occurrences
+// Responds with a GeoJSON FeatureCollection of the occurrences instead when asked for, see
+//      wants_geojson
+pub async fn get_nearby(
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    State(state): State<state::AppState>,
+) -> Result<Response, ApiError> {
+    let geojson = wants_geojson(&query, &headers)?;
+    let lat = parse_number(&query, "lat", None, -90.0, 90.0)?;
+    let lon = parse_number(&query, "lon", None, -180.0, 180.0)?;
+    let radius_m = parse_number(&query, "radius_m", Some(5000.0), 0.0, MAX_RADIUS_M)?;
+    let (from, to) = parse_window(&query)?;
+
+    let events: Vec<Event> = state.store.list().await?;
+    // Filtering by distance first avoids expanding the schedule of every far away event
+    let events: Vec<Event> = events
+        .into_iter()
+        .filter(|event| geo::may_be_near(event, lat, lon, radius_m))
+        .collect();
+
+    let occurrences = schedule::expand(&events, from, to);
+    let nearby = geo::nearby(occurrences, lat, lon, radius_m);
+    if !geojson {
+        return Ok(Json(nearby).into_response());
+    }
+
+    // Only the vendors of the nearby occurrences are read
+    let vendor_ids = linked_ids(nearby.iter().map(|nearby| &nearby.occurrence.event.vendor));
+    let vendors: Vec<Vendor> = state.store.get_many(&vendor_ids).await?;
+    let vendors = vendors_by_uuid(&vendors);
+    let features = nearby
+        .iter()
+        .map(|nearby| geojson::nearby_feature(nearby, &vendors))
+        .collect();
+    Ok(geojson_response(FeatureCollection::new(features)))
+}
+
+// Returns events as an iCalendar feed which calendar apps can subscribe to
+// With a vendor_id the feed holds that vendor's events, otherwise every event, optionally only
+//      those within radius_m meters of near
+//      near        -> latitude,longitude such as 42.33,-83.05
+//      radius_m    -> defaults to 5km, only used with near
+pub async fn get_calendar(
+    Path(params): Path<HashMap<String, String>>,
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<state::AppState>,
+) -> Result<Response, ApiError> {
+    let (name, events, menus, vendors) = match params.get("vendor_id") {
+        Some(vendor_id) => {
+            let vendor: Vendor = match state.store.get(vendor_id).await? {
+                Some(vendor) => vendor,
+                None => return Err(ApiError::not_found("vendors", vendor_id)),
+            };
+            let events: Vec<Event> = state.store.list_by_vendor(vendor_id).await?;
+            let menus: Vec<Menu> = state.store.list_by_vendor(vendor_id).await?;
+            (vendor.name.to_string(), events, menus, vec![vendor])
+        }
+        None => {
+            let events: Vec<Event> = state.store.list().await?;
+            let (name, events) = match parse_near(&query)? {
+                Some((lat, lon)) => {
+                    let radius_m =
+                        parse_number(&query, "radius_m", Some(5000.0), 0.0, MAX_RADIUS_M)?;
+                    let events = events
+                        .into_iter()
+                        .filter(|event| geo::may_be_near(event, lat, lon, radius_m))
+                        .collect();
+                    ("Food trucks nearby".to_string(), events)
+                }
+                None if query.contains_key("radius_m") => {
+                    return Err(ApiError::Validation(vec![FieldError::new(
+                        "radius_m",
+                        "can only be given along with near",
+                    )]))
+                }
+                None => ("Food trucks".to_string(), events),
+            };
+            // Only the menus and vendors the feed's events link to are read
+            let menu_ids = linked_ids(events.iter().map(|event| &event.menu));
+            let menus: Vec<Menu> = state.store.get_many(&menu_ids).await?;
+            let vendors = page_vendors(&state, &events).await?;
+            (name, events, menus, vendors)
         }
     };
-    Json(event_vec)
+
+    Ok((
+        [(CONTENT_TYPE, "text/calendar; charset=utf-8")],
+        ical::calendar(&name, &events, &menus, &vendors),
+    )
+        .into_response())
 }
 
-// TODO: Test for bugs
+// A single menu is returned as it is, a vendor's menus a page at a time
 pub async fn get_menus(
     Path(params): Path<HashMap<String, String>>,
+    Query(query): Query<HashMap<String, String>>,
     State(state): State<state::AppState>,
-) -> impl IntoResponse {
+) -> Result<Response, ApiError> {
     if let Some(menu_id) = params.get("menu_id") {
-        let menu_option_result: Result<Option<Menu>, surrealdb::Error> =
-            state.db.select(("menus", menu_id)).await;
-        let menu_option = match menu_option_result {
-            Ok(menu_option) => menu_option,
-            Err(err) => {
-                println!("failed in result match: line 293\n{:?}", err);
-                return Json::default();
-            }
-        };
-        let menu = match menu_option {
-            Some(menu) => menu,
-            None => {
-                return Json::default();
-            }
-        };
-        return Json(vec![menu]);
-    } else if let Some(vendor_id) = params.get("vendor_id") {
-        let menu_vec_result: Result<Vec<Menu>, surrealdb::Error> = state.db.select("menus").await;
-        let menu_vec = match menu_vec_result {
-            Ok(menu_vec) => menu_vec,
-            Err(err) => {
-                println!("failed in result match: line 310\n{:?}", err);
-                return Json::default();
-            }
+        let menu_option: Option<Menu> = state.store.get(menu_id).await?;
+        return match menu_option {
+            Some(menu) => Ok(Json(menu).into_response()),
+            None => Err(ApiError::not_found("menus", menu_id)),
         };
-        let menu_vec = menu_vec
-            .iter()
-            .map(|m| m.clone())
-            .filter(|menu| match &menu.vendor {
-                Some(vendor) => vendor.id.to_string() == vendor_id.to_owned(),
-                None => false,
-            })
-            .collect();
-        return Json(menu_vec);
     }
-    Json(vec![])
+    let page_params = PageParams::from_query::<Menu>(&query, &[SortField::Name])?;
+    let listing: Listing<Menu> = match params.get("vendor_id") {
+        Some(vendor_id) => {
+            state
+                .store
+                .page(&page_params.query(Some(vendor_id)))
+                .await?
+        }
+        None => page_records(Vec::new(), &page_params.query(None))?,
+    };
+    Ok(Json(Page::new(listing, &page_params)?).into_response())
 }
 
-// TODO: Test for bugs
+// A single item is returned as it is, a vendor's or menu's items a page at a time
+// A menu's items are all read through its links and paged in memory, menus are short
 pub async fn get_items(
     Path(params): Path<HashMap<String, String>>,
+    Query(query): Query<HashMap<String, String>>,
     State(state): State<state::AppState>,
-) -> impl IntoResponse {
+) -> Result<Response, ApiError> {
     if let Some(item_id) = params.get("item_id") {
-        let item_option_result: Result<Option<Item>, surrealdb::Error> =
-            state.db.select(("items", item_id)).await;
-        let item_option = match item_option_result {
-            Ok(item_option) => item_option,
-            Err(err) => {
-                println!("Failed in result match\n{:?}", err);
-                return Json::default();
-            }
+        let item_option: Option<Item> = state.store.get(item_id).await?;
+        return match item_option {
+            Some(item) => Ok(Json(item).into_response()),
+            None => Err(ApiError::not_found("items", item_id)),
         };
-        let item = match item_option {
-            Some(item) => item,
-            None => {
-                return Json::default();
-            }
-        };
-        return Json(vec![item]);
-    } else if let Some(vendor_id) = params.get("vendor_id") {
-        let item_vec_result: Result<Vec<Item>, surrealdb::Error> = state.db.select("items").await;
-        let item_vec = match item_vec_result {
-            Ok(item_vec) => item_vec,
-            Err(err) => {
-                println!("Failed in result match\n{:?}", err);
-                return Json::default();
-            }
-        };
-        let item_vec = item_vec
-            .iter()
-            .map(|i| i.clone())
-            .filter(|item| match &item.vendor {
-                Some(vendor) => vendor.id.to_string() == vendor_id.to_owned(),
-                None => false,
-            })
-            .collect();
-        return Json(item_vec);
+    }
+    let page_params = PageParams::from_query::<Item>(&query, &[SortField::Name])?;
+    let listing: Listing<Item> = if let Some(vendor_id) = params.get("vendor_id") {
+        state
+            .store
+            .page(&page_params.query(Some(vendor_id)))
+            .await?
     } else if let Some(menu_id) = params.get("menu_id") {
-        let menu_option_result: Result<Option<Menu>, surrealdb::Error> =
-            state.db.select(("menus", menu_id)).await;
-        let menu_option = match menu_option_result {
-            Ok(menu_option) => menu_option,
-            Err(err) => {
-                println!("Failed in result match\n{:?}", err);
-                return Json::default();
-            }
-        };
-        let menu = match menu_option {
-            Some(menu) => menu,
-            None => {
-                return Json::default();
-            }
+        let items_vec = match state.store.items_by_menu(menu_id).await? {
+            Some(items_vec) => items_vec,
+            None => return Err(ApiError::not_found("menus", menu_id)),
         };
+        page_records(items_vec, &page_params.query(None))?
+    } else {
+        page_records(Vec::new(), &page_params.query(None))?
+    };
+    Ok(Json(Page::new(listing, &page_params)?).into_response())
+}
 
-        let menu_items: Vec<String> = menu.items.iter().map(|item| item.id.to_string()).collect();
+// Searches vendors, menus and items, results are grouped by kind and ranked best match first
+//      q       -> The words to search for, close misspellings and the start of words also match
+//      limit   -> Results per group, defaults to 10, at most 50
+pub async fn get_search(
+    Query(query): Query<HashMap<String, String>>,
+    State(state): State<state::AppState>,
+) -> Result<Json<SearchResults>, ApiError> {
+    let q = match query.get("q").map(|q| q.trim()) {
+        Some(q) if !q.is_empty() => q,
+        _ => {
+            return Err(ApiError::Validation(vec![FieldError::new(
+                "q",
+                "is required",
+            )]))
+        }
+    };
+    if q.chars().count() > MAX_QUERY_LENGTH {
+        return Err(ApiError::Validation(vec![FieldError::new(
+            "q",
+            "must be at most 200 characters",
+        )]));
+    }
+    let limit = parse_integer(&query, "limit", Some(10), 1, MAX_SEARCH_LIMIT)?;
 
-        let items_vec_result: Result<Vec<Item>, surrealdb::Error> = state.db.select("items").await;
+    // Only records containing part of the query are read, at most MAX_SEARCH_CANDIDATES of each
+    let fragments = search::fragments(q);
+    let mut vendors: Vec<Vendor> = state
+        .store
+        .containing(search::VENDOR_FIELDS, &fragments, MAX_SEARCH_CANDIDATES)
+        .await?;
+    let menus: Vec<Menu> = state
+        .store
+        .containing(search::MENU_FIELDS, &fragments, MAX_SEARCH_CANDIDATES)
+        .await?;
+    let items: Vec<Item> = state
+        .store
+        .containing(search::ITEM_FIELDS, &fragments, MAX_SEARCH_CANDIDATES)
+        .await?;
+    // Vendors are also found through what they sell, so the sellers which weren't found
+    //      themselves are read as well
+    let found: Vec<&str> = vendors.iter().map(|vendor| vendor.uuid.as_ref()).collect();
+    let mut sellers: Vec<String> = menus
+        .iter()
+        .map(|menu| &menu.vendor)
+        .chain(items.iter().map(|item| &item.vendor))
+        .filter_map(|vendor| vendor.as_ref().map(|vendor| vendor.id.to_raw()))
+        .filter(|vendor_id| !found.contains(&vendor_id.as_str()))
+        .collect();
+    sellers.sort();
+    sellers.dedup();
+    let sellers: Vec<Vendor> = state.store.get_many(&sellers).await?;
+    vendors.extend(sellers);
+    Ok(Json(search::search(q, &vendors, &menus, &items, limit)))
+}
 
-        let items_vec = match items_vec_result {
-            Ok(items_vec) => items_vec,
-            Err(err) => {
-                println!("Failed in result match\n{:?}", err);
-                return Json::default();
-            }
-        };
+// Longer queries are almost certainly not typed by a person and make matching slower
+const MAX_QUERY_LENGTH: usize = 200;
+
+// The most results search returns for each kind of record
+const MAX_SEARCH_LIMIT: usize = 50;
+
+// The most records of each kind search reads from the store to rank
+const MAX_SEARCH_CANDIDATES: usize = 500;
+
+// The longest window occurrences can be requested for, keeps daily events from producing
+//      unbounded responses
+const MAX_WINDOW_DAYS: i64 = 366;
+
+// The largest radius nearby events can be searched for in, 100km
+const MAX_RADIUS_M: f64 = 100_000.0;
+
+// Whether the response should be GeoJSON, asked for with format=geojson or an Accept header
+//      naming application/geo+json
+// format=json asks for the usual response whatever the Accept header says
+fn wants_geojson(query: &HashMap<String, String>, headers: &HeaderMap) -> Result<bool, ApiError> {
+    match query.get("format").map(String::as_str) {
+        Some("geojson") => return Ok(true),
+        Some("json") => return Ok(false),
+        Some(_) => {
+            return Err(ApiError::Validation(vec![FieldError::new(
+                "format",
+                "must be json or geojson",
+            )]))
+        }
+        None => (),
+    }
+    Ok(headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|accept| accept.to_str().ok())
+        .flat_map(|accept| accept.split(','))
+        .any(|media| {
+            media
+                .split(';')
+                .next()
+                .is_some_and(|media| media.trim().eq_ignore_ascii_case(geojson::CONTENT_TYPE))
+        }))
+}
+
+fn geojson_response<T: Serialize>(geojson: T) -> Response {
+    ([(CONTENT_TYPE, geojson::CONTENT_TYPE)], Json(geojson)).into_response()
+}
+
+// The vendors of the events, read together in one query
+async fn page_vendors(state: &state::AppState, events: &[Event]) -> Result<Vec<Vendor>, ApiError> {
+    let vendor_ids = linked_ids(events.iter().map(|event| &event.vendor));
+    Ok(state.store.get_many(&vendor_ids).await?)
+}
 
-        let items_vec = items_vec
-            .iter()
-            .map(|i| i.clone())
-            .filter(|item| menu_items.contains(&item.uuid.to_string()))
-            .collect();
+// The distinct uuids of the linked records
+fn linked_ids<'a>(links: impl Iterator<Item = &'a Option<Thing>>) -> Vec<String> {
+    let mut ids: Vec<String> = links
+        .filter_map(|link| link.as_ref().map(|link| link.id.to_raw()))
+        .collect();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+fn vendors_by_uuid(vendors: &[Vendor]) -> HashMap<String, &Vendor> {
+    vendors
+        .iter()
+        .map(|vendor| (vendor.uuid.to_string(), vendor))
+        .collect()
+}
+
+// Reads the near query parameter, a latitude and longitude separated by a comma
+fn parse_near(query: &HashMap<String, String>) -> Result<Option<(f64, f64)>, ApiError> {
+    let near = match query.get("near") {
+        Some(near) => near,
+        None => return Ok(None),
+    };
+    let cords: Vec<Option<f64>> = near
+        .split(',')
+        .map(|cord| cord.trim().parse::<f64>().ok())
+        .collect();
+    match cords[..] {
+        [Some(lat), Some(lon)]
+            if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) =>
+        {
+            Ok(Some((lat, lon)))
+        }
+        _ => Err(ApiError::Validation(vec![FieldError::new(
+            "near",
+            "must be a latitude and longitude such as 42.33,-83.05",
+        )])),
+    }
+}
 
-        return Json(items_vec);
+// Reads the from and to query parameters into a window of time
+fn parse_window(
+    query: &HashMap<String, String>,
+) -> Result<(DateTime<FixedOffset>, DateTime<FixedOffset>), ApiError> {
+    let from = match query.get("from") {
+        Some(from) => match parse_datetime(from) {
+            Some(from) => from,
+            None => {
+                return Err(ApiError::Validation(vec![FieldError::new(
+                    "from",
+                    "must be an RFC 3339 datetime",
+                )]))
+            }
+        },
+        None => Utc::now().fixed_offset(),
+    };
+    let to = match query.get("to") {
+        Some(to) => match parse_datetime(to) {
+            Some(to) => to,
+            None => {
+                return Err(ApiError::Validation(vec![FieldError::new(
+                    "to",
+                    "must be an RFC 3339 datetime",
+                )]))
+            }
+        },
+        None => from + Duration::days(7),
+    };
+    if to <= from {
+        return Err(ApiError::Validation(vec![FieldError::new(
+            "to",
+            "must be after from",
+        )]));
+    }
+    if to - from > Duration::days(MAX_WINDOW_DAYS) {
+        return Err(ApiError::Validation(vec![FieldError::new(
+            "to",
+            "must be at most 366 days after from",
+        )]));
     }
-    Json(vec![])
+    Ok((from, to))
 }
@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::server::error::RETRY_AFTER_SECS;
+use crate::server::state;
+use axum::extract::State;
+use axum::http::{header::RETRY_AFTER, StatusCode};
+use axum::response::{IntoResponse, Json, Response};
+use serde::Serialize;
+
+// How long the geocoder has to respond before the server is reported as not ready
+const GEOCODER_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Serialize, Debug)]
+pub struct Liveness {
+    status: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Readiness {
+    status: &'static str,
+    // The result of each dependency check, "ok" or why it failed
+    checks: BTreeMap<&'static str, String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Version {
+    name: &'static str,
+    version: &'static str,
+    commit: &'static str,
+    profile: &'static str,
+}
+
+// Answers as long as the process is running and able to serve requests
+pub async fn healthz() -> Json<Liveness> {
+    Json(Liveness { status: "ok" })
+}
+
+// Answers 200 once the database responds, 503 with a Retry-After otherwise so no traffic is
+//      routed here
+// The geocoder is only checked when health.check_geocoder is set
+pub async fn readyz(State(state): State<state::AppState>) -> Response {
+    let mut checks = BTreeMap::new();
+    let mut ready = true;
+
+    match state.store.ping().await {
+        Ok(()) => {
+            checks.insert("database", "ok".to_string());
+        }
+        Err(err) => {
+            ready = false;
+            checks.insert("database", err.to_string());
+        }
+    }
+
+    if state.config.health.check_geocoder {
+        match check_geocoder(&state.config.health.geocoder_url).await {
+            Ok(()) => {
+                checks.insert("geocoder", "ok".to_string());
+            }
+            Err(err) => {
+                ready = false;
+                checks.insert("geocoder", err);
+            }
+        }
+    }
+
+    match ready {
+        true => (
+            StatusCode::OK,
+            Json(Readiness {
+                status: "ready",
+                checks,
+            }),
+        )
+            .into_response(),
+        false => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(RETRY_AFTER, RETRY_AFTER_SECS)],
+            Json(Readiness {
+                status: "unavailable",
+                checks,
+            }),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn version() -> Json<Version> {
+    Json(Version {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        commit: env!("FTF_GIT_SHA"),
+        profile: env!("FTF_BUILD_PROFILE"),
+    })
+}
+
+async fn check_geocoder(url: &str) -> Result<(), String> {
+    let client = match reqwest::Client::builder().timeout(GEOCODER_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(err) => return Err(err.to_string()),
+    };
+    match client.get(url).send().await {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => Err(format!("responded with {}", response.status())),
+        Err(err) => Err(err.to_string()),
+    }
+}
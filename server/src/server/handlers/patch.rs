@@ -1,197 +1,295 @@
 use std::collections::HashMap;
 
-use crate::database::models::{Item, Menu, Vendor};
+use crate::database::models::{Event, Item, Location, Menu, OccurrenceOverride, Vendor};
+use crate::database::schedule::{self, Occurrence};
+use crate::database::store::{Model, Repository, Store};
+use crate::server::error::{get_param, parse_date, ApiError, ApiJson, FieldError};
 use crate::server::state;
-use axum::extract::{Json as ExtractJson, Path, State};
-use axum::response::IntoResponse;
+use axum::extract::{Path, State};
 use axum::response::Json;
-use serde_json::Value;
-use surrealdb::opt::PatchOp;
+use chrono::NaiveDate;
+use serde_json::{json, Value};
+use surrealdb::sql::Thing;
 
 pub async fn patch_vendor(
     Path(params): Path<HashMap<String, String>>,
     State(state): State<state::AppState>,
-    Json(json): ExtractJson<serde_json::Value>,
-) -> impl IntoResponse {
-    if let Some(vendor_id) = params.get("vendor_id") {
-        let mut patch_pairs: Vec<(&str, &Value)> = Vec::new();
+    ApiJson(json): ApiJson<serde_json::Value>,
+) -> Result<Json<Vendor>, ApiError> {
+    let vendor_id = get_param(&params, "vendor_id")?;
+    let mut patch_pairs: Vec<(String, Value)> = Vec::new();
 
-        if let Some(name) = json.get("name") {
-            patch_pairs.push(("/name", name));
-        }
-        if let Some(description) = json.get("description") {
-            patch_pairs.push(("/description", description));
-        }
-        if let Some(vendor_type) = json.get("vendor_type") {
-            patch_pairs.push(("/vendor_type", vendor_type));
-        }
-        if let Some(email) = json.get("email") {
-            patch_pairs.push(("/email", email));
-        }
-        if let Some(phone_number) = json.get("phone_number") {
-            patch_pairs.push(("/phone_number", phone_number));
-        }
-        if let Some(website) = json.get("website") {
-            patch_pairs.push(("/website", website));
-        }
-
-        let mut vendor_option_result: Result<Option<Vendor>, surrealdb::Error> = Ok(None);
-
-        for patch_pair in patch_pairs.iter() {
-            vendor_option_result = state
-                .db
-                .update(("vendors", vendor_id))
-                .patch(PatchOp::replace(patch_pair.0, patch_pair.1))
-                .await;
-        }
-
-        let vendor_option = match vendor_option_result {
-            Ok(vendor_option) => vendor_option,
-            Err(err) => {
-                println!("Failed in result match\n{err:?}");
-                return Json::default();
-            }
-        };
-
-        let vendor = match vendor_option {
-            Some(vendor) => vendor,
-            None => return Json::default(),
-        };
-
-        return Json(vendor);
+    if let Some(name) = json.get("name") {
+        patch_pairs.push(("/name".to_string(), name.clone()));
+    }
+    if let Some(description) = json.get("description") {
+        patch_pairs.push(("/description".to_string(), description.clone()));
+    }
+    if let Some(vendor_type) = json.get("vendor_type") {
+        patch_pairs.push(("/vendor_type".to_string(), vendor_type.clone()));
+    }
+    if let Some(email) = json.get("email") {
+        patch_pairs.push(("/email".to_string(), email.clone()));
     }
-    Json::default()
+    if let Some(phone_number) = json.get("phone_number") {
+        patch_pairs.push(("/phone_number".to_string(), phone_number.clone()));
+    }
+    if let Some(website) = json.get("website") {
+        patch_pairs.push(("/website".to_string(), website.clone()));
+    }
+
+    apply_patches::<Vendor>(&state, vendor_id, patch_pairs).await
 }
 
+// The fields besides name are checked against the stored event
+//      location    -> Geocoded unless cord_x and cord_y are given with it
+//      menu        -> A menu of the event's vendor, or null for none
+//      timing      -> See Event::apply_timing, rejected while an occurrence override is on a date
+//                     the event would no longer be scheduled on
 pub async fn patch_event(
     Path(params): Path<HashMap<String, String>>,
     State(state): State<state::AppState>,
-    Json(json): ExtractJson<serde_json::Value>,
-) -> impl IntoResponse {
-    if let Some(event_id) = params.get("event_id") {
-        let mut patch_pairs: Vec<(&str, &Value)> = Vec::new();
-        if let Some(name) = json.get("name") {
-            patch_pairs.push(("/name", name));
-        }
-        if let Some(datetime) = json.get("datetime") {
-            patch_pairs.push(("/datetime", datetime));
-        }
-        if let Some(location) = json.get("location") {
-            patch_pairs.push(("/location", location));
-        }
-        if let Some(menu) = json.get("menu") {
-            patch_pairs.push(("/menu", menu));
-        }
-        if let Some(repeat_schedule) = json.get("repeat_schedule") {
-            patch_pairs.push(("/repeat_schedule", repeat_schedule));
-        }
-        if let Some(repeat_end) = json.get("repeat_end") {
-            patch_pairs.push(("/repeat_end", repeat_end));
-        }
+    ApiJson(json): ApiJson<serde_json::Value>,
+) -> Result<Json<Event>, ApiError> {
+    let event_id = get_param(&params, "event_id")?;
+    let mut patch_pairs: Vec<(String, Value)> = Vec::new();
 
-        let mut event_option_result: Result<Option<Menu>, surrealdb::Error> = Ok(None);
+    if let Some(name) = json.get("name") {
+        patch_pairs.push(("/name".to_string(), name.clone()));
+    }
+
+    let located = ["location", "cord_x", "cord_y"]
+        .iter()
+        .any(|field| json.get(field).is_some());
+    // The timing fields depend on each other, so they are checked against the stored event and
+    //      written back together in the event's time zone
+    let timing = [
+        "datetime",
+        "timezone",
+        "end",
+        "duration_minutes",
+        "repeat_schedule",
+        "repeat_end",
+    ];
+    let timed = timing.iter().any(|field| json.get(field).is_some());
+    if !located && !timed && json.get("menu").is_none() {
+        return apply_patches::<Event>(&state, event_id, patch_pairs).await;
+    }
+
+    let mut event: Event = match state.store.get(event_id).await? {
+        Some(event) => event,
+        None => return Err(ApiError::not_found(Event::TABLE, event_id)),
+    };
 
-        for patch_pair in patch_pairs.iter() {
-            event_option_result = state
-                .db
-                .update(("events", event_id))
-                .patch(PatchOp::replace(patch_pair.0, patch_pair.1))
-                .await;
+    if located {
+        if let Some(address) = event.apply_location(&json)? {
+            let location = geocode(address).await?;
+            if location.x == 0.0 && location.y == 0.0 {
+                return Err(ApiError::Validation(vec![FieldError::new(
+                    "location",
+                    "couldn't be geocoded, give its cord_x and cord_y",
+                )]));
+            }
+            event = event.with_cords(location.x, location.y);
         }
+        patch_pairs.push(("/location".to_string(), json!(event.location)));
+        patch_pairs.push(("/cord_x".to_string(), json!(event.cord_x)));
+        patch_pairs.push(("/cord_y".to_string(), json!(event.cord_y)));
+    }
 
-        let event_option = match event_option_result {
-            Ok(event_option) => event_option,
-            Err(err) => {
-                println!("Failed in result match\n{err:?}");
-                return Json::default();
+    if let Some(menu) = json.get("menu") {
+        event.menu = match menu {
+            Value::Null => None,
+            Value::String(menu_id) => {
+                let menu: Option<Menu> = state.store.get(menu_id).await?;
+                match menu {
+                    Some(menu) if menu.vendor() == event.vendor => Some(Thing {
+                        tb: Menu::TABLE.into(),
+                        id: menu_id.as_str().into(),
+                    }),
+                    Some(_) => {
+                        return Err(ApiError::Validation(vec![FieldError::new(
+                            "menu",
+                            "is not a menu of the event's vendor",
+                        )]))
+                    }
+                    None => {
+                        return Err(ApiError::Validation(vec![FieldError::new(
+                            "menu",
+                            &format!("{menu_id} is not a menu"),
+                        )]))
+                    }
+                }
+            }
+            _ => {
+                return Err(ApiError::Validation(vec![FieldError::new(
+                    "menu",
+                    "must be the uuid of a menu, or null for none",
+                )]))
             }
         };
+        patch_pairs.push(("/menu".to_string(), json!(event.menu)));
+    }
 
-        let event = match event_option {
-            Some(event) => event,
-            None => return Json::default(),
-        };
+    if timed {
+        event.apply_timing(&json)?;
+        // Overrides are kept by the date the occurrence was scheduled on, so they are left behind
+        //      when the schedule moves away from the date
+        let stranded: Vec<String> = event
+            .overrides
+            .iter()
+            .filter(|over| schedule::scheduled_on(&event, over.date).is_none())
+            .map(|over| over.date.to_string())
+            .collect();
+        if !stranded.is_empty() {
+            return Err(ApiError::Validation(vec![FieldError::new(
+                "overrides",
+                &format!(
+                    "{} would no longer be scheduled, clear the overrides of those occurrences first",
+                    stranded.join(", ")
+                ),
+            )]));
+        }
+        patch_pairs.push(("/datetime".to_string(), json!(event.datetime)));
+        patch_pairs.push(("/timezone".to_string(), json!(event.timezone)));
+        patch_pairs.push((
+            "/duration_minutes".to_string(),
+            json!(event.duration_minutes),
+        ));
+        patch_pairs.push(("/repeat_schedule".to_string(), json!(event.repeat_schedule)));
+        patch_pairs.push(("/repeat_end".to_string(), json!(event.repeat_end)));
+    }
 
-        return Json(event);
+    apply_patches::<Event>(&state, event_id, patch_pairs).await
+}
+
+// Changes a single occurrence of the event, the fields are those of OccurrenceOverride
+// Setting every field back to null or false removes the override
+pub async fn patch_occurrence(
+    Path(params): Path<HashMap<String, String>>,
+    State(state): State<state::AppState>,
+    ApiJson(json): ApiJson<serde_json::Value>,
+) -> Result<Json<Occurrence>, ApiError> {
+    let event_id = get_param(&params, "event_id")?;
+    let date = parse_date(&params, "date")?;
+    let fields = ["cancelled", "start", "location", "cord_x", "cord_y", "note"];
+    if !fields.iter().any(|field| json.get(field).is_some()) {
+        return Err(ApiError::Validation(vec![FieldError::new(
+            "body",
+            "contains no fields which can be patched",
+        )]));
     }
-    Json::default()
+
+    override_occurrence(&state, event_id, date, &json).await
 }
 
 pub async fn patch_menu(
     Path(params): Path<HashMap<String, String>>,
     State(state): State<state::AppState>,
-    Json(json): ExtractJson<serde_json::Value>,
-) -> impl IntoResponse {
-    if let Some(menu_id) = params.get("menu_id") {
-        if let Some(name) = json.get("name") {
-            let menu_option_result: Result<Option<Menu>, surrealdb::Error> = state
-                .db
-                .update(("menus", menu_id))
-                .patch(PatchOp::replace("/name", name))
-                .await;
-            let menu_option = match menu_option_result {
-                Ok(menu_option) => menu_option,
-                Err(err) => {
-                    println!("Failed in result match\n{err:?}");
-                    return Json::default();
-                }
-            };
-
-            let menu = match menu_option {
-                Some(menu) => menu,
-                None => return Json::default(),
-            };
+    ApiJson(json): ApiJson<serde_json::Value>,
+) -> Result<Json<Menu>, ApiError> {
+    let menu_id = get_param(&params, "menu_id")?;
+    let mut patch_pairs: Vec<(String, Value)> = Vec::new();
 
-            return Json(menu);
-        }
+    if let Some(name) = json.get("name") {
+        patch_pairs.push(("/name".to_string(), name.clone()));
     }
-    Json::default()
+
+    apply_patches::<Menu>(&state, menu_id, patch_pairs).await
 }
 
 pub async fn patch_item(
     Path(params): Path<HashMap<String, String>>,
     State(state): State<state::AppState>,
-    Json(json): ExtractJson<serde_json::Value>,
-) -> impl IntoResponse {
-    if let Some(item_id) = params.get("item_id") {
-        let mut patch_pairs: Vec<(&str, &Value)> = Vec::new();
-        if let Some(name) = json.get("name") {
-            patch_pairs.push(("/name", name));
-        }
-        if let Some(description) = json.get("description") {
-            patch_pairs.push(("/description", description));
-        }
-        if let Some(price) = json.get("price") {
-            patch_pairs.push(("/price", price));
-        }
-        if let Some(picture) = json.get("picture") {
-            patch_pairs.push(("/picture", picture));
-        }
+    ApiJson(json): ApiJson<serde_json::Value>,
+) -> Result<Json<Item>, ApiError> {
+    let item_id = get_param(&params, "item_id")?;
+    let mut patch_pairs: Vec<(String, Value)> = Vec::new();
 
-        let mut item_option_result: Result<Option<Item>, surrealdb::Error> = Ok(None);
+    if let Some(name) = json.get("name") {
+        patch_pairs.push(("/name".to_string(), name.clone()));
+    }
+    if let Some(description) = json.get("description") {
+        patch_pairs.push(("/description".to_string(), description.clone()));
+    }
+    if let Some(price) = json.get("price") {
+        patch_pairs.push(("/price".to_string(), price.clone()));
+    }
+    if let Some(picture) = json.get("picture") {
+        patch_pairs.push(("/picture".to_string(), picture.clone()));
+    }
 
-        for patch_pair in patch_pairs.iter() {
-            item_option_result = state
-                .db
-                .update(("items", item_id))
-                .patch(PatchOp::replace(patch_pair.0, patch_pair.1))
-                .await;
-        }
+    apply_patches::<Item>(&state, item_id, patch_pairs).await
+}
 
-        let item_option = match item_option_result {
-            Ok(item_option) => item_option,
-            Err(err) => {
-                println!("Failed in result match Test\n{err:?}");
-                return Json::default();
-            }
-        };
+// Applies the body to the override of the occurrence on the date and stores it with the event
+// Returns the occurrence with the override applied
+pub async fn override_occurrence(
+    state: &state::AppState,
+    event_id: &str,
+    date: NaiveDate,
+    json: &Value,
+) -> Result<Json<Occurrence>, ApiError> {
+    let event_option: Option<Event> = state.store.get(event_id).await?;
+    let event = match event_option {
+        Some(event) => event,
+        None => return Err(ApiError::not_found(Event::TABLE, event_id)),
+    };
+    if schedule::occurrence_on(&event, date).is_none() {
+        return Err(ApiError::no_occurrence(event_id, date));
+    }
 
-        let item = match item_option {
-            Some(item) => item,
-            None => return Json::default(),
-        };
+    let mut over = match event.override_on(date) {
+        Some(over) => over.clone(),
+        None => OccurrenceOverride::new(date),
+    };
+    over.apply(json, event.timezone)?;
+    // Only this date's override is written so overrides of other occurrences made meanwhile stay
+    let over = match over.is_empty() {
+        true => None,
+        false => Some(over),
+    };
+    let event_option = state.store.set_override(event_id, date, over).await?;
+    let event = match event_option {
+        Some(event) => event,
+        None => return Err(ApiError::not_found(Event::TABLE, event_id)),
+    };
+    match schedule::occurrence_on(&event, date) {
+        Some(occurrence) => Ok(Json(occurrence)),
+        None => Err(ApiError::no_occurrence(event_id, date)),
+    }
+}
+
+// Applies every patch to the record and returns the patched record
+async fn apply_patches<T>(
+    state: &state::AppState,
+    id: &str,
+    patch_pairs: Vec<(String, Value)>,
+) -> Result<Json<T>, ApiError>
+where
+    T: Model,
+    dyn Store: Repository<T>,
+{
+    if patch_pairs.is_empty() {
+        return Err(ApiError::Validation(vec![FieldError::new(
+            "body",
+            "contains no fields which can be patched",
+        )]));
+    }
+
+    let record_option: Option<T> = state.store.patch(id, patch_pairs).await?;
+    match record_option {
+        Some(record) => Ok(Json(record)),
+        None => Err(ApiError::not_found(T::TABLE, id)),
+    }
+}
 
-        return Json(item);
+// Geocoding blocks on the request to the geocoder, a location which can't be found is at 0, 0
+pub async fn geocode(address: String) -> Result<Location, ApiError> {
+    match tokio::task::spawn_blocking(move || Location::from(address)).await {
+        Ok(location) => Ok(location),
+        Err(err) => Err(ApiError::Internal(format!(
+            "Failed to geocode a location: {err}"
+        ))),
     }
-    Json::default()
 }
@@ -1,146 +1,167 @@
 use std::collections::HashMap;
 
+use crate::database::ical::{self, CalendarEntry, ImportReport, ImportStatus, ImportedEvent};
 use crate::database::models::{Event, Item, Menu, Record, Vendor};
+use crate::server::error::{get_param, require_strings, ApiError, ApiJson, FieldError};
+use crate::server::handlers::patch::geocode;
 use crate::server::state;
-use axum::extract::{Json as ExtractJson, Path, State};
-use axum::response::IntoResponse;
+use axum::extract::{Path, State};
 use axum::response::Json;
 
 // TODO: Test for bugs
 pub async fn post_vendor(
     State(state): State<state::AppState>,
-    Json(json): ExtractJson<serde_json::Value>,
-) -> impl IntoResponse {
-    println!("->> {:<12} - handler vendor_add - {json:?}", "HANDLER");
-
+    ApiJson(json): ApiJson<serde_json::Value>,
+) -> Result<Json<Record>, ApiError> {
+    require_strings(&json, &["name"])?;
     let vendor = Vendor::from(json);
 
-    let record_option_result: Result<Option<Record>, surrealdb::Error> = state
-        .db
-        .create(("vendors", vendor.uuid.to_string()))
-        .content(vendor)
-        .await;
-    let record_option = match record_option_result {
-        Ok(record_option) => record_option,
-        Err(err) => {
-            println!("Failed to add vendor to database: {err:?}");
-            return Json::default();
-        }
-    };
-    let record = match record_option {
-        Some(record) => record,
-        None => return Json::default(),
-    };
-
-    Json(record)
+    let record = state.store.create(vendor).await?;
+    Ok(Json(record))
 }
 
-// TODO: Test for bugs
+// Events are placed at cord_x and cord_y when they are given, otherwise their location is
+//      geocoded and rejected when it can't be found
 pub async fn post_event(
     Path(params): Path<HashMap<String, String>>,
     State(state): State<state::AppState>,
-    Json(json): ExtractJson<serde_json::Value>,
-) -> impl IntoResponse {
-    println!(
-        "->> {:<12} - handler post_event - {params:?} - {json:?}",
-        "HANDLER"
-    );
+    ApiJson(json): ApiJson<serde_json::Value>,
+) -> Result<Json<Record>, ApiError> {
+    let vendor_id = get_param(&params, "vendor_id")?;
+    // cord_x is only accepted along with cord_y
+    let located = json.get("cord_x").is_some();
+    let mut event = Event::try_from(json)?.with_vendor(vendor_id.into());
+    if !located {
+        let location = geocode(event.location.to_string()).await?;
+        if location.x == 0.0 && location.y == 0.0 {
+            return Err(ApiError::Validation(vec![FieldError::new(
+                "location",
+                "couldn't be geocoded, give its cord_x and cord_y",
+            )]));
+        }
+        event = event.with_cords(location.x, location.y);
+    }
 
-    if let Some(vendor_id) = params.get("vendor_id") {
-        let event = Event::from(json).with_vendor(vendor_id.into());
+    let record = state.store.create(event).await?;
+    Ok(Json(record))
+}
 
-        let record_option_result: Result<Option<Record>, surrealdb::Error> = state
-            .db
-            .create(("events", event.uuid.to_string()))
-            .content(event)
-            .await;
+// Creates or updates the vendor's events from the VEVENTs of an uploaded iCalendar file
+// VEVENTs are matched to events by UID, so a calendar can be imported again after it changes
+//      Updated events keep their menu and occurrence overrides
+//      LOCATIONs without a GEO are geocoded, unless the event is already at that location
+// Returns what happened to each VEVENT, ones which can't be imported are skipped with a reason
+pub async fn import_calendar(
+    Path(params): Path<HashMap<String, String>>,
+    State(state): State<state::AppState>,
+    body: String,
+) -> Result<Json<ImportReport>, ApiError> {
+    let vendor_id = get_param(&params, "vendor_id")?;
+    let vendor: Option<Vendor> = state.store.get(vendor_id).await?;
+    if vendor.is_none() {
+        return Err(ApiError::not_found("vendors", vendor_id));
+    }
+    let calendar = match ical::read(&body) {
+        Ok(calendar) => calendar,
+        Err(err) => return Err(ApiError::Validation(vec![FieldError::new("body", &err)])),
+    };
 
-        let record_option = match record_option_result {
-            Ok(record_option) => record_option,
-            Err(err) => {
-                println!("Failed to add event to database: {err:?}");
-                return Json::default();
+    let mut report = ImportReport::default();
+    let mut imported: Vec<String> = Vec::new();
+    // Calendars often repeat a location, each is only looked up once
+    let mut geocoded: HashMap<String, (f64, f64)> = HashMap::new();
+    for properties in calendar.events.iter() {
+        let entry = match CalendarEntry::read(properties, calendar.timezone) {
+            Ok(entry) => entry,
+            Err(reason) => {
+                report.skip(properties, reason);
+                continue;
             }
         };
+        let uuid = entry.uuid(vendor_id);
+        if imported.contains(&uuid) {
+            report.skip(properties, "has the same UID as an earlier VEVENT");
+            continue;
+        }
 
-        let record = match record_option {
-            Some(record) => record,
-            None => return Json::default(),
+        let mut event = entry.event.with_vendor(vendor_id.into());
+        event.uuid = uuid.clone().into();
+        let stored: Option<Event> = state.store.get(&uuid).await?;
+        let status = match stored {
+            Some(stored) if stored.vendor != event.vendor => {
+                report.skip(properties, "is an event of another vendor");
+                continue;
+            }
+            Some(stored) => {
+                event.menu = stored.menu;
+                event.overrides = stored.overrides;
+                if !entry.geo && stored.location == event.location {
+                    event = event.with_cords(stored.cord_x, stored.cord_y);
+                }
+                ImportStatus::Updated
+            }
+            None => ImportStatus::Created,
         };
 
-        return Json(record);
+        let mut warning = None;
+        if !entry.geo && event.cord_x == 0.0 && event.cord_y == 0.0 {
+            let address = event.location.to_string();
+            let (x, y) = match geocoded.get(&address) {
+                Some(cords) => *cords,
+                None => {
+                    let location = geocode(address).await?;
+                    geocoded.insert(location.address.to_string(), (location.x, location.y));
+                    (location.x, location.y)
+                }
+            };
+            if x == 0.0 && y == 0.0 {
+                warning =
+                    Some("LOCATION couldn't be geocoded, the event has no coordinates".into());
+            }
+            event = event.with_cords(x, y);
+        }
+
+        let summary = match event.name.as_ref() {
+            "" => None,
+            name => Some(name.to_string()),
+        };
+        state.store.upsert(event).await?;
+        report.push(ImportedEvent {
+            uid: Some(entry.uid),
+            summary,
+            status,
+            event_id: Some(uuid.clone()),
+            reason: None,
+            warning,
+        });
+        imported.push(uuid);
     }
-    Json::default()
+    Ok(Json(report))
 }
 
 // TODO: Test for bugs
 pub async fn post_menu(
     Path(params): Path<HashMap<String, String>>,
     State(state): State<state::AppState>,
-    Json(json): ExtractJson<serde_json::Value>,
-) -> impl IntoResponse {
-    println!(
-        "->> {:<12} - handler menu_add - {params:?} - {json:?}",
-        "HANDLER"
-    );
-
-    if let Some(vendor_id) = params.get("vendor_id") {
-        let menu = Menu::from(json).with_vendor(vendor_id.into());
-
-        let record_option_result: Result<Option<Record>, surrealdb::Error> = state
-            .db
-            .create(("menus", menu.uuid.to_string()))
-            .content(menu)
-            .await;
-
-        let record_option = match record_option_result {
-            Ok(record_option) => record_option,
-            Err(err) => {
-                println!("Failed to add menu to database: {err:?}");
-                return Json::default();
-            }
-        };
-
-        let record = match record_option {
-            Some(record) => record,
-            None => return Json::default(),
-        };
+    ApiJson(json): ApiJson<serde_json::Value>,
+) -> Result<Json<Record>, ApiError> {
+    let vendor_id = get_param(&params, "vendor_id")?;
+    let menu = Menu::from(json).with_vendor(vendor_id.into());
 
-        return Json(record);
-    }
-    Json::default()
+    let record = state.store.create(menu).await?;
+    Ok(Json(record))
 }
 
 // TODO: Test for bugs
 pub async fn post_item(
     Path(params): Path<HashMap<String, String>>,
     State(state): State<state::AppState>,
-    Json(json): ExtractJson<serde_json::Value>,
-) -> impl IntoResponse {
-    println!("->> {:<12} - handler item_add - {json:?}", "HANDLER");
-    if let Some(vendor_id) = params.get("vendor_id") {
-        let item = Item::from(json).with_vendor(vendor_id.into());
-
-        let record_option_result: Result<Option<Record>, surrealdb::Error> = state
-            .db
-            .create(("items", item.uuid.to_string()))
-            .content(item)
-            .await;
-
-        let record_option = match record_option_result {
-            Ok(record_option) => record_option,
-            Err(err) => {
-                println!("Failed to add item to database: {err:?}");
-                return Json::default();
-            }
-        };
-
-        let record = match record_option {
-            Some(record) => record,
-            None => return Json::default(),
-        };
-
-        return Json(record);
-    }
-    Json::default()
+    ApiJson(json): ApiJson<serde_json::Value>,
+) -> Result<Json<Record>, ApiError> {
+    let vendor_id = get_param(&params, "vendor_id")?;
+    require_strings(&json, &["name"])?;
+    let item = Item::from(json).with_vendor(vendor_id.into());
+
+    let record = state.store.create(item).await?;
+    Ok(Json(record))
 }
@@ -0,0 +1,208 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use crate::config::{LogFormat, LoggingConfig};
+use axum::{
+    body::Body,
+    extract::MatchedPath,
+    http::{header, HeaderName, Request, Response},
+    Router,
+};
+use color_eyre::{eyre::bail, Result};
+use tower::ServiceBuilder;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    sensitive_headers::SetSensitiveRequestHeadersLayer,
+    trace::TraceLayer,
+};
+use tracing::{debug, field, info, info_span, Span};
+use tracing_subscriber::EnvFilter;
+
+// Requests keep the id they were sent with, otherwise one is generated
+// The id is sent back on the response so clients can quote it when reporting problems
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Where the log lines are written, set once by init
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+
+#[derive(Clone)]
+enum Output {
+    Stdout,
+    Stderr,
+    File(Arc<File>),
+}
+
+impl Output {
+    fn open(output: &str) -> Result<Output> {
+        match output {
+            "stdout" => Ok(Output::Stdout),
+            "stderr" => Ok(Output::Stderr),
+            path => match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Ok(Output::File(Arc::new(file))),
+                Err(err) => bail!("Failed to open the log file {path}: {err}"),
+            },
+        }
+    }
+
+    // Colours are only written to the terminal
+    fn ansi(&self) -> bool {
+        match self {
+            Output::Stdout | Output::Stderr => true,
+            Output::File(_) => false,
+        }
+    }
+
+    // Files are also synced so the lines survive the machine going down with the process
+    fn sync(&self) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().flush(),
+            Output::Stderr => io::stderr().flush(),
+            Output::File(file) => {
+                let mut file: &File = file;
+                file.flush()?;
+                file.sync_data()
+            }
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout => io::stdout().write(buf),
+            Output::Stderr => io::stderr().write(buf),
+            Output::File(file) => (&**file).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().flush(),
+            Output::Stderr => io::stderr().flush(),
+            Output::File(file) => (&**file).flush(),
+        }
+    }
+}
+
+// Installs the global subscriber, records from the log crate used by dependencies are included
+pub fn init(config: &LoggingConfig) -> Result<()> {
+    let filter = match EnvFilter::try_new(&config.filter) {
+        Ok(filter) => filter,
+        Err(err) => bail!("Invalid log filter {}: {err}", config.filter),
+    };
+    let output = Output::open(&config.output)?;
+    let writer = output.clone();
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(output.ansi())
+        .with_writer(move || writer.clone());
+    let result = match config.format {
+        LogFormat::Pretty => builder.pretty().try_init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .try_init(),
+    };
+    match result {
+        Ok(()) => {
+            // try_init only succeeds once, so this is the first output set
+            let _ = OUTPUT.set(output);
+            Ok(())
+        }
+        Err(err) => bail!("Failed to install the logger: {err}"),
+    }
+}
+
+// Log lines are written as they happen so this only catches anything the output still holds
+// Called before the process exits, logs go to stdout if init was never called
+pub fn flush() {
+    let output = OUTPUT.get().unwrap_or(&Output::Stdout);
+    if let Err(err) = output.sync() {
+        eprintln!("Failed to flush the logs: {err}");
+    }
+}
+
+// Wraps every route in a span carrying the method, route template and request id
+// Once the response is ready its status and latency are added and the request is logged
+// Credentials are marked sensitive so they are never printed, even by the headers logged at debug
+pub fn layer<S>(router: Router<S>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let request_id = HeaderName::from_static(REQUEST_ID_HEADER);
+    router.layer(
+        ServiceBuilder::new()
+            .layer(SetSensitiveRequestHeadersLayer::new([
+                header::AUTHORIZATION,
+                header::COOKIE,
+            ]))
+            .layer(SetRequestIdLayer::new(request_id.clone(), MakeRequestUuid))
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(make_span)
+                    .on_request(on_request)
+                    .on_response(on_response),
+            )
+            .layer(PropagateRequestIdLayer::new(request_id)),
+    )
+}
+
+fn make_span(request: &Request<Body>) -> Span {
+    // Using the template rather than the uri keeps ids out of the route field
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+        .unwrap_or("unmatched");
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|request_id| request_id.to_str().ok())
+        .unwrap_or("");
+    info_span!(
+        "request",
+        method = %request.method(),
+        route,
+        request_id,
+        status = field::Empty,
+        latency_ms = field::Empty,
+    )
+}
+
+fn on_request(request: &Request<Body>, _: &Span) {
+    // Sensitive headers print as Sensitive rather than their value
+    debug!(headers = ?request.headers(), "started request");
+}
+
+fn on_response(response: &Response<Body>, latency: Duration, span: &Span) {
+    span.record("status", response.status().as_u16());
+    span.record("latency_ms", latency.as_millis() as u64);
+    info!("finished request");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_output_appends_and_syncs() {
+        let path = std::env::temp_dir().join(format!("ftf-log-{}.log", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "earlier run\n").unwrap();
+        let mut output = Output::open(&path.to_string_lossy()).unwrap();
+        assert!(!output.ansi());
+        output.write_all(b"this run\n").unwrap();
+        output.sync().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "earlier run\nthis run\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(Output::open("stderr"), Ok(Output::Stderr)));
+        assert!(Output::open("/nonexistent/dir/server.log").is_err());
+    }
+}
@@ -1,54 +1,139 @@
+use std::collections::HashMap;
+
+use crate::database::models::{Event, Item, Menu};
+use crate::database::store::{vendor_thing, Model, Repository, Store};
+use crate::server::error::ApiError;
+use crate::server::state::AppState;
+use crate::utils::token::{verify_jwt, Claims, Role};
 use axum::{
-    extract::{Extension, Request},
-    http::StatusCode,
-    middleware::{self, Next},
-    response::{IntoResponse, Response},
+    extract::{Path, Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
 };
+use surrealdb::sql::Thing;
+use tracing::warn;
 
-// Ensures the request only modifies data belonging to the provided vendor id
-//      Since we already confirmed the token matches the vendor id provided
+// Ensures the request only modifies data belonging to the authenticated vendor
+//      Since we already verified the token the claims can be trusted
 //      We can assume either the token was leaked or the user is authorized
-pub async fn authorizer(mut request: Request, next: Next) -> Result<Response, StatusCode> {
-    if request.method() == axum::http::Method::GET {
+// Admins are allowed to modify any data
+pub async fn authorizer(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if request.method() == Method::GET {
         return Ok(next.run(request).await);
     }
 
-    // TODO: add authorization functionality
+    let claims = match request.extensions().get::<Claims>() {
+        Some(claims) => claims,
+        None => return Err(ApiError::Unauthorized("Missing credentials".to_string())),
+    };
 
-    Ok(next.run(request).await)
+    if claims.has_role(Role::Admin) {
+        return Ok(next.run(request).await);
+    }
+
+    // Creating vendors out of band and deleting vendors are reserved for admins
+    //      Vendors are created when they first log in and can't delete their own accounts
+    if params.is_empty() || (request.method() == Method::DELETE && params.contains_key("vendor_id"))
+    {
+        return Err(ApiError::Forbidden(
+            "Only admins can create or delete vendors".to_string(),
+        ));
+    }
+
+    let owner = if let Some(vendor_id) = params.get("vendor_id") {
+        Some(vendor_thing(vendor_id))
+    } else if let Some(event_id) = params.get("event_id") {
+        get_owner::<Event>(&state, event_id).await?
+    } else if let Some(menu_id) = params.get("menu_id") {
+        get_owner::<Menu>(&state, menu_id).await?
+    } else if let Some(item_id) = params.get("item_id") {
+        get_owner::<Item>(&state, item_id).await?
+    } else {
+        None
+    };
+
+    match owner {
+        Some(owner) if owner.id.to_raw() == claims.sub => Ok(next.run(request).await),
+        _ => Err(ApiError::Forbidden(
+            "The resource does not belong to the authenticated vendor".to_string(),
+        )),
+    }
+}
+
+// Looks up the vendor a stored record belongs to
+async fn get_owner<T>(state: &AppState, id: &str) -> Result<Option<Thing>, ApiError>
+where
+    T: Model,
+    dyn Store: Repository<T>,
+{
+    let record_option: Option<T> = state.store.get(id).await?;
+    match record_option {
+        Some(record) => Ok(record.vendor()),
+        None => Err(ApiError::not_found(T::TABLE, id)),
+    }
 }
 
-// Authenticates the request to ensure the requester's token matches the given ID
-// FIX: This solution will not work if the vendor does not already exist
-pub async fn authenticator(mut request: Request, next: Next) -> Result<Response, StatusCode> {
-    if request.method() == axum::http::Method::GET {
+// Authenticates the request by verifying the JWT given as a bearer token
+// The signature, expiry and audience are all checked, on success the verified claims are added
+//      to the request extensions so handlers and the authorizer know who is calling
+pub async fn authenticator(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if request.method() == Method::GET {
         return Ok(next.run(request).await);
     }
+    let claims = verify_bearer(&state, &request)?;
+    request.extensions_mut().insert(claims);
+    Ok(next.run(request).await)
+}
+
+// Guards the admin routes, unlike the rest of the API even GET requests need a token
+pub async fn require_admin(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let claims = verify_bearer(&state, &request)?;
+    if !claims.has_role(Role::Admin) {
+        return Err(ApiError::Forbidden(
+            "Only admins can use this route".to_string(),
+        ));
+    }
+    request.extensions_mut().insert(claims);
+    Ok(next.run(request).await)
+}
+
+fn verify_bearer(state: &AppState, request: &Request) -> Result<Claims, ApiError> {
     let token_option = request
         .headers()
         .get(axum::http::header::AUTHORIZATION)
-        .and_then(|header| header.to_str().ok());
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "));
 
     let token = match token_option {
-        Some(token) => token,
-        None => return Err(StatusCode::NOT_FOUND),
+        Some(token) => token.trim(),
+        None => {
+            return Err(ApiError::Unauthorized(
+                "Missing bearer token in the Authorization header".to_string(),
+            ))
+        }
     };
-    let vendor_id_option = request
-        .headers()
-        .get("x-user-id")
-        .and_then(|vendor_id| vendor_id.to_str().ok());
 
-    let vendor_id = if let Some(vendor_id) = vendor_id_option {
-        vendor_id
-    } else {
-        return Err(StatusCode::NOT_FOUND);
-    };
-
-    // WARN: Vendor id is used only as a temporary way to authorize the user
-    //      JWTs will be used in the production version
-    if vendor_id == token {
-        return Ok(next.run(request).await);
+    match verify_jwt(token, &state.config.auth.jwt_secret) {
+        Ok(claims) => Ok(claims),
+        Err(err) => {
+            warn!("Rejected token: {err}");
+            Err(ApiError::Unauthorized(
+                "The bearer token is invalid or has expired".to_string(),
+            ))
+        }
     }
-
-    Err(StatusCode::NOT_FOUND)
 }
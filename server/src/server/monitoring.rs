@@ -0,0 +1,86 @@
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use crate::utils::token::active_tokens;
+use axum::{
+    extract::{MatchedPath, Request},
+    http::header::CONTENT_TYPE,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use tracing::error;
+
+// Every metric the API exports, kept together so the dashboards have one place to look
+pub const HTTP_REQUESTS_TOTAL: &str = "http_requests_total";
+pub const HTTP_REQUEST_DURATION_SECONDS: &str = "http_request_duration_seconds";
+pub const DB_QUERY_DURATION_SECONDS: &str = "db_query_duration_seconds";
+pub const DB_ERRORS_TOTAL: &str = "db_errors_total";
+pub const GEOCODING_REQUESTS_TOTAL: &str = "geocoding_requests_total";
+pub const AUTH_TOKENS_ISSUED_TOTAL: &str = "auth_tokens_issued_total";
+pub const AUTH_ACTIVE_TOKENS: &str = "auth_active_tokens";
+
+// Requests are expected to take milliseconds, anything past 10 seconds has already timed out
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+static HANDLE: OnceLock<Option<PrometheusHandle>> = OnceLock::new();
+
+// Installs the global recorder the first time it is called
+// Metrics recorded before this are dropped, so it is called before the app is built
+pub fn handle() -> Option<&'static PrometheusHandle> {
+    HANDLE
+        .get_or_init(|| {
+            let builder = PrometheusBuilder::new()
+                .set_buckets_for_metric(
+                    Matcher::Suffix("duration_seconds".to_string()),
+                    LATENCY_BUCKETS,
+                )
+                .and_then(|builder| builder.install_recorder());
+            match builder {
+                Ok(handle) => Some(handle),
+                Err(err) => {
+                    error!("Failed to install the metrics recorder: {err}");
+                    None
+                }
+            }
+        })
+        .as_ref()
+}
+
+// Counts every request and how long it took, labelled by the route template rather than the uri
+//      so ids don't create a new series per record
+pub async fn track(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let latency = start.elapsed().as_secs_f64();
+
+    let status = response.status().as_u16().to_string();
+    counter!(HTTP_REQUESTS_TOTAL, "method" => method.clone(), "route" => route.clone(), "status" => status)
+        .increment(1);
+    histogram!(HTTP_REQUEST_DURATION_SECONDS, "method" => method, "route" => route).record(latency);
+    response
+}
+
+// Renders every metric in the Prometheus text format
+pub async fn metrics() -> Response {
+    gauge!(AUTH_ACTIVE_TOKENS).set(active_tokens() as f64);
+    let body = match handle() {
+        Some(handle) => handle.render(),
+        None => String::new(),
+    };
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        body,
+    )
+        .into_response()
+}
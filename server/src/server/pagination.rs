@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use crate::database::store::{Listing, Model, PageQuery, SortField};
+use crate::server::error::{parse_integer, parse_number, ApiError, FieldError};
+use serde::Serialize;
+use serde_json::Value;
+
+pub const DEFAULT_LIMIT: usize = 50;
+pub const MAX_LIMIT: usize = 200;
+
+fn sort_field(value: &str) -> Option<SortField> {
+    match value {
+        "name" => Some(SortField::Name),
+        "datetime" => Some(SortField::Datetime),
+        "distance" => Some(SortField::Distance),
+        _ => None,
+    }
+}
+
+// The paging options shared by every collection endpoint, read from the query string
+//      limit       -> Number of records per page, defaults to 50, at most 200
+//      after       -> The next_cursor of the previous page
+//      sort        -> name, datetime or distance, prefixed with - to sort descending
+//                     Distance sorting requires lat and lon
+//      fields      -> Comma seperated list of the fields to return, uuid is always included
+// Sorting and paging are done by the store so only the page is read
+#[derive(Debug, Clone)]
+pub struct PageParams {
+    pub limit: usize,
+    pub after: Option<String>,
+    pub sort: Option<SortField>,
+    pub descending: bool,
+    pub fields: Option<Vec<String>>,
+    pub origin: Option<(f64, f64)>,
+}
+
+// The envelope every collection endpoint responds with
+// next_cursor is only set when there are more records after this page
+#[derive(Serialize, Debug)]
+pub struct Page {
+    pub data: Vec<Value>,
+    pub next_cursor: Option<String>,
+    pub total: usize,
+}
+
+impl PageParams {
+    // sortable lists the sort fields the endpoint supports, fields can name any field of T
+    pub fn from_query<T: Model>(
+        query: &HashMap<String, String>,
+        sortable: &[SortField],
+    ) -> Result<Self, ApiError> {
+        let limit = parse_integer(query, "limit", Some(DEFAULT_LIMIT), 1, MAX_LIMIT)?;
+
+        let (sort, descending) = match query.get("sort") {
+            Some(sort) => {
+                let (name, descending) = match sort.strip_prefix('-') {
+                    Some(name) => (name, true),
+                    None => (sort.as_str(), false),
+                };
+                match sort_field(name) {
+                    Some(field) if sortable.contains(&field) => (Some(field), descending),
+                    _ => {
+                        return Err(ApiError::Validation(vec![FieldError::new(
+                            "sort",
+                            "is not a supported sort field for this endpoint",
+                        )]))
+                    }
+                }
+            }
+            None => (None, false),
+        };
+
+        let origin = if sort == Some(SortField::Distance) {
+            Some((
+                parse_number(query, "lat", None, -90.0, 90.0)?,
+                parse_number(query, "lon", None, -180.0, 180.0)?,
+            ))
+        } else {
+            None
+        };
+
+        let fields: Option<Vec<String>> = query.get("fields").map(|fields| {
+            fields
+                .split(',')
+                .map(|field| field.trim().to_string())
+                .filter(|field| !field.is_empty())
+                .collect()
+        });
+        if let Some(fields) = &fields {
+            let unknown: Vec<&str> = fields
+                .iter()
+                .map(String::as_str)
+                .filter(|field| !T::FIELDS.contains(field))
+                .collect();
+            if !unknown.is_empty() {
+                return Err(ApiError::Validation(vec![FieldError::new(
+                    "fields",
+                    &format!(
+                        "{} can't be returned, the fields are {}",
+                        unknown.join(", "),
+                        T::FIELDS.join(", ")
+                    ),
+                )]));
+            }
+        }
+
+        Ok(PageParams {
+            limit,
+            after: query.get("after").cloned(),
+            sort,
+            descending,
+            fields,
+            origin,
+        })
+    }
+
+    // The page of the collection these parameters ask for, vendor_id limits it to one vendor's
+    pub fn query(&self, vendor_id: Option<&str>) -> PageQuery {
+        PageQuery {
+            vendor: vendor_id.map(str::to_string),
+            limit: self.limit,
+            after: self.after.clone(),
+            sort: self.sort,
+            descending: self.descending,
+            origin: self.origin,
+        }
+    }
+
+    // Keeps only the fields asked for, records which aren't objects are left alone
+    pub fn project(&self, record: &mut Value) {
+        if let (Some(fields), Value::Object(map)) = (&self.fields, record) {
+            map.retain(|key, _| key == "uuid" || fields.contains(key));
+        }
+    }
+}
+
+impl Page {
+    // The envelope for a page read from the store, projected to the fields asked for
+    pub fn new<T: Serialize>(listing: Listing<T>, params: &PageParams) -> Result<Self, ApiError> {
+        let mut data: Vec<Value> = listing
+            .records
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<_, _>>()
+            .map_err(|err| ApiError::Internal(format!("Failed to serialize record: {err}")))?;
+        for record in data.iter_mut() {
+            params.project(record);
+        }
+        Ok(Page {
+            data,
+            next_cursor: listing.next_cursor,
+            total: listing.total,
+        })
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::database::store::Store;
+use axum_server::Handle;
+use tokio::{signal, task::JoinHandle};
+use tracing::{error, info, warn};
+
+// Resolves on the first SIGINT (ctrl+c) or SIGTERM, which is what orchestrators send on deploy
+pub async fn signal() {
+    let interrupt = async {
+        if let Err(err) = signal::ctrl_c().await {
+            warn!("Failed to listen for SIGINT: {err}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                warn!("Failed to listen for SIGTERM: {err}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => info!("Received SIGINT"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+}
+
+// Waits for a signal in the background and then stops the server from accepting connections
+// Requests already being handled get drain_timeout to finish before their connections are closed
+// The server is also stopped if the store can never become available, the task then resolves to
+//      the reason so the process can exit with an error
+pub fn on_signal(
+    handle: Handle,
+    store: Arc<dyn Store>,
+    drain_timeout: Duration,
+) -> JoinHandle<Option<String>> {
+    tokio::spawn(async move {
+        let failure = tokio::select! {
+            _ = signal() => None,
+            reason = store.failed() => {
+                error!("The store can't be used, shutting down: {reason}");
+                Some(reason)
+            }
+        };
+        info!(
+            connections = handle.connection_count(),
+            "Shutting down, draining connections for up to {drain_timeout:?}"
+        );
+        handle.graceful_shutdown(Some(drain_timeout));
+        failure
+    })
+}
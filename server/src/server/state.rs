@@ -1,6 +1,10 @@
-use surrealdb::{engine::remote::ws::Client, Surreal};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::database::store::Store;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub db: Surreal<Client>,
+    pub store: Arc<dyn Store>,
+    pub config: Arc<Config>,
 }
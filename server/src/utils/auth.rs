@@ -1,10 +1,10 @@
+use crate::config::{AuthConfig, OAuthConfig};
+use crate::server::error::ApiError;
 use crate::server::state;
 use axum::extract::State;
 use axum::Form;
 use axum::Json;
-use color_eyre::{eyre::bail, Result};
-use dotenv::dotenv;
-use log::info;
+use color_eyre::Result;
 use oauth2::EmptyExtraTokenFields;
 use oauth2::{
     basic::{BasicClient, BasicErrorResponse, BasicTokenType},
@@ -12,13 +12,15 @@ use oauth2::{
     AccessToken, AuthUrl, AuthorizationCode, ClientId, ClientSecret, PkceCodeVerifier, RedirectUrl,
     StandardTokenResponse, TokenResponse, TokenUrl,
 };
+use tracing::{debug, error, warn};
 
 use serde::{Deserialize, Serialize};
-use std::env;
-use surrealdb::engine::remote::ws::Client;
-use surrealdb::Surreal;
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::database::models::{Record, Vendor};
+use crate::database::models::Vendor;
+use crate::database::store::Store;
+use crate::utils::token::{issue_jwt, Claims, JWTExtraFeilds, Role, JWT_LIFETIME};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TokenBody {
@@ -37,49 +39,92 @@ struct UserInfo {
 pub async fn token(
     State(state): State<state::AppState>,
     Form(body): Form<TokenBody>,
-) -> Json<oauth2::StandardTokenResponse<EmptyExtraTokenFields, oauth2::basic::BasicTokenType>> {
-    let mut token = match get_token(body.into()).await {
+) -> Result<Json<StandardTokenResponse<JWTExtraFeilds, BasicTokenType>>, ApiError> {
+    let token = match get_token(body, &state.config.oauth).await {
         Some(token) => token,
-        None => panic!("TODO better error handling"),
+        None => {
+            return Err(ApiError::Unauthorized(
+                "The authorization code could not be exchanged for a token".to_string(),
+            ))
+        }
     };
-    let user_info = match get_info(token.access_token().secret().as_str(), "email".as_ref()).await {
+    let user_info = match get_info(
+        &state.config.oauth,
+        token.access_token().secret().as_str(),
+        "email",
+    )
+    .await
+    {
         Some(user_info) => user_info,
-        None => panic!("TODO Better error handling here"),
+        None => {
+            return Err(ApiError::Unauthorized(
+                "The user info could not be retrieved with the given token".to_string(),
+            ))
+        }
     };
 
     let name = user_info.name.to_string();
     let id = user_info.id.to_string();
 
-    let login_result = process_login(name, id, token.clone(), state.db).await;
+    let vendor = process_login(name, id, state.store).await?;
 
-    println!("{:?}", login_result);
-    dbg!(&token);
+    // The facebook token is only used to identify the vendor, all further requests are
+    // authenticated with the JWT issued here
+    let claims = Claims::new(
+        vendor.uuid.to_string(),
+        get_roles(&state.config.auth, &vendor.email),
+    );
+    let jwt = match issue_jwt(&claims, &state.config.auth.jwt_secret) {
+        Ok(jwt) => jwt,
+        Err(err) => return Err(ApiError::Internal(format!("Failed to issue token: {err}"))),
+    };
 
-    Json(token)
+    let mut response = StandardTokenResponse::new(
+        token.access_token().clone(),
+        token.token_type().clone(),
+        JWTExtraFeilds::new(jwt),
+    );
+    response.set_expires_in(Some(&Duration::from_secs(JWT_LIFETIME)));
 
-    //let record_option_result: Result<Option<Record>, surrealdb::Error> = state.db.create()
+    Ok(Json(response))
 }
 
+// Every logged in user is a vendor, admins are the users whose facebook ids are listed in
+//      auth.admin_ids
+fn get_roles(auth: &AuthConfig, identifier: &str) -> Vec<Role> {
+    let mut roles = vec![Role::Vendor];
+    if auth.admin_ids.iter().any(|admin_id| admin_id == identifier) {
+        roles.push(Role::Admin);
+    }
+    roles
+}
+
+// The provider settings were validated when the config was loaded, only the redirect uri sent by
+//      the client can be malformed here
 async fn get_token(
     req: TokenBody,
+    oauth: &OAuthConfig,
 ) -> Option<StandardTokenResponse<EmptyExtraTokenFields, oauth2::basic::BasicTokenType>> {
-    dotenv().ok();
-    let client_secret = match env::var("TEST_CLIENT_SECRET") {
-        Err(e) => panic!("{e}"),
-        Ok(client_secret) => client_secret,
+    let redirect_url = match RedirectUrl::new(req.redirect_uri.clone()) {
+        Ok(redirect_url) => redirect_url,
+        Err(err) => {
+            warn!("Invalid redirect uri {}: {err}", req.redirect_uri);
+            return None;
+        }
     };
     let client = BasicClient::new(
-        ClientId::new("345272141598641".to_string()),
-        Some(ClientSecret::new(client_secret.to_string())),
-        AuthUrl::new("https://auth".to_string()).expect("blah"),
-        Some(
-            TokenUrl::new("https://graph.facebook.com/v18.0/oauth/access_token".to_string())
-                .expect("blah"),
-        ),
+        ClientId::new(oauth.client_id.clone().unwrap_or_default()),
+        Some(ClientSecret::new(
+            oauth
+                .client_secret
+                .as_ref()
+                .map(|secret| secret.expose().to_string())
+                .unwrap_or_default(),
+        )),
+        AuthUrl::new(oauth.auth_url.clone()).ok()?,
+        Some(TokenUrl::new(oauth.token_url.clone()).ok()?),
     )
-    .set_redirect_uri(
-        RedirectUrl::new(req.redirect_uri.clone()).expect("Issue constructing Redirect url"),
-    );
+    .set_redirect_uri(redirect_url);
 
     let pkce_verifier = PkceCodeVerifier::new(req.code_verifier.clone());
     let token_result = client
@@ -91,7 +136,7 @@ async fn get_token(
     match token_result {
         Ok(token) => Some(token),
         Err(err) => {
-            info!("{:?}", err);
+            warn!(error = ?err, "Failed to exchange the authorization code");
             None
         }
     }
@@ -100,63 +145,62 @@ async fn get_token(
 // Due to the apps lack of verification of facebook we arent able to access user email
 // Instead the user id will be used instead and emails will be added later
 // TODO: Add email
-async fn get_info(token: &str, feilds: &str) -> Option<UserInfo> {
+async fn get_info(oauth: &OAuthConfig, token: &str, feilds: &str) -> Option<UserInfo> {
     // This is arbitrary since the api will always return name and id
     let uri = format!(
-        "https://graph.facebook.com/v18.0/me?feilds=name,{}&access_token={}",
+        "{}?feilds=name,{}&access_token={}",
+        oauth.user_info_url,
         feilds,
         token.to_string()
     );
 
+    // The url carries the access token so it is stripped from any error before logging
     let response_result = reqwest::get(uri).await;
     let user_info_result = match response_result {
         Ok(user_info_result) => user_info_result.json::<UserInfo>().await,
-        Err(_) => return None,
+        Err(err) => {
+            warn!(error = %err.without_url(), "Failed to request user info");
+            return None;
+        }
     };
 
     let user_info = match user_info_result {
         Ok(user_info) => user_info,
-        Err(_) => return None,
+        Err(err) => {
+            warn!(error = %err.without_url(), "Failed to read user info");
+            return None;
+        }
     };
 
-    println!("{:?}", &user_info);
+    debug!(user_id = %user_info.id, "Retrieved user info");
     Some(user_info)
 }
 
+// Finds the vendor the user logged in as, creating it on their first login
+// Store errors keep their status so logging in while the database is down is a 503
 async fn process_login(
     name: String,
     identifier: String,
-    token: oauth2::StandardTokenResponse<EmptyExtraTokenFields, oauth2::basic::BasicTokenType>,
-    db: Surreal<Client>,
-) -> color_eyre::eyre::Result<()> {
-    let vendor_vec: Vec<Vendor> = db.select("vendors").await?;
-
-    let vendor_vec: Vec<Vendor> = vendor_vec
-        .iter()
-        .map(|v| v.clone())
-        .filter(|vendor| vendor.email == identifier)
-        .collect();
+    store: Arc<dyn Store>,
+) -> Result<Vendor, ApiError> {
+    let mut vendor_vec: Vec<Vendor> = store.vendors_by_email(&identifier).await?;
 
-    dbg!(&vendor_vec);
+    debug!(matches = vendor_vec.len(), "Looked up vendor for login");
 
-    let vendor = match vendor_vec.len() {
+    match vendor_vec.len() {
         0 => {
             let vendor = Vendor::new(name).email(identifier);
-            let option_result: Option<Record> = db
-                .create(("vendors", vendor.uuid.clone().into_owned()))
-                .content(vendor.clone())
-                .await?;
-            match option_result {
-                Some(_) => vendor,
-                None => bail!("Failed to create user!"),
-            }
+            store.create(vendor.clone()).await?;
+            Ok(vendor)
         }
-        1 => vendor_vec.first().unwrap().clone(), // HACK: this will always work since we precheck the len
-        // But its not a great way to write code so fix later
-        _ => panic!("Nonunique idenfifier found!\n{}", identifier),
-    };
-
-    println!("{:?}", vendor);
-
-    Ok(())
+        1 => Ok(vendor_vec.remove(0)),
+        // Which vendor the user is can't be known, so neither is logged in until the duplicate
+        //      is removed
+        matches => {
+            error!(matches, "Several vendors share the identifier {identifier}");
+            Err(ApiError::Conflict(
+                "More than one vendor is registered with this account".to_string(),
+            ))
+        }
+    }
 }
@@ -1,5 +1,16 @@
+use color_eyre::Result;
+use jsonwebtoken::{
+    decode, encode, get_current_timestamp, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::Mutex;
+
+use crate::config::Secret;
+use crate::server::monitoring::AUTH_TOKENS_ISSUED_TOTAL;
+use metrics::counter;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct TokenStore {
@@ -45,3 +56,90 @@ impl JWTExtraFeilds {
 }
 
 impl oauth2::ExtraTokenFields for JWTExtraFeilds {}
+
+// region:      -- JWT
+
+// Audience every token issued by this API is minted for, tokens with any other audience are
+// rejected by the authenticator
+pub const JWT_AUDIENCE: &str = "food_truck_finder";
+// How long an issued token stays valid, in seconds
+pub const JWT_LIFETIME: u64 = 60 * 60 * 24;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Vendor,
+    Admin,
+}
+
+// The claims carried by the JWTs this API issues
+// sub holds the uuid of the vendor the token was issued to
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Claims {
+    pub sub: String,
+    pub aud: String,
+    pub iat: u64,
+    pub exp: u64,
+    pub roles: Vec<Role>,
+}
+
+impl Claims {
+    pub fn new(vendor_id: String, roles: Vec<Role>) -> Self {
+        let now = get_current_timestamp();
+        Claims {
+            sub: vendor_id,
+            aud: JWT_AUDIENCE.to_string(),
+            iat: now,
+            exp: now + JWT_LIFETIME,
+            roles,
+        }
+    }
+
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+}
+
+// The secret is auth.jwt_secret from the config, which is checked at startup
+pub fn issue_jwt(claims: &Claims, secret: &Secret) -> Result<String> {
+    let jwt = encode(
+        &Header::default(),
+        claims,
+        &EncodingKey::from_secret(secret.expose().as_bytes()),
+    )?;
+    counter!(AUTH_TOKENS_ISSUED_TOTAL).increment(1);
+    ISSUED_EXPIRIES
+        .lock()
+        .expect("issued token lock poisoned")
+        .push(Reverse(claims.exp));
+    Ok(jwt)
+}
+
+// The expiry of every token issued since the server started, soonest first
+// Tokens aren't stored anywhere so this is the only record of how many are still valid
+static ISSUED_EXPIRIES: Mutex<BinaryHeap<Reverse<u64>>> = Mutex::new(BinaryHeap::new());
+
+// How many tokens issued by this process haven't expired yet
+pub fn active_tokens() -> usize {
+    let now = get_current_timestamp();
+    let mut expiries = ISSUED_EXPIRIES.lock().expect("issued token lock poisoned");
+    while matches!(expiries.peek(), Some(Reverse(exp)) if *exp <= now) {
+        expiries.pop();
+    }
+    expiries.len()
+}
+
+// Checks the signature, expiry and audience of the token and returns its claims
+pub fn verify_jwt(jwt: &str, secret: &Secret) -> Result<Claims> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_audience(&[JWT_AUDIENCE]);
+    validation.set_required_spec_claims(&["exp", "aud", "sub"]);
+    let token_data = decode::<Claims>(
+        jwt,
+        &DecodingKey::from_secret(secret.expose().as_bytes()),
+        &validation,
+    )?;
+    Ok(token_data.claims)
+}
+
+// endregion:   -- JWT
@@ -0,0 +1,1352 @@
+#![allow(unused)]
+#[path = "../src/config.rs"]
+mod config;
+#[path = "../src/database.rs"]
+mod database;
+#[path = "../src/server.rs"]
+mod server;
+#[path = "../src/utils.rs"]
+mod utils;
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::config::{Config, Secret};
+use crate::database::memory_store::MemoryStore;
+use crate::database::models::{Event, Item, OccurrenceOverride, Record, Vendor};
+use crate::database::store::{Listing, Model, PageQuery, Repository, Store, StoreError};
+use crate::server::{app::make_app, monitoring, state::AppState};
+use crate::utils::token::{issue_jwt, Claims, Role};
+use async_trait::async_trait;
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+// Drives the whole router, middleware included, against a MemoryStore so no database is needed
+
+const SECRET: &str = "api-test-secret";
+
+async fn app() -> Router {
+    app_with(Arc::new(MemoryStore::new())).await
+}
+
+async fn app_with(store: Arc<dyn Store>) -> Router {
+    // As make_state does, so requests are counted
+    monitoring::handle();
+    let mut config = Config::default();
+    config.auth.jwt_secret = Secret::new(SECRET.to_string());
+    let state = AppState {
+        store,
+        config: Arc::new(config),
+    };
+    make_app(state).await.expect("the router should build")
+}
+
+// A store whose database can't be reached, every request fails the way SurrealStore's do
+struct DownStore;
+
+fn down<T>() -> Result<T, StoreError> {
+    Err(StoreError::Unavailable(
+        "The database is not connected".to_string(),
+    ))
+}
+
+#[async_trait]
+impl<T: Model> Repository<T> for DownStore {
+    async fn get(&self, _: &str) -> Result<Option<T>, StoreError> {
+        down()
+    }
+
+    async fn get_many(&self, _: &[String]) -> Result<Vec<T>, StoreError> {
+        down()
+    }
+
+    async fn list(&self) -> Result<Vec<T>, StoreError> {
+        down()
+    }
+
+    async fn list_by_vendor(&self, _: &str) -> Result<Vec<T>, StoreError> {
+        down()
+    }
+
+    async fn list_by_vendors(&self, _: &[String]) -> Result<Vec<T>, StoreError> {
+        down()
+    }
+
+    async fn page(&self, _: &PageQuery) -> Result<Listing<T>, StoreError> {
+        down()
+    }
+
+    async fn containing(
+        &self,
+        _: &[&'static str],
+        _: &[String],
+        _: usize,
+    ) -> Result<Vec<T>, StoreError> {
+        down()
+    }
+
+    async fn create(&self, _: T) -> Result<Record, StoreError> {
+        down()
+    }
+
+    async fn upsert(&self, _: T) -> Result<(), StoreError> {
+        down()
+    }
+
+    async fn patch(&self, _: &str, _: Vec<(String, Value)>) -> Result<Option<T>, StoreError> {
+        down()
+    }
+
+    async fn delete(&self, _: &str) -> Result<Option<T>, StoreError> {
+        down()
+    }
+}
+
+#[async_trait]
+impl Store for DownStore {
+    async fn items_by_menu(&self, _: &str) -> Result<Option<Vec<Item>>, StoreError> {
+        down()
+    }
+
+    async fn vendors_by_email(&self, _: &str) -> Result<Vec<Vendor>, StoreError> {
+        down()
+    }
+
+    async fn set_override(
+        &self,
+        _: &str,
+        _: chrono::NaiveDate,
+        _: Option<OccurrenceOverride>,
+    ) -> Result<Option<Event>, StoreError> {
+        down()
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        down()
+    }
+}
+
+fn token(vendor_id: &str, roles: Vec<Role>) -> String {
+    let claims = Claims::new(vendor_id.to_string(), roles);
+    issue_jwt(&claims, &Secret::new(SECRET.to_string())).expect("the token should be issued")
+}
+
+fn admin() -> String {
+    token("admin", vec![Role::Admin])
+}
+
+async fn send(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    token: Option<&str>,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+    }
+    // Strings are sent as they are, such as calendars to import, anything else as json
+    let request = match body {
+        Some(Value::String(body)) => request
+            .header(header::CONTENT_TYPE, "text/calendar")
+            .body(Body::from(body)),
+        Some(body) => request
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    }
+    .expect("the request should build");
+
+    let response = app.clone().oneshot(request).await.expect("infallible");
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("the body should be read");
+    let body = match serde_json::from_slice(&bytes) {
+        Ok(body) => body,
+        Err(_) if bytes.is_empty() => Value::Null,
+        Err(_) => Value::String(String::from_utf8_lossy(&bytes).to_string()),
+    };
+    (status, body)
+}
+
+// Creates a vendor as an admin and returns its uuid
+async fn create_vendor(app: &Router, name: &str) -> String {
+    let (status, _) = send(
+        app,
+        Method::POST,
+        "/api/vendors",
+        Some(&admin()),
+        Some(json!({ "name": name })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, page) = send(app, Method::GET, "/api/vendors?limit=200", None, None).await;
+    page["data"]
+        .as_array()
+        .expect("a page of vendors")
+        .iter()
+        .find(|vendor| vendor["name"] == name)
+        .and_then(|vendor| vendor["uuid"].as_str())
+        .expect("the vendor should be listed")
+        .to_string()
+}
+
+// The uuid of the only record in a page
+fn only_uuid(page: &Value) -> String {
+    let data = page["data"].as_array().expect("a page");
+    assert_eq!(data.len(), 1, "{page}");
+    data[0]["uuid"].as_str().expect("a uuid").to_string()
+}
+
+fn error_code(body: &Value) -> &str {
+    body["error"]["code"].as_str().unwrap_or_default()
+}
+
+#[tokio::test]
+async fn vendors_crud() {
+    let app = app().await;
+    let vendor_id = create_vendor(&app, "Taco Truck").await;
+    let vendor_token = token(&vendor_id, vec![Role::Vendor]);
+
+    let (status, vendor) = send(
+        &app,
+        Method::GET,
+        &format!("/api/vendors/{vendor_id}"),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(vendor["name"], "Taco Truck");
+    assert!(vendor.get("data").is_none(), "single records aren't paged");
+
+    let (status, vendor) = send(
+        &app,
+        Method::PATCH,
+        &format!("/api/vendors/{vendor_id}"),
+        Some(&vendor_token),
+        Some(json!({ "description": "Tacos" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(vendor["description"], "Tacos");
+
+    let (status, _) = send(
+        &app,
+        Method::DELETE,
+        &format!("/api/vendors/{vendor_id}"),
+        Some(&admin()),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = send(
+        &app,
+        Method::GET,
+        &format!("/api/vendors/{vendor_id}"),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(error_code(&body), "not_found");
+}
+
+#[tokio::test]
+async fn events_crud() {
+    let app = app().await;
+    let vendor_id = create_vendor(&app, "Pizza Van").await;
+    let vendor_token = token(&vendor_id, vec![Role::Vendor]);
+
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        &format!("/api/vendors/{vendor_id}/events"),
+        Some(&vendor_token),
+        Some(json!({
+            "name": "Market",
+            "datetime": "2024-05-03T10:00:00Z",
+            "location": "Main Street",
+            "cord_x": -83.05,
+            "cord_y": 42.33,
+            "repeat_schedule": "FREQ=WEEKLY;BYDAY=FR",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, page) = send(
+        &app,
+        Method::GET,
+        &format!("/api/vendors/{vendor_id}/events"),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page["total"], 1);
+    let event_id = only_uuid(&page);
+
+    let (status, event) = send(
+        &app,
+        Method::PATCH,
+        &format!("/api/events/{event_id}"),
+        Some(&vendor_token),
+        Some(json!({ "name": "Night Market" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(event["name"], "Night Market");
+
+    let (status, event) = send(
+        &app,
+        Method::GET,
+        &format!("/api/events/{event_id}"),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(event["name"], "Night Market");
+
+    let (status, _) = send(
+        &app,
+        Method::DELETE,
+        &format!("/api/events/{event_id}"),
+        Some(&vendor_token),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, page) = send(&app, Method::GET, "/api/events", None, None).await;
+    assert_eq!(page["total"], 0);
+}
+
+#[tokio::test]
+async fn menus_and_items_crud() {
+    let app = app().await;
+    let vendor_id = create_vendor(&app, "Burger Bus").await;
+    let vendor_token = token(&vendor_id, vec![Role::Vendor]);
+
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        &format!("/api/vendors/{vendor_id}/items"),
+        Some(&vendor_token),
+        Some(json!({ "name": "Cheeseburger", "price": 8.5 })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, page) = send(
+        &app,
+        Method::GET,
+        &format!("/api/vendors/{vendor_id}/items"),
+        None,
+        None,
+    )
+    .await;
+    let item_id = only_uuid(&page);
+
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        &format!("/api/vendors/{vendor_id}/menus"),
+        Some(&vendor_token),
+        Some(json!({ "name": "Lunch" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, page) = send(
+        &app,
+        Method::GET,
+        &format!("/api/vendors/{vendor_id}/menus"),
+        None,
+        None,
+    )
+    .await;
+    let menu_id = only_uuid(&page);
+
+    let (status, item) = send(
+        &app,
+        Method::PATCH,
+        &format!("/api/items/{item_id}"),
+        Some(&vendor_token),
+        Some(json!({ "name": "Double Cheeseburger" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(item["name"], "Double Cheeseburger");
+    let (status, menu) = send(
+        &app,
+        Method::PATCH,
+        &format!("/api/menus/{menu_id}"),
+        Some(&vendor_token),
+        Some(json!({ "name": "Dinner" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(menu["name"], "Dinner");
+
+    let (status, menu) = send(
+        &app,
+        Method::GET,
+        &format!("/api/menus/{menu_id}"),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(menu["name"], "Dinner");
+
+    for path in [
+        format!("/api/items/{item_id}"),
+        format!("/api/menus/{menu_id}"),
+    ] {
+        let (status, _) = send(&app, Method::DELETE, &path, Some(&vendor_token), None).await;
+        assert_eq!(status, StatusCode::OK, "{path}");
+        let (status, _) = send(&app, Method::GET, &path, None, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{path}");
+    }
+}
+
+#[tokio::test]
+async fn writes_need_a_valid_token() {
+    let app = app().await;
+    let vendor_id = create_vendor(&app, "Coffee Cart").await;
+    let uri = format!("/api/vendors/{vendor_id}/items");
+    let item = json!({ "name": "Latte" });
+
+    let (status, body) = send(&app, Method::POST, &uri, None, Some(item.clone())).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error_code(&body), "unauthorized");
+
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        &uri,
+        Some("not-a-jwt"),
+        Some(item.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let mut forged = Claims::new(vendor_id.clone(), vec![Role::Vendor]);
+    forged.roles.push(Role::Admin);
+    let forged = issue_jwt(&forged, &Secret::new("another-secret".to_string())).unwrap();
+    let (status, _) = send(&app, Method::POST, &uri, Some(&forged), Some(item)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(&app, Method::GET, "/api/admin/export", None, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn vendors_only_change_their_own_records() {
+    let app = app().await;
+    let owner_id = create_vendor(&app, "Owner").await;
+    let other_id = create_vendor(&app, "Other").await;
+    let other_token = token(&other_id, vec![Role::Vendor]);
+
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        &format!("/api/vendors/{owner_id}/items"),
+        Some(&other_token),
+        Some(json!({ "name": "Stolen" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error_code(&body), "forbidden");
+
+    let (status, _) = send(
+        &app,
+        Method::PATCH,
+        &format!("/api/vendors/{owner_id}"),
+        Some(&other_token),
+        Some(json!({ "name": "Renamed" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // Creating and deleting vendors is left to admins, even for the vendor itself
+    let own_token = token(&owner_id, vec![Role::Vendor]);
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        "/api/vendors",
+        Some(&own_token),
+        Some(json!({ "name": "New" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = send(
+        &app,
+        Method::DELETE,
+        &format!("/api/vendors/{owner_id}"),
+        Some(&own_token),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = send(
+        &app,
+        Method::GET,
+        "/api/admin/export",
+        Some(&own_token),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn errors_use_the_envelope() {
+    let app = app().await;
+
+    let (status, body) = send(&app, Method::GET, "/api/events/missing", None, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(error_code(&body), "not_found");
+    assert!(body["error"]["message"].is_string());
+
+    let (status, body) = send(&app, Method::GET, "/api/vendors?limit=10.7", None, None).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(error_code(&body), "validation_failed");
+    assert_eq!(body["error"]["fields"][0]["field"], "limit");
+
+    let (status, body) = send(
+        &app,
+        Method::GET,
+        "/api/vendors?fields=name,secret",
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["fields"][0]["field"], "fields");
+
+    let vendor_id = create_vendor(&app, "Validation").await;
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        &format!("/api/vendors/{vendor_id}/events"),
+        Some(&admin()),
+        Some(json!({ "duration_minutes": 0 })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let fields: Vec<&str> = body["error"]["fields"]
+        .as_array()
+        .expect("the invalid fields")
+        .iter()
+        .filter_map(|field| field["field"].as_str())
+        .collect();
+    assert!(fields.contains(&"datetime"), "{body}");
+    assert!(fields.contains(&"location"), "{body}");
+
+    // Legacy patterns which can't be read are rejected rather than stored as not repeating
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        &format!("/api/vendors/{vendor_id}/events"),
+        Some(&admin()),
+        Some(json!({
+            "datetime": "2024-05-03T10:00:00Z",
+            "location": "Main Street",
+            "repeat_schedule": { "pattern": "Weekly", "days": ["FUNDAY"], "spacing": 1 },
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["fields"][0]["field"], "repeat_schedule");
+}
+
+// A calendar from another app, with a VEVENT of each kind which is skipped
+const CALENDAR: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+X-WR-TIMEZONE:Europe/Berlin\r
+BEGIN:VEVENT\r
+UID:market@example.com\r
+SUMMARY:Market\r
+DTSTART:20240503T100000\r
+DURATION:PT3H\r
+RRULE:FREQ=WEEKLY;BYDAY=FR\r
+LOCATION:Alexanderplatz\r
+GEO:52.52;13.41\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:market@example.com\r
+RECURRENCE-ID:20240510T100000\r
+DTSTART:20240510T120000\r
+LOCATION:Alexanderplatz\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:festival@example.com\r
+SUMMARY:Festival\r
+DTSTART;TZID=Europe/Berlin:20240601T180000\r
+DTEND;TZID=Europe/Berlin:20240601T230000\r
+LOCATION:Tempelhof\r
+GEO:52.47;13.40\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:rained-out@example.com\r
+STATUS:CANCELLED\r
+DTSTART:20240602T180000Z\r
+LOCATION:Tempelhof\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+#[tokio::test]
+async fn calendars_import_again_without_duplicates() {
+    let app = app().await;
+    let vendor_id = create_vendor(&app, "Calendar").await;
+    let vendor_token = token(&vendor_id, vec![Role::Vendor]);
+    let import = format!("/api/vendors/{vendor_id}/events/import");
+
+    let (status, report) = send(
+        &app,
+        Method::POST,
+        &import,
+        Some(&vendor_token),
+        Some(Value::String(CALENDAR.to_string())),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{report}");
+    assert_eq!(report["created"], 2, "{report}");
+    assert_eq!(report["skipped"], 2, "{report}");
+
+    let (status, report) = send(
+        &app,
+        Method::POST,
+        &import,
+        Some(&vendor_token),
+        Some(Value::String(CALENDAR.to_string())),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{report}");
+    assert_eq!(report["created"], 0, "{report}");
+    assert_eq!(report["updated"], 2, "{report}");
+
+    let events = format!("/api/vendors/{vendor_id}/events?sort=datetime");
+    let (_, before) = send(&app, Method::GET, &events, None, None).await;
+    assert_eq!(before["total"], 2);
+    assert_eq!(before["data"][0]["name"], "Market");
+    assert_eq!(before["data"][0]["duration_minutes"], 180);
+    assert_eq!(before["data"][1]["duration_minutes"], 300);
+
+    // The feed written for the vendor reads back into the same events
+    let (status, feed) = send(
+        &app,
+        Method::GET,
+        &format!("/api/vendors/{vendor_id}/calendar.ics"),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, report) = send(&app, Method::POST, &import, Some(&vendor_token), Some(feed)).await;
+    assert_eq!(status, StatusCode::OK, "{report}");
+    assert_eq!(report["created"], 0, "{report}");
+    assert_eq!(report["updated"], 2, "{report}");
+    let (_, after) = send(&app, Method::GET, &events, None, None).await;
+    assert_eq!(after, before);
+}
+
+#[tokio::test]
+async fn admins_export_every_table() {
+    let app = app().await;
+    let taco_id = create_vendor(&app, "Taco Truck").await;
+    create_vendor(&app, "Burger Bus").await;
+
+    let (status, body) = send(&app, Method::GET, "/api/admin/export", Some(&admin()), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let vendors = body["vendors"].as_array().expect("the vendors");
+    assert_eq!(vendors.len(), 2);
+    assert!(vendors
+        .iter()
+        .any(|vendor| vendor["uuid"] == taco_id.as_str()));
+    for table in ["items", "menus", "events"] {
+        assert_eq!(body[table], json!([]), "{table}");
+    }
+
+    let (status, body) = send(
+        &app,
+        Method::GET,
+        "/api/admin/export?format=csv&table=vendors",
+        Some(&admin()),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let lines: Vec<&str> = body.as_str().expect("csv").lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(
+        lines[0],
+        "uuid|name|email|phone|description|vendor_type|website"
+    );
+    assert!(lines
+        .iter()
+        .any(|line| line.starts_with(&format!("{taco_id}|Taco Truck|"))));
+
+    let (status, body) = send(
+        &app,
+        Method::GET,
+        "/api/admin/export?format=csv",
+        Some(&admin()),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["fields"][0]["field"], "table");
+}
+
+#[tokio::test]
+async fn patched_events_keep_their_menu_location_and_overrides_consistent() {
+    let app = app().await;
+    let vendor_id = create_vendor(&app, "Pizza Van").await;
+    let vendor_token = token(&vendor_id, vec![Role::Vendor]);
+    let other_id = create_vendor(&app, "Burger Bus").await;
+
+    let mut menus = Vec::new();
+    for owner in [&vendor_id, &other_id] {
+        let (status, _) = send(
+            &app,
+            Method::POST,
+            &format!("/api/vendors/{owner}/menus"),
+            Some(&admin()),
+            Some(json!({ "name": "Lunch" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, page) = send(
+            &app,
+            Method::GET,
+            &format!("/api/vendors/{owner}/menus"),
+            None,
+            None,
+        )
+        .await;
+        menus.push(only_uuid(&page));
+    }
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        &format!("/api/vendors/{vendor_id}/events"),
+        Some(&vendor_token),
+        Some(json!({
+            "datetime": "2024-05-03T10:00:00Z",
+            "location": "Main Street",
+            "cord_x": -83.05,
+            "cord_y": 42.33,
+            "repeat_schedule": "FREQ=WEEKLY;BYDAY=FR",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, page) = send(
+        &app,
+        Method::GET,
+        &format!("/api/vendors/{vendor_id}/events"),
+        None,
+        None,
+    )
+    .await;
+    let event_id = only_uuid(&page);
+    let patch = |body: Value| {
+        let app = app.clone();
+        let uri = format!("/api/events/{event_id}");
+        let token = vendor_token.clone();
+        async move { send(&app, Method::PATCH, &uri, Some(&token), Some(body)).await }
+    };
+
+    // Menus have to belong to the event's vendor
+    let (status, body) = patch(json!({ "menu": menus[1] })).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["fields"][0]["field"], "menu");
+    let (status, _) = patch(json!({ "menu": "missing" })).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, event) = patch(json!({ "menu": menus[0] })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(event["menu"].is_object(), "{event}");
+    let (status, event) = patch(json!({ "menu": null })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(event["menu"].is_null(), "{event}");
+
+    // Locations given with their coordinates aren't geocoded
+    let (status, event) =
+        patch(json!({ "location": "Park", "cord_x": 13.4, "cord_y": 52.5 })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(event["location"], "Park");
+    assert_eq!(event["cord_x"], 13.4);
+    assert_eq!(event["cord_y"], 52.5);
+    let (status, body) = patch(json!({ "location": "" })).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["fields"][0]["field"], "location");
+    let (status, body) = patch(json!({ "cord_x": 13.5 })).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["fields"][0]["field"], "cord_x");
+
+    // Rescheduling can't strand an override on a date the event no longer happens on
+    let occurrence = format!("/api/events/{event_id}/occurrences/2024-05-10");
+    let (status, _) = send(
+        &app,
+        Method::PATCH,
+        &occurrence,
+        Some(&vendor_token),
+        Some(json!({ "cancelled": true })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, event) = patch(json!({ "datetime": "2024-05-03T12:00:00Z" })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(event["overrides"].as_array().map(Vec::len), Some(1));
+    let (status, body) = patch(json!({
+        "datetime": "2024-05-04T12:00:00Z",
+        "repeat_schedule": "FREQ=WEEKLY;BYDAY=SA",
+    }))
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["fields"][0]["field"], "overrides");
+    let (status, _) = send(
+        &app,
+        Method::PATCH,
+        &occurrence,
+        Some(&vendor_token),
+        Some(json!({ "cancelled": false })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, event) = patch(json!({
+        "datetime": "2024-05-04T12:00:00Z",
+        "repeat_schedule": "FREQ=WEEKLY;BYDAY=SA",
+    }))
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(event["repeat_schedule"], "RRULE:FREQ=WEEKLY;BYDAY=SA");
+}
+
+#[tokio::test]
+async fn vendors_are_served_as_geojson_where_they_are_next() {
+    let app = app().await;
+    let vendor_id = create_vendor(&app, "Pizza Van").await;
+    let vendor_token = token(&vendor_id, vec![Role::Vendor]);
+    let idle_id = create_vendor(&app, "Burger Bus").await;
+
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        &format!("/api/vendors/{vendor_id}/events"),
+        Some(&vendor_token),
+        Some(json!({
+            "name": "Market",
+            "datetime": "2024-05-03T10:00:00Z",
+            "location": "Main Street",
+            "cord_x": -83.05,
+            "cord_y": 42.33,
+            "repeat_schedule": "FREQ=WEEKLY;BYDAY=FR",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, page) = send(
+        &app,
+        Method::GET,
+        &format!("/api/vendors/{vendor_id}/events"),
+        None,
+        None,
+    )
+    .await;
+    let event_id = only_uuid(&page);
+    let (status, _) = send(
+        &app,
+        Method::PATCH,
+        &format!("/api/events/{event_id}"),
+        Some(&vendor_token),
+        Some(json!({ "location": "Park", "cord_x": 13.4, "cord_y": 52.5 })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    // Each vendor on the page is placed by its own events
+    let other_id = create_vendor(&app, "Taco Truck").await;
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        &format!("/api/vendors/{other_id}/events"),
+        Some(&token(&other_id, vec![Role::Vendor])),
+        Some(json!({
+            "datetime": "2024-05-03T12:00:00Z",
+            "location": "Square",
+            "cord_x": 2.35,
+            "cord_y": 48.85,
+            "repeat_schedule": "FREQ=DAILY",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, collection) =
+        send(&app, Method::GET, "/api/vendors?format=geojson", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(collection["type"], "FeatureCollection");
+    assert_eq!(collection["total"], 3);
+    let features = collection["features"].as_array().expect("the features");
+    let feature = |id: &str| {
+        features
+            .iter()
+            .find(|feature| feature["id"] == id)
+            .expect("every vendor is a feature")
+    };
+    let busy = feature(&vendor_id);
+    assert_eq!(
+        busy["geometry"],
+        json!({ "type": "Point", "coordinates": [13.4, 52.5] })
+    );
+    assert_eq!(busy["properties"]["name"], "Pizza Van");
+    let next = &busy["properties"]["next_occurrence"];
+    assert_eq!(next["event"]["uuid"], event_id.as_str());
+    assert_eq!(next["location"], "Park");
+    assert_eq!(
+        feature(&other_id)["geometry"],
+        json!({ "type": "Point", "coordinates": [2.35, 48.85] })
+    );
+    let idle = feature(&idle_id);
+    assert!(idle["geometry"].is_null());
+    assert!(idle["properties"]["next_occurrence"].is_null());
+
+    let (status, single) = send(
+        &app,
+        Method::GET,
+        &format!("/api/vendors/{vendor_id}?format=geojson"),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(single["type"], "Feature");
+    assert_eq!(single["geometry"], busy["geometry"]);
+
+    let (status, event) = send(
+        &app,
+        Method::GET,
+        &format!("/api/events/{event_id}?format=geojson"),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(event["properties"]["vendor"]["uuid"], vendor_id.as_str());
+    assert_eq!(
+        event["properties"]["next_occurrence"]["start"],
+        next["start"]
+    );
+}
+
+#[tokio::test]
+async fn nearby_events_are_sorted_by_distance() {
+    let app = app().await;
+    let vendor_id = create_vendor(&app, "Pizza Van").await;
+    let vendor_token = token(&vendor_id, vec![Role::Vendor]);
+
+    // Created farthest first so the order can't come from the store
+    for (name, cord_y) in [("Far", 42.40), ("Close", 42.34), ("Here", 42.33)] {
+        let (status, _) = send(
+            &app,
+            Method::POST,
+            &format!("/api/vendors/{vendor_id}/events"),
+            Some(&vendor_token),
+            Some(json!({
+                "name": name,
+                "datetime": "2024-05-03T10:00:00Z",
+                "location": "Main Street",
+                "cord_x": -83.05,
+                "cord_y": cord_y,
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let window = "from=2024-05-03T00:00:00Z&to=2024-05-04T00:00:00Z";
+    let (status, nearby) = send(
+        &app,
+        Method::GET,
+        &format!("/api/events/nearby?lat=42.33&lon=-83.05&radius_m=5000&{window}"),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let nearby = nearby.as_array().expect("the nearby occurrences");
+    let names: Vec<&str> = nearby
+        .iter()
+        .filter_map(|nearby| nearby["event"]["name"].as_str())
+        .collect();
+    assert_eq!(names, vec!["Here", "Close"]);
+    assert_eq!(nearby[0]["distance_m"], 0.0);
+    let close = nearby[1]["distance_m"].as_f64().expect("the distance");
+    assert!(close > 1000.0 && close < 1200.0, "{close}");
+
+    let (status, collection) = send(
+        &app,
+        Method::GET,
+        &format!("/api/events/nearby?lat=42.33&lon=-83.05&format=geojson&{window}"),
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let features = collection["features"].as_array().expect("the features");
+    assert_eq!(features.len(), 2);
+    for feature in features {
+        assert_eq!(feature["properties"]["vendor"]["uuid"], vendor_id.as_str());
+        assert_eq!(feature["properties"]["vendor"]["name"], "Pizza Van");
+    }
+
+    // Query parameters are rejected the same way as the rest of the API's, naming the field
+    for (query, field) in [
+        ("lon=-83.05", "lat"),
+        ("lat=91&lon=-83.05", "lat"),
+        ("lat=north&lon=-83.05", "lat"),
+        ("lat=42.33", "lon"),
+        ("lat=42.33&lon=-181", "lon"),
+        ("lat=42.33&lon=-83.05&radius_m=-1", "radius_m"),
+        ("lat=42.33&lon=-83.05&radius_m=far", "radius_m"),
+        ("lat=42.33&lon=-83.05&radius_m=1000000", "radius_m"),
+    ] {
+        let (status, body) = send(
+            &app,
+            Method::GET,
+            &format!("/api/events/nearby?{query}"),
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{query}: {body}");
+        assert_eq!(body["error"]["fields"][0]["field"], field, "{query}");
+    }
+}
+
+#[tokio::test]
+async fn search_finds_vendors_through_what_they_sell() {
+    let app = app().await;
+    let vendor_id = create_vendor(&app, "Blue Van").await;
+    create_vendor(&app, "Red Van").await;
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        &format!("/api/vendors/{vendor_id}/items"),
+        Some(&token(&vendor_id, vec![Role::Vendor])),
+        Some(json!({ "name": "Burrito", "price": 9 })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, results) = send(&app, Method::GET, "/api/search?q=burrtio", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(results["items"][0]["name"], "Burrito");
+    let vendors = results["vendors"].as_array().expect("the vendors");
+    assert_eq!(vendors.len(), 1);
+    assert_eq!(vendors[0]["uuid"], vendor_id.as_str());
+    assert_eq!(vendors[0]["via"], json!(["Burrito"]));
+}
+
+#[tokio::test]
+async fn calendar_feeds_name_the_vendors_and_menus_of_their_events() {
+    let app = app().await;
+    let mut vendor_ids = Vec::new();
+    for (name, cord_y) in [("Pizza Van", 42.33), ("Burger Bus", 40.71)] {
+        let vendor_id = create_vendor(&app, name).await;
+        let vendor_token = token(&vendor_id, vec![Role::Vendor]);
+        let (status, _) = send(
+            &app,
+            Method::POST,
+            &format!("/api/vendors/{vendor_id}/menus"),
+            Some(&vendor_token),
+            Some(json!({ "name": format!("{name} Lunch") })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, menus) = send(
+            &app,
+            Method::GET,
+            &format!("/api/vendors/{vendor_id}/menus"),
+            None,
+            None,
+        )
+        .await;
+        // Unnamed events are summarized by their vendor's name
+        let (status, _) = send(
+            &app,
+            Method::POST,
+            &format!("/api/vendors/{vendor_id}/events"),
+            Some(&vendor_token),
+            Some(json!({
+                "datetime": "2024-05-03T10:00:00Z",
+                "location": "Main Street",
+                "cord_x": -83.05,
+                "cord_y": cord_y,
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, events) = send(
+            &app,
+            Method::GET,
+            &format!("/api/vendors/{vendor_id}/events"),
+            None,
+            None,
+        )
+        .await;
+        let (status, _) = send(
+            &app,
+            Method::PATCH,
+            &format!("/api/events/{}", only_uuid(&events)),
+            Some(&vendor_token),
+            Some(json!({ "menu": only_uuid(&menus) })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        vendor_ids.push(vendor_id);
+    }
+
+    let feed = |uri: String| {
+        let app = app.clone();
+        async move {
+            let (status, feed) = send(&app, Method::GET, &uri, None, None).await;
+            assert_eq!(status, StatusCode::OK);
+            feed.as_str().expect("a calendar").to_string()
+        }
+    };
+    let city = feed("/api/events/calendar.ics".to_string()).await;
+    for name in ["Pizza Van", "Burger Bus"] {
+        assert!(city.contains(&format!("SUMMARY:{name}\r\n")), "{city}");
+        assert!(city.contains(&format!("Menu: {name} Lunch")), "{city}");
+    }
+    let nearby = feed("/api/events/calendar.ics?near=42.33,-83.05".to_string()).await;
+    assert!(nearby.contains("SUMMARY:Pizza Van\r\n"), "{nearby}");
+    assert!(nearby.contains("Menu: Pizza Van Lunch"), "{nearby}");
+    assert!(!nearby.contains("Burger Bus"), "{nearby}");
+    let vendor = feed(format!("/api/vendors/{}/calendar.ics", vendor_ids[1])).await;
+    assert!(vendor.contains("SUMMARY:Burger Bus\r\n"), "{vendor}");
+    assert!(vendor.contains("Menu: Burger Bus Lunch"), "{vendor}");
+    assert!(!vendor.contains("Pizza Van"), "{vendor}");
+
+    let (status, _) = send(
+        &app,
+        Method::GET,
+        "/api/vendors/missing/calendar.ics",
+        None,
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn probes_report_the_store() {
+    let app = app().await;
+    let (status, body) = send(&app, Method::GET, "/healthz", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "status": "ok" }));
+    let (status, body) = send(&app, Method::GET, "/readyz", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ready");
+    assert_eq!(body["checks"]["database"], "ok");
+
+    // The process is still alive while the store is down, it just can't take traffic
+    let app = app_with(Arc::new(DownStore)).await;
+    let (status, _) = send(&app, Method::GET, "/healthz", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    for uri in ["/readyz", "/api/vendors"] {
+        let request = Request::builder()
+            .uri(uri)
+            .body(Body::empty())
+            .expect("the request should build");
+        let response = app.clone().oneshot(request).await.expect("infallible");
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE, "{uri}");
+        assert_eq!(response.headers()[header::RETRY_AFTER], "5", "{uri}");
+    }
+    let (_, body) = send(&app, Method::GET, "/readyz", None, None).await;
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["checks"]["database"], "The database is not connected");
+}
+
+#[tokio::test]
+async fn metrics_count_requests_by_route() {
+    let app = app().await;
+    let (status, _) = send(&app, Method::GET, "/version", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = send(&app, Method::GET, "/metrics", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let body = body.as_str().expect("metrics should be text");
+
+    // The recorder is shared by every test, so other requests may have been counted too
+    let requests = body
+        .lines()
+        .find(|line| {
+            line.starts_with("http_requests_total{")
+                && line.contains(r#"method="GET""#)
+                && line.contains(r#"route="/version""#)
+                && line.contains(r#"status="200""#)
+        })
+        .expect("the request should be counted");
+    let count: f64 = requests
+        .rsplit(' ')
+        .next()
+        .and_then(|count| count.parse().ok())
+        .expect("the count should be a number");
+    assert!(count >= 1.0, "{requests}");
+    assert!(body.lines().any(
+        |line| line.starts_with("http_request_duration_seconds_count{")
+            && line.contains(r#"route="/version""#)
+    ));
+}
+
+// Collects what the subscriber writes so the test can read it back
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn credentials_are_never_traced() {
+    let logs = Logs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::TRACE)
+        .with_ansi(false)
+        .with_writer(move || writer.clone())
+        .finish();
+    // Only this test's thread logs to it, the runtime is single threaded
+    let _default = tracing::subscriber::set_default(subscriber);
+
+    let app = app().await;
+    let admin = admin();
+    let (status, _) = send(&app, Method::GET, "/api/vendors", Some(&admin), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(
+        &app,
+        Method::DELETE,
+        "/api/vendors/nobody",
+        Some("not-a-real-token"),
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let request = Request::builder()
+        .uri("/api/vendors")
+        .header(header::COOKIE, "session=cookie-secret")
+        .body(Body::empty())
+        .expect("the request should build");
+    app.clone().oneshot(request).await.expect("infallible");
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(logs.contains("finished request"), "{logs}");
+    assert!(logs.contains("/api/vendors"), "{logs}");
+    // The headers are logged, with the credentials hidden
+    assert!(logs.contains(r#""authorization": Sensitive"#), "{logs}");
+    assert!(logs.contains(r#""cookie": Sensitive"#), "{logs}");
+    for secret in [
+        admin.as_str(),
+        "not-a-real-token",
+        "cookie-secret",
+        "Bearer",
+    ] {
+        assert!(!logs.contains(secret), "{secret} is in {logs}");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_overrides_of_an_event_are_all_kept() {
+    let app = app().await;
+    let vendor_id = create_vendor(&app, "Pizza Van").await;
+    let vendor_token = token(&vendor_id, vec![Role::Vendor]);
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        &format!("/api/vendors/{vendor_id}/events"),
+        Some(&vendor_token),
+        Some(json!({
+            "name": "Market",
+            "datetime": "2024-05-03T10:00:00Z",
+            "location": "Main Street",
+            "cord_x": -83.05,
+            "cord_y": 42.33,
+            "repeat_schedule": "FREQ=WEEKLY;BYDAY=FR",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let events = format!("/api/vendors/{vendor_id}/events");
+    let (_, page) = send(&app, Method::GET, &events, None, None).await;
+    let event_id = only_uuid(&page);
+
+    // Every Friday of the next 20 weeks is changed at once, half noted and half cancelled
+    let fridays: Vec<chrono::NaiveDate> = (0..20)
+        .map(|week| {
+            chrono::NaiveDate::from_ymd_opt(2024, 5, 3).unwrap() + chrono::Days::new(week * 7)
+        })
+        .collect();
+    let tasks: Vec<_> = fridays
+        .iter()
+        .enumerate()
+        .map(|(week, date)| {
+            let app = app.clone();
+            let token = vendor_token.clone();
+            let uri = format!("/api/events/{event_id}/occurrences/{date}");
+            tokio::spawn(async move {
+                match week % 2 {
+                    0 => {
+                        send(
+                            &app,
+                            Method::PATCH,
+                            &uri,
+                            Some(&token),
+                            Some(json!({ "note": "Late" })),
+                        )
+                        .await
+                    }
+                    _ => send(&app, Method::DELETE, &uri, Some(&token), None).await,
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        let (status, body) = task.await.expect("the request shouldn't panic");
+        assert_eq!(status, StatusCode::OK, "{body}");
+    }
+
+    let (_, page) = send(&app, Method::GET, &events, None, None).await;
+    let overrides = page["data"][0]["overrides"]
+        .as_array()
+        .expect("the event should have overrides");
+    let dates: Vec<String> = overrides
+        .iter()
+        .map(|over| over["date"].as_str().unwrap_or_default().to_string())
+        .collect();
+    let expected: Vec<String> = fridays.iter().map(ToString::to_string).collect();
+    assert_eq!(dates, expected);
+    assert_eq!(
+        overrides
+            .iter()
+            .filter(|over| over["cancelled"] == true)
+            .count(),
+        10
+    );
+}